@@ -1,3 +1,36 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// Build the C++ shim implementing the throwing operator new and link it in the
+/// shared library only, the weak Rust definitions are used for the other targets.
+fn build_cxx_shim() {
+	let out_dir = env::var("OUT_DIR").unwrap();
+	let obj = Path::new(&out_dir).join("export_cxx.o");
+	let cxx = env::var("CXX").unwrap_or(String::from("c++"));
+
+	//compile
+	let status = Command::new(&cxx)
+		.args(&["-std=c++17", "-O2", "-fPIC", "-Wall", "-c", "src/export_cxx.cpp", "-o"])
+		.arg(&obj)
+		.status()
+		.expect("Fail to run the C++ compiler, set CXX to use another one");
+	assert!(status.success(), "Fail to build src/export_cxx.cpp");
+
+	//link
+	println!("cargo:rustc-cdylib-link-arg={}", obj.display());
+	println!("cargo:rerun-if-changed=src/export_cxx.cpp");
+	println!("cargo:rerun-if-env-changed=CXX");
+}
+
 fn main() {
 	println!("cargo:rustc-link-lib=dylib=numa");
+
+	//unwinder used by the landing pads, as done by libstd
+	println!("cargo:rustc-link-lib=dylib=gcc_s");
+
+	//as for the export_cxx module
+	if env::var_os("CARGO_FEATURE_C_EXPORT").is_some() && env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap() == "64" {
+		build_cxx_shim();
+	}
 }
@@ -0,0 +1,328 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+///Provide private arenas to be used with the unstable Rust allocator API. An arena
+///owns its own registry, memory source and local allocator so all the memory it
+///handed out is returned to the OS at once when it is dropped :
+///
+///```ignore
+///let arena = hpc_allocator_rust::arena::HpcArena::new();
+///let mut v = Vec::new_in(&arena);
+///v.push(42);
+///```
+
+//import
+use core::alloc::{Allocator,AllocError,Layout};
+use core::ptr::{self,NonNull};
+use core::cmp;
+use core::mem;
+use common::shared::SharedPtrBox;
+use common::types::{Addr,Size};
+use common::consts::*;
+use common::ops;
+use common::oom;
+use common::traits::{ChunkManager,ChunkManagerPtr};
+use registry::registry::RegionRegistry;
+use mmsource::cached::CachedMMSource;
+use posix::local::LocalAllocator;
+use portability::osmem;
+use portability::libc;
+
+/// Private heap implementing the Rust Allocator trait. The chunks must not be
+/// freed by another allocator as they are registered only in the arena registry.
+pub struct HpcArena {
+	registry: SharedPtrBox<RegionRegistry>,
+	mm_source: SharedPtrBox<CachedMMSource>,
+	allocator: SharedPtrBox<LocalAllocator>,
+	/// Size of the segment storing the three objects.
+	size: Size,
+}
+
+impl HpcArena {
+	/// Build a new empty arena. The objects are placed in a dedicated
+	/// mmap segment so their addresses does not change if the arena is moved.
+	/// Abort if the OS cannot provide the memory.
+	pub fn new() -> Self {
+		match Self::try_new() {
+			Some(arena) => arena,
+			None => oom::fatal("an arena"),
+		}
+	}
+
+	/// Same than new() but return None if the OS cannot provide the memory.
+	pub fn try_new() -> Option<Self> {
+		// allocate
+		let ptr = osmem::mmap(0, Self::get_header_size());
+		if ptr == NULL {
+			return None;
+		}
+		let mut arena = Self::from_addr(ptr);
+
+		// spawn
+		*arena.registry.get_mut() = RegionRegistry::new();
+		*arena.mm_source.get_mut() = CachedMMSource::new_default(Some(arena.registry.clone()));
+		*arena.allocator.get_mut() = LocalAllocator::new(true, Some(arena.registry.clone()), Some(SharedPtrBox::new_ref_mut(arena.mm_source.clone().get_mut())));
+		arena.allocator.clone().post_init(ChunkManagerPtr::new_ref_mut(&mut *arena.allocator.clone().get_mut()));
+
+		Some(arena)
+	}
+
+	/// Size of the segment storing the registry, memory source and allocator.
+	fn get_header_size() -> Size {
+		let registry_size = mem::size_of::<RegionRegistry>();
+		let mm_source_size = mem::size_of::<CachedMMSource>();
+		let allocator_size = mem::size_of::<LocalAllocator>();
+		ops::up_to_power_of_2(registry_size + mm_source_size + allocator_size, SMALL_PAGE_SIZE)
+	}
+
+	/// Point the objects placed in the given header segment.
+	fn from_addr(ptr: Addr) -> Self {
+		let registry_size = mem::size_of::<RegionRegistry>();
+		let mm_source_size = mem::size_of::<CachedMMSource>();
+		Self {
+			registry: SharedPtrBox::new_addr(ptr),
+			mm_source: SharedPtrBox::new_addr(ptr + registry_size),
+			allocator: SharedPtrBox::new_addr(ptr + registry_size + mm_source_size),
+			size: Self::get_header_size(),
+		}
+	}
+
+	/// Release the ownership of the arena and return an opaque handle on it
+	/// to be used from C. It can be rebuilt latter with from_raw().
+	pub fn into_raw(self) -> Addr {
+		let handle = self.registry.get_addr();
+		mem::forget(self);
+		handle
+	}
+
+	/// Take back the ownership of an arena from the handle returned by into_raw().
+	///
+	/// @param handle The handle returned by into_raw().
+	pub unsafe fn from_raw(handle: Addr) -> Self {
+		debug_assert!(handle != NULL);
+		Self::from_addr(handle)
+	}
+
+	/// Allocate a chunk with basic alignement, return NULL on failure.
+	pub fn malloc(&self, size: Size) -> Addr {
+		self.allocator.clone().malloc(size, BASIC_ALIGN, false)
+	}
+
+	/// Free a chunk allocated in the arena.
+	pub fn free(&self, ptr: Addr) {
+		self.allocator.clone().free(ptr);
+	}
+
+	/// Resize a chunk allocated in the arena with the same semantic than libc realloc().
+	pub fn realloc(&self, ptr: Addr, size: Size) -> Addr {
+		self.allocator.clone().realloc(ptr, size)
+	}
+
+	/// Allocate a chunk in the arena.
+	///
+	/// @param layout Size and alignement to use.
+	/// @param zero Tell if the chunk need to be zeroed.
+	fn alloc(&self, layout: Layout, zero: bool) -> Result<NonNull<[u8]>, AllocError> {
+		let mut allocator = self.allocator.clone();
+		let align = cmp::max(layout.align(), BASIC_ALIGN) as Size;
+		let ptr = allocator.malloc(layout.size() as Size, align, zero);
+		if ptr == NULL {
+			return Err(AllocError);
+		}
+		self.as_slice(ptr, layout.size())
+	}
+
+	/// Resize a chunk, the content is kept up to the smallest of the two sizes.
+	///
+	/// @param ptr The chunk to resize.
+	/// @param old_layout Layout used to allocate the chunk.
+	/// @param new_layout Expected layout.
+	unsafe fn resize(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		let mut allocator = self.allocator.clone();
+
+		//basic alignement is guaranteed by realloc
+		let res;
+		if new_layout.align() <= BASIC_ALIGN {
+			res = allocator.realloc(ptr.as_ptr() as Addr, cmp::max(new_layout.size(), 1) as Size);
+		} else {
+			//otherwise move by hand to keep alignement
+			res = allocator.malloc(new_layout.size() as Size, new_layout.align() as Size, false);
+			if res != NULL {
+				libc::memcpy(res, ptr.as_ptr() as Addr, cmp::min(old_layout.size(), new_layout.size()) as Size);
+				allocator.free(ptr.as_ptr() as Addr);
+			}
+		}
+
+		if res == NULL {
+			Err(AllocError)
+		} else {
+			self.as_slice(res, new_layout.size())
+		}
+	}
+
+	/// Build the slice returned to the user.
+	fn as_slice(&self, ptr: Addr, size: usize) -> Result<NonNull<[u8]>, AllocError> {
+		NonNull::new(ptr::slice_from_raw_parts_mut(ptr as * mut u8, size)).ok_or(AllocError)
+	}
+
+	/// Return the inner size of a chunk allocated in the arena.
+	pub fn get_inner_size(&self, ptr: * const u8) -> Size {
+		self.allocator.get_inner_size(ptr as Addr)
+	}
+}
+
+unsafe impl Allocator for HpcArena {
+	fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		self.alloc(layout, false)
+	}
+
+	fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		self.alloc(layout, true)
+	}
+
+	unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+		self.allocator.clone().free_sized(ptr.as_ptr() as Addr, cmp::max(layout.size(), layout.align()) as Size);
+	}
+
+	unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		debug_assert!(new_layout.size() >= old_layout.size());
+		self.resize(ptr, old_layout, new_layout)
+	}
+
+	unsafe fn grow_zeroed(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		debug_assert!(new_layout.size() >= old_layout.size());
+		let res = self.resize(ptr, old_layout, new_layout)?;
+		let addr = res.as_ptr() as * mut u8 as Addr;
+		libc::memset(addr + old_layout.size(), 0, (new_layout.size() - old_layout.size()) as Size);
+		Ok(res)
+	}
+
+	unsafe fn shrink(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		debug_assert!(new_layout.size() <= old_layout.size());
+		self.resize(ptr, old_layout, new_layout)
+	}
+}
+
+impl Drop for HpcArena {
+	/// Return all the memory of the arena to the OS without walking the chunks.
+	fn drop(&mut self) {
+		self.mm_source.get_mut().free_all();
+		self.registry.get_mut().unmap_all_memory();
+		osmem::munmap(self.registry.get_addr(), self.size);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	extern crate std;
+	use arena::*;
+	use self::std::vec::Vec;
+	use self::std::boxed::Box;
+
+	#[test]
+	fn allocate_deallocate() {
+		let arena = HpcArena::new();
+		for size in [8, 1024, 64*1024, 4*1024*1024].iter() {
+			let layout = Layout::from_size_align(*size, 8).unwrap();
+			let ptr = arena.allocate(layout).unwrap();
+			assert_eq!(ptr.len(), *size);
+			assert!(arena.get_inner_size(ptr.as_ptr() as * const u8) >= *size);
+			unsafe {
+				*(ptr.as_ptr() as * mut u8) = 1;
+				arena.deallocate(NonNull::new_unchecked(ptr.as_ptr() as * mut u8), layout);
+			}
+		}
+	}
+
+	#[test]
+	fn allocate_aligned() {
+		let arena = HpcArena::new();
+		let layout = Layout::from_size_align(64*1024, 4096).unwrap();
+		let ptr = arena.allocate(layout).unwrap();
+		assert_eq!(ptr.as_ptr() as * mut u8 as usize % 4096, 0);
+		unsafe {arena.deallocate(NonNull::new_unchecked(ptr.as_ptr() as * mut u8), layout)};
+	}
+
+	#[test]
+	fn allocate_zeroed() {
+		let arena = HpcArena::new();
+		for size in [64, 64*1024, 4*1024*1024].iter() {
+			let layout = Layout::from_size_align(*size, 8).unwrap();
+			for _ in 0..2 {
+				let ptr = arena.allocate_zeroed(layout).unwrap().as_ptr() as * mut u8;
+				unsafe {
+					for i in 0..*size {
+						assert_eq!(*ptr.offset(i as isize), 0);
+					}
+					libc::memset(ptr as Addr, 0xFF, *size);
+					arena.deallocate(NonNull::new_unchecked(ptr), layout);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn grow_shrink() {
+		let arena = HpcArena::new();
+		for align in [8, 512].iter() {
+			let small = Layout::from_size_align(1024, *align).unwrap();
+			let large = Layout::from_size_align(128*1024, *align).unwrap();
+			unsafe {
+				let ptr = arena.allocate(small).unwrap().as_ptr() as * mut u8;
+				*ptr.offset(1023) = 42;
+				let ptr = arena.grow_zeroed(NonNull::new_unchecked(ptr), small, large).unwrap().as_ptr() as * mut u8;
+				assert_eq!(ptr as usize % *align, 0);
+				assert_eq!(*ptr.offset(1023), 42);
+				assert_eq!(*ptr.offset(128*1024-1), 0);
+				let ptr = arena.shrink(NonNull::new_unchecked(ptr), large, small).unwrap().as_ptr() as * mut u8;
+				assert_eq!(*ptr.offset(1023), 42);
+				arena.deallocate(NonNull::new_unchecked(ptr), small);
+			}
+		}
+	}
+
+	#[test]
+	fn collections() {
+		let arena = HpcArena::new();
+		let mut v = Vec::new_in(&arena);
+		for i in 0..100000 {
+			v.push(i);
+		}
+		assert_eq!(v[99999], 99999);
+		let b = Box::new_in(42, &arena);
+		assert_eq!(*b, 42);
+	}
+
+	#[test]
+	fn raw_handle() {
+		let arena = HpcArena::new();
+		let ptr = arena.malloc(64);
+		let handle = arena.into_raw();
+
+		let arena = unsafe{HpcArena::from_raw(handle)};
+		unsafe{*(ptr as * mut u8) = 1};
+		let ptr = arena.realloc(ptr, 256*1024);
+		assert_ne!(ptr, NULL);
+		assert_eq!(unsafe{*(ptr as * mut u8)}, 1);
+		arena.free(ptr);
+	}
+
+	#[test]
+	fn drop_wholesale() {
+		//never free the chunks, drop release everything
+		for _ in 0..10 {
+			let arena = HpcArena::new();
+			let mut v = Vec::new_in(&arena);
+			for size in [16, 4096, 256*1024, 8*1024*1024].iter() {
+				v.push(arena.allocate(Layout::from_size_align(*size, 8).unwrap()).unwrap());
+			}
+			mem::forget(v);
+		}
+	}
+}
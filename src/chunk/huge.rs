@@ -13,8 +13,12 @@
 use common::traits::{ChunkManager,ChunkManagerPtr,MemorySourcePtr,MemorySource};
 use common::types::{Addr,Size,SSize};
 use common::consts::*;
+use common::config;
+use common::stats::DetailedReport;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use registry::segment::RegionSegment;
 use chunk::padding::PaddedChunk;
+use portability::libc;
 use common::shared::SharedPtrBox;
 
 //decl
@@ -22,6 +26,12 @@ pub struct HugeChunkManager {
 	/// Keep track of the parent chunk manager
 	parent: Option<ChunkManagerPtr>,
 	mmsource: MemorySourcePtr,
+	/// Minimal size to generate a realloc, from runtime config.
+	realloc_threashold: SSize,
+	/// Number of segments currently allocated, for statistics.
+	segments: AtomicUsize,
+	/// Total size of the segments currently allocated, for statistics.
+	bytes: AtomicUsize,
 }
 
 //impl
@@ -30,6 +40,9 @@ impl HugeChunkManager {
 		HugeChunkManager {
 			parent:None,
 			mmsource: mmsource,
+			realloc_threashold: config::get().realloc_threashold,
+			segments: AtomicUsize::new(0),
+			bytes: AtomicUsize::new(0),
 		}
 	}
 
@@ -37,6 +50,12 @@ impl HugeChunkManager {
 		self.mmsource = mmsource;
 	}
 
+	/// Add the segments currently allocated to the report.
+	pub fn collect_details(&self, details: &mut DetailedReport) {
+		details.huge_segments += self.segments.load(Ordering::Relaxed) as u64;
+		details.huge_bytes += self.bytes.load(Ordering::Relaxed) as u64;
+	}
+
 	fn get_mm_source(&mut self) -> &mut dyn MemorySource {
 		self.mmsource.get_mut()
 	}
@@ -47,7 +66,8 @@ impl HugeChunkManager {
 
 		//errors
 		//allocAssert(this != NULL);
-		debug_assert!(align >= BASIC_ALIGN);
+		//we always guarantee the basic alignement
+		let align = if align < BASIC_ALIGN {BASIC_ALIGN} else {align};
 		
 		//trivial
 		if checked_size == 0 {
@@ -66,14 +86,20 @@ impl HugeChunkManager {
 		let (segment,z) = self.get_mm_source().map(checked_size,zero,Some(manager));
 		//allocCondWarning(segment != NULL,"Caution, get OOM in huge allocation method.");
 		
+		//out of memory
+		if segment.is_null() {
+			return (0,false);
+		}
+
 		//setup zero
 		zero = z;
 
 		//ok this is good get ptr
 		let mut res = segment.get_content_addr();
-		if res == 0 {
-			return (0,zero);
-		}
+
+		//account
+		self.segments.fetch_add(1, Ordering::Relaxed);
+		self.bytes.fetch_add(segment.get_total_size(), Ordering::Relaxed);
 		
 		//check for padding
 		if res % align != 0 {
@@ -102,6 +128,10 @@ impl ChunkManager for HugeChunkManager {
 		//TODO make a safe version of this function with checking (if possible)
 		let segment = RegionSegment::get_from_content_ptr(addr);
 
+		//account
+		self.segments.fetch_sub(1, Ordering::Relaxed);
+		self.bytes.fetch_sub(segment.get_total_size(), Ordering::Relaxed);
+
 		//return it to mm source
 		self.get_mm_source().unmap(segment);
 	}
@@ -121,24 +151,44 @@ impl ChunkManager for HugeChunkManager {
 		
 		//check if padded
 		let ptr = PaddedChunk::unpad(ptr);
+		let padding = old_ptr - ptr;
 		
 		//get old size
 		let segment = RegionSegment::get_from_content_ptr(ptr);
 		//allocAssert(segment != NULL);
 		//TODO assume
-		let old_size = segment.get_inner_size();
+		let old_size = segment.get_inner_size() - padding;
 		let delta = old_size as SSize - size as SSize;
 		
 		//if can resuse old one without resize
-		if old_size >= size && delta <= REALLOC_THREASHOLD {
+		if old_size >= size && delta <= self.realloc_threashold {
 			return old_ptr;
 		}
+
+		//remap would shift the content of padded chunks so move it by hand
+		if padding != 0 {
+			let new_ptr = self.malloc(size,BASIC_ALIGN,false).0;
+			if new_ptr != NULL {
+				libc::memcpy(new_ptr,old_ptr,size.min(old_size));
+				self.free(old_ptr);
+			}
+			return new_ptr;
+		}
 		
 		//remap
+		let old_total_size = segment.get_total_size();
 		let manager: ChunkManagerPtr = SharedPtrBox::new_ref_mut(self);
 		let new_segment = self.get_mm_source().remap(segment,size,Some(manager));
-		//allocCondWarning(newSegment != NULL,"Get OOM in realloc of huge segment.");
+
+		//out of memory, the old segment is still valid
+		if new_segment.is_null() {
+			return NULL;
+		}
 		debug_assert!(new_segment.get_inner_size() >= size);
+
+		//account
+		self.bytes.fetch_sub(old_total_size, Ordering::Relaxed);
+		self.bytes.fetch_add(new_segment.get_total_size(), Ordering::Relaxed);
 		
 		return new_segment.get_content_addr();
 	}
@@ -215,11 +265,11 @@ mod tests
 		let (ptr,zero) = huge.malloc(4096, BASIC_ALIGN, false);
 		assert_eq!(zero, true);
 		assert!(ptr != 0);
-		assert_eq!(huge.get_inner_size(ptr),2*1024*1024-32);
+		assert_eq!(huge.get_inner_size(ptr),2*1024*1024-48);
 		assert_eq!(huge.get_total_size(ptr),2*1024*1024);
 
 		let ptr = huge.realloc(ptr, 4*1024*1024);
-		assert_eq!(huge.get_inner_size(ptr),4*1024*1024+4096-32);
+		assert_eq!(huge.get_inner_size(ptr),4*1024*1024+4096-48);
 		assert_eq!(huge.get_total_size(ptr),4*1024*1024+4096);
 
 		huge.free(ptr);
@@ -242,7 +292,7 @@ mod tests
 		let (ptr,zero) = huge.malloc(8, BASIC_ALIGN, false);
 		assert_eq!(zero, true);
 		assert!(ptr != 0);
-		assert_eq!(huge.get_inner_size(ptr),2*1024*1024-32);
+		assert_eq!(huge.get_inner_size(ptr),2*1024*1024-48);
 		assert_eq!(huge.get_total_size(ptr),2*1024*1024);
 
 		huge.free(ptr);
@@ -301,6 +351,46 @@ mod tests
 		mmsource.free_all();
 	}
 
+	#[test]
+	fn realloc_padded() {
+		let mut registry = RegionRegistry::new();
+		let mut mmsource = CachedMMSource::new_default(Some(SharedPtrBox::new_ref_mut(&mut registry)));
+		let mut huge = HugeChunkManager::new(SharedPtrBox::new_ref_mut(&mut mmsource));
+
+		let (ptr,_zero) = huge.malloc(2*1024*1024, SMALL_PAGE_SIZE, false);
+		assert_eq!(ptr % SMALL_PAGE_SIZE, 0);
+		unsafe{*((ptr + 2*1024*1024 - 1) as * mut u8) = 42};
+
+		let ptr = huge.realloc(ptr, 8*1024*1024);
+		assert!(huge.get_inner_size(ptr) >= 8*1024*1024);
+		assert_eq!(unsafe{*((ptr + 2*1024*1024 - 1) as * mut u8)}, 42);
+		huge.free(ptr);
+
+		mmsource.free_all();
+	}
+
+	#[test]
+	fn out_of_memory() {
+		let mut registry = RegionRegistry::new();
+		let mut mmsource = CachedMMSource::new_default(Some(SharedPtrBox::new_ref_mut(&mut registry)));
+		let mut huge = HugeChunkManager::new(SharedPtrBox::new_ref_mut(&mut mmsource));
+
+		//malloc
+		let (ptr,zero) = huge.malloc(1 << 60, BASIC_ALIGN, true);
+		assert_eq!(ptr, NULL);
+		assert_eq!(zero, false);
+
+		//realloc keep the old chunk
+		let (ptr,_zero) = huge.malloc(4*1024*1024, BASIC_ALIGN, false);
+		unsafe{*(ptr as * mut u8) = 42};
+		assert_eq!(huge.realloc(ptr, 1 << 60), NULL);
+		assert_eq!(unsafe{*(ptr as * const u8)}, 42);
+		assert!(huge.get_inner_size(ptr) >= 4*1024*1024);
+		huge.free(ptr);
+
+		mmsource.free_all();
+	}
+
 	#[test]
 	fn is_thread_safe() {
 		let mut registry = RegionRegistry::new();
@@ -14,16 +14,17 @@ use common::shared::SharedPtrBox;
 use common::types::{Addr,Size};
 use common::list::{ListNode,Listable};
 use common::ops;
+use portability::libc;
 use core::mem;
 
 /// To be used to annotate chunk as free
-pub const CHUNK_FREE:u32 = 0;
+pub const CHUNK_FREE:ChunkStatus = 0;
 
 /// To be used to annotate chunk as allocated
-pub const CHUNK_ALLOCATED:u32 = 1;
+pub const CHUNK_ALLOCATED:ChunkStatus = 1;
 
 /// To be used to store chunk status (FREE or ALLOCATED)
-type ChunkStatus = u32;
+type ChunkStatus = u16;
 pub type MediumChunkPtr = SharedPtrBox<MediumChunk>;
 
 /// Define a medium chunk by its header. Medium chunk are chained in memory
@@ -36,6 +37,8 @@ pub struct MediumChunk {
 	next: MediumChunkPtr,
 	/// Status of chunk (FREE or ALLOCATED)
 	status: ChunkStatus,
+	/// Tell if the content after the list node is known to be zero (untouched memory from the OS).
+	zeroed: bool,
 	/// Mafick number for checking/asserting.
 	magick: u32,
 }
@@ -51,7 +54,7 @@ impl MediumChunk {
 	fn setup(ptr: Addr) -> MediumChunkPtr {
 		//checks
 		debug_assert!(ptr != 0);
-		debug_assert!(ptr % mem::size_of::<Addr>() == 0);
+		debug_assert!(ptr % BASIC_ALIGN == 0);
 
 		//create
 		let mut res = MediumChunkPtr::new_addr(ptr);
@@ -65,7 +68,15 @@ impl MediumChunk {
 		
 		//infos
 		res.status = CHUNK_ALLOCATED;
+		res.zeroed = false;
 		res.magick = MAGICK_VALUE;
+
+		//clear the gap up to the content so unpad() cannot find the header
+		//of an old padded chunk in it
+		let gap = Self::header_size() - mem::size_of::<Self>();
+		if gap > 0 {
+			libc::memset(ptr + mem::size_of::<Self>(), 0, gap);
+		}
 		
 		//ret
 		res
@@ -142,9 +153,11 @@ impl MediumChunk {
 	}
 
 	/// Return the header size to avoid putting mem::size_of() everywhere.
+	/// It is rounded to BASIC_ALIGN so the content of the chunks keep the
+	/// alignement of their header.
 	#[inline]
 	pub fn header_size() -> Size {
-		mem::size_of::<MediumChunk>()
+		ops::up_to_power_of_2(mem::size_of::<MediumChunk>(), BASIC_ALIGN)
 	}
 
 	/// Return the base address (header address) of the current chunk.
@@ -255,8 +268,22 @@ impl MediumChunk {
 		self.status = status;
 	}
 
+	/// Return true if the content of the chunk is known to be zero except the
+	/// list node placed at its beginning when it is stored in the free lists.
+	#[inline]
+	pub fn is_zeroed(&self) -> bool {
+		self.zeroed
+	}
+
+	/// Mark the content of the chunk as known to be zero or not.
+	#[inline]
+	pub fn set_zeroed(&mut self,zeroed: bool) {
+		self.zeroed = zeroed;
+	}
+
 	/// Split the chunk at the given inner (contant) size.
 	/// If size if too big then return None.
+	/// The new chunk inherit the zero status as it take the untouched tail.
 	pub fn split(&mut self, inner_size: Size) -> Option<MediumChunkPtr> {
 		//round size to multiple of 8
 		let total_size = ops::up_to_power_of_2(inner_size,BASIC_ALIGN) + Self::header_size();
@@ -267,7 +294,8 @@ impl MediumChunk {
 		}
 		
 		//split
-		let chunk = Self::setup_prev_next(self.get_root_addr()+total_size,Some(MediumChunkPtr::new_ref(self)),self.next.clone());
+		let mut chunk = Self::setup_prev_next(self.get_root_addr()+total_size,Some(MediumChunkPtr::new_ref(self)),self.next.clone());
+		chunk.zeroed = self.zeroed;
 		
 		//update
 		if !self.next.is_null() {
@@ -305,7 +333,8 @@ impl MediumChunk {
 
 	/// Merge all next chunk until the given one.
 	/// It will merge on the current chunk by updating its
-	/// next entry.
+	/// next entry. The merged chunk is not considered as zeroed
+	/// anymore as it now contain the old headers.
 	pub fn merge(&mut self, last: MediumChunkPtr) {
 		//errors
 		self.check();
@@ -325,6 +354,7 @@ impl MediumChunk {
 		}
 		
 		first.next = last.next.clone();
+		first.zeroed = false;
 	}
 
 	/// Check if the chunk contain (in the inner content part) the given
@@ -368,14 +398,16 @@ impl Listable<MediumChunk> for MediumChunk {
 mod tests
 {
 	use chunk::medium::chunk::*;
+	use chunk::padding::PaddedChunk;
 	use portability::osmem;
 	use core::mem;
 
 	#[test]
 	fn struct_size() {
 		//size
-		assert_eq!(MediumChunk::header_size(), mem::size_of::<MediumChunk>());
-		assert_eq!(MediumChunk::header_size(), 3*mem::size_of::<Size>());
+		assert!(MediumChunk::header_size() >= mem::size_of::<MediumChunk>());
+		assert_eq!(MediumChunk::header_size() % BASIC_ALIGN, 0);
+		assert_eq!(mem::size_of::<MediumChunk>(), 3*mem::size_of::<Size>());
 	}
 
 	#[test]
@@ -396,6 +428,18 @@ mod tests
 		osmem::munmap(ptr, 4096);
 	}
 
+	#[test]
+	fn setup_clear_padding() {
+		let ptr = osmem::mmap(0,4096);
+		libc::memset(ptr, PADDED_CHUNK_MAGICK as i32, 4096);
+
+		let chunk = MediumChunk::setup_size(ptr, 4096);
+		let addr = chunk.get().get_content_addr();
+		assert_eq!(PaddedChunk::unpad(addr), addr);
+
+		osmem::munmap(ptr, 4096);
+	}
+
 	#[test]
 	fn get_root_addr() {
 		let ptr = osmem::mmap(0,4096);
@@ -540,6 +584,26 @@ mod tests
 		osmem::munmap(ptr, 4096);
 	}
 
+	#[test]
+	fn zeroed() {
+		let ptr = osmem::mmap(0,4096);
+
+		let mut chunk = MediumChunk::setup_size(ptr, 4096);
+		assert_eq!(chunk.is_zeroed(), false);
+		chunk.set_zeroed(true);
+
+		//split keep the status on the tail
+		let residut = chunk.split(512).unwrap();
+		assert_eq!(residut.is_zeroed(), true);
+		assert_eq!(chunk.is_zeroed(), true);
+
+		//merge reset it
+		chunk.merge(residut);
+		assert_eq!(chunk.is_zeroed(), false);
+
+		osmem::munmap(ptr, 4096);
+	}
+
 	#[test]
 	fn contain() {
 		let ptr = osmem::mmap(0,4096);
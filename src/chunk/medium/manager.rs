@@ -17,9 +17,12 @@ use common::traits::{ChunkManager,ChunkManagerPtr,MemorySourcePtr};
 use registry::registry::RegionRegistry;
 use common::types::{Addr,Size,SSize};
 use common::consts::*;
+use common::config;
+use common::stats::DetailedReport;
 use common::ops;
 use chunk::padding::PaddedChunk;
 use common::shared::SharedPtrBox;
+use common::list::ListNode;
 use core::mem;
 use registry::segment::RegionSegment;
 use portability::libc;
@@ -36,6 +39,8 @@ pub struct MediumChunkManager {
 	registry: Option<SharedPtrBox<RegionRegistry>>,
 	use_lock: bool,
 	parent: Option<ChunkManagerPtr>,
+	/// Minimal size to generate a realloc, from runtime config.
+	realloc_threashold: SSize,
 }
 
 //implement
@@ -54,16 +59,20 @@ impl MediumChunkManager {
 			registry: None,
 			use_lock: use_lock,
 			parent: None,
+			realloc_threashold: config::get().realloc_threashold,
 		}
 	}
 
 	/// Allocate a new segment.
+	/// @param zero_filled Ask for memory cleared to zero. The returned status is true
+	/// only if the chunk was taken in a never touched part of a macro bloc.
 	pub fn malloc(&mut self, size: Size, align:Size, zero_filled: bool) -> (Addr,bool) {
 		let mut zero = zero_filled;
 		let mut checked_size = size;
 
 		//errors
-		debug_assert!(align >= BASIC_ALIGN);
+		//we always guarantee the basic alignement
+		let align = if align < BASIC_ALIGN {BASIC_ALIGN} else {align};
 		
 		//trivial
 		if checked_size == 0 {
@@ -87,33 +96,44 @@ impl MediumChunkManager {
 		
 			//try to get memory
 			chunk = guard.pools.find_chunk( checked_size );
-			match chunk {
-				Some(_) => zero = false,
-				None => {
-					let (tchunk, tzero) = Self::refill(&mut *guard,checked_size,zero,SharedPtrBox::new_ptr_mut(self as * const dyn ChunkManager as * mut dyn ChunkManager));
-					chunk = tchunk;
-					zero = tzero;
-				},
+			if chunk.is_none() {
+				let (mut tchunk, tzero) = Self::refill(&mut *guard,checked_size,zero,SharedPtrBox::new_ptr_mut(self as * const dyn ChunkManager as * mut dyn ChunkManager));
+				match tchunk {
+					Some(ref mut x) => x.set_zeroed(tzero),
+					None => {},
+				}
+				chunk = tchunk;
 			}
 			
 			//error out of memory (unlocking is managed by TakeLock destructor)
-			match chunk.as_ref() {
+			match chunk.as_mut() {
 				Some(chunk) => {
-					//try to split
+					//try to split, the residut keep the zero status of the tail
 					let residut = Self::split(chunk.clone(),checked_size);
 					debug_assert!(chunk.get().get_inner_size() >= checked_size);
 					match residut {
 						Some(x) => guard.pools.insert_chunk(x,ChunkInsertMode::LIFO),
 						None => {},
 					}
+
+					//the user will write in it
+					zero = chunk.is_zeroed();
+					chunk.set_zeroed(false);
 				},
-				None => return (0,zero),
+				None => return (0,false),
 			}
 		}
 		
 		//ok this is good get ptr
 		let chunk = chunk.unwrap();
 		let mut res = chunk.get_content_addr();
+
+		//only the free list node was written in a known zero chunk
+		if zero && zero_filled {
+			libc::memset(res, 0, mem::size_of::<ListNode>());
+		} else {
+			zero = false;
+		}
 		
 		//check for padding
 		if align != BASIC_ALIGN {
@@ -132,6 +152,16 @@ impl MediumChunkManager {
 		return (res,zero);
 	}
 
+	/// Add the occupancy of the free lists to the report.
+	pub fn collect_details(&self, details: &mut DetailedReport) {
+		self.locked.optional_lock(self.use_lock).pools.collect_details(details);
+	}
+
+	/// Return the unused pages of the free chunks to the OS. Return the released size.
+	pub fn trim(&mut self) -> Size {
+		self.locked.optional_lock(self.use_lock).pools.trim()
+	}
+
 	pub fn rebind_mm_source(&mut self,mmsource: Option<MemorySourcePtr>) {
 		self.locked.lock().mmsource = mmsource;
 	}
@@ -149,6 +179,9 @@ impl MediumChunkManager {
 		
 		//request mem
 		let (segment, zero) = mmsource.map(size,zero_filled,Some(manager));
+		if segment.is_null() {
+			return (None, false);
+		}
 		debug_assert!(segment.get_inner_size() >= size);
 		
 		//get inner segment
@@ -175,7 +208,7 @@ impl MediumChunkManager {
 		let avail_size = chunk.get_inner_size();
 		
 		//check minimal size
-		if avail_size - inner_size <= MEDIUM_MIN_INNER_SIZE + 2*mem::size_of::<MediumChunk>() {
+		if avail_size - inner_size <= MEDIUM_MIN_INNER_SIZE + 2*MediumChunk::header_size() {
 			return None;
 		}
 		
@@ -272,6 +305,7 @@ impl ChunkManager for MediumChunkManager {
 		
 		//check if padded
 		let ptr = PaddedChunk::unpad(ptr);
+		let padding = old_ptr - ptr;
 		
 		//get old size
 		let chunk = MediumChunk::get_chunk_safe(ptr);
@@ -285,17 +319,18 @@ impl ChunkManager for MediumChunkManager {
 		}
 
 		//TODO assume
-		let old_size = schunk.get_inner_size();
+		let old_size = schunk.get_inner_size() - padding;
 		let delta = old_size as SSize - size as SSize;
 		
 		//if can resuse old one without resize
-		if old_size >= size && delta <= REALLOC_THREASHOLD {
+		if old_size >= size && delta <= self.realloc_threashold {
 			return old_ptr;
 		}
 		
-		//check if can realloc the next one
+		//check if can realloc the next one, padded chunks are moved as the
+		//content address change
 		//TODO maybe find a way to avoid to retake the lock for next malloc call
-		{
+		if padding == 0 {
 			let mut guard = self.locked.optional_lock(self.use_lock);
 
 			//try merge
@@ -331,11 +366,11 @@ impl ChunkManager for MediumChunkManager {
 		//ok do alloc/copy/free
 		let new_ptr = self.malloc(size,BASIC_ALIGN,false).0;
 		if new_ptr != NULL {
-			libc::memcpy(new_ptr,ptr,size.max(old_size));
-		}
+			libc::memcpy(new_ptr,old_ptr,size.min(old_size));
 
-		//free olf
-		self.free(ptr);
+			//free old, it is kept on out of memory
+			self.free(ptr);
+		}
 		
 		//Return
 		return new_ptr;
@@ -433,7 +468,7 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		osmem::munmap(ptr,2*1024*1024);
@@ -452,7 +487,7 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut registry)));
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>() + mem::size_of::<RegionSegment>());
+		assert_eq!(res, ptr + MediumChunk::header_size() + mem::size_of::<RegionSegment>());
 		assert_eq!(zero, false);
 
 		assert_eq!(ptr,registry.get_segment(res).unwrap().get().get_root_addr());
@@ -474,11 +509,11 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>() * 2 + 64);
+		assert_eq!(res, ptr + MediumChunk::header_size() * 2 + 64);
 		assert_eq!(zero, false);
 
 		osmem::munmap(ptr,2*1024*1024);
@@ -496,13 +531,13 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		manager.free(res);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		osmem::munmap(ptr,2*1024*1024);
@@ -520,11 +555,11 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let (res,zero) = manager.malloc(128,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		let res = manager.realloc(res,64);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(manager.get_inner_size(res), 128);
 
 		osmem::munmap(ptr,2*1024*1024);
@@ -542,11 +577,11 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		let res = manager.realloc(res,128);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(manager.get_inner_size(res), 128);
 
 		osmem::munmap(ptr,2*1024*1024);
@@ -560,7 +595,7 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		manager.malloc(64,BASIC_ALIGN,false);
@@ -583,14 +618,14 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		let res2 = manager.realloc(res,0);
 		assert_eq!(res2, NULL);
 
 		let (res,zero) = manager.malloc(64,BASIC_ALIGN,false);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 		assert_eq!(zero, false);
 
 		osmem::munmap(ptr,2*1024*1024);
@@ -604,7 +639,7 @@ mod tests
 		manager.fill(ptr, 2*1024*1024,None);
 
 		let res = manager.realloc(NULL,64);
-		assert_eq!(res, ptr + mem::size_of::<MediumChunk>());
+		assert_eq!(res, ptr + MediumChunk::header_size());
 
 		osmem::munmap(ptr,2*1024*1024);
 	}
@@ -658,7 +693,7 @@ mod tests
 		let (res,_) = manager.malloc(64,BASIC_ALIGN,false);
 		
 		assert_eq!(manager.get_inner_size(res),64);
-		assert_eq!(manager.get_total_size(res),64+mem::size_of::<MediumChunk>());
+		assert_eq!(manager.get_total_size(res),64+MediumChunk::header_size());
 		assert_eq!(manager.get_requested_size(res),UNSUPPORTED);
 
 		osmem::munmap(ptr,2*1024*1024);
@@ -695,6 +730,38 @@ mod tests
 		assert_eq!(registry.get_segment(ptr).is_none(),true);
 	}
 
+	#[test]
+	fn malloc_zeroed() {
+		let mut registry = RegionRegistry::new();
+		let mut mmsource = DummyMMSource::new(Some(SharedPtrBox::new_ref_mut(&mut registry)));
+		let mut manager = MediumChunkManager::new(false, Some(MemorySourcePtr::new_ptr_mut(&mut mmsource)));
+
+		//fresh macro bloc
+		let (ptr1,zero) = manager.malloc(1024, BASIC_ALIGN, true);
+		assert_eq!(zero, true);
+
+		//untouched tail, the list node was cleared
+		let (ptr2,zero) = manager.malloc(1024, BASIC_ALIGN, true);
+		assert_eq!(zero, true);
+		for i in 0..1024 {
+			assert_eq!(unsafe{*((ptr2 + i) as * const u8)}, 0);
+		}
+
+		//not asked
+		let (ptr3,zero) = manager.malloc(1024, BASIC_ALIGN, false);
+		assert_eq!(zero, false);
+
+		//reuse of dirty chunk
+		unsafe{*(ptr2 as * mut u8) = 1};
+		manager.free(ptr2);
+		let (ptr2,zero) = manager.malloc(1024, BASIC_ALIGN, true);
+		assert_eq!(zero, false);
+
+		manager.free(ptr1);
+		manager.free(ptr2);
+		manager.free(ptr3);
+	}
+
 	#[test]
 	fn malloc_align() {
 		let mut manager = MediumChunkManager::new(false, None);
@@ -749,4 +816,29 @@ mod tests
 
 		osmem::munmap(ptr,2*1024*1024);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn realloc_padded() {
+		let mut manager = MediumChunkManager::new(false, None);
+
+		let ptr = osmem::mmap(0,2*1024*1024);
+		manager.fill(ptr, 2*1024*1024,None);
+
+		let (res,_zero) = manager.malloc(4096, 4096, false);
+		assert_eq!(res % 4096, 0);
+		assert!(padding::PaddedChunk::unpad(res) != res);
+		unsafe{*((res + 4095) as * mut u8) = 42};
+
+		//in place
+		let inner_size = manager.get_inner_size(res);
+		assert_eq!(manager.realloc(res, inner_size), res);
+
+		//move
+		let res = manager.realloc(res, 64*1024);
+		assert!(manager.get_inner_size(res) >= 64*1024);
+		assert_eq!(unsafe{*((res + 4095) as * mut u8)}, 42);
+		manager.free(res);
+
+		osmem::munmap(ptr,2*1024*1024);
+	}
+}
@@ -18,6 +18,9 @@ use common::list::{List,ListNode};
 use common::consts::*;
 use portability::arch;
 use core::mem;
+use common::stats::DetailedReport;
+use common::ops;
+use portability::osmem;
 
 /// Provide the default list of size to be used to build segregated lists.
 // CAUTION, IF YOU CHANGE THIS YOU NEED TO ADAPT reverse_default_free_sizes() OR
@@ -113,6 +116,34 @@ impl MediumFreePool {
 		}
 	}
 
+	/// Add the number and size of free chunks of each list to the report.
+	pub fn collect_details(&self, details: &mut DetailedReport) {
+		for i in 0..self.nb_list {
+			let list = &mut details.medium[i];
+			list.max_size = self.sizes[i] as u64;
+			for chunk in self.lists[i].iter() {
+				list.chunks += 1;
+				list.bytes += chunk.get_inner_size() as u64;
+			}
+		}
+	}
+
+	/// Return to the OS the pages in the middle of the free chunks, keeping
+	/// the headers and list nodes in place. Return the released size.
+	pub fn trim(&self) -> Size {
+		let mut res = 0;
+		for i in 0..self.nb_list {
+			for chunk in self.lists[i].iter() {
+				let start = ops::up_to_power_of_2(chunk.get_content_addr() + mem::size_of::<ListNode>(), SMALL_PAGE_SIZE);
+				let end = ops::ceil_to_power_of_2(chunk.get_root_addr() + chunk.get_total_size(), SMALL_PAGE_SIZE);
+				if end > start && osmem::madvise_dontneed(start, end - start) {
+					res += end - start;
+				}
+			}
+		}
+		res
+	}
+
 	/// Insert a new memory segment in the pool.
 	/// 
 	/// @param ptr Define the base address of the segment.
@@ -16,6 +16,12 @@ use chunk::small::run::{SMALL_RUN_SIZE,SmallChunkRun,SmallChunkRunPtr};
 use common::ops;
 use common::consts::*;
 use core::mem;
+use portability::osmem;
+
+/// Max number of runs in a container built from a macro bloc.
+const MAX_RUNS: usize = MACRO_BLOC_SIZE / SMALL_RUN_SIZE + 1;
+/// Number of entries in the bitmap tracking the purged runs.
+const PURGED_ENTRIES: usize = (MAX_RUNS + 63) / 64;
 
 /// Implement container which is used to store all the runs obtained by splitting
 /// a macro bloc insto runs (segs of 4K thesemve splitted for given small sizes.)
@@ -25,19 +31,30 @@ pub struct SmallChunkContainer
 	empty: List<SmallChunkRun>,
 	size: Size,
 	reserved_runs: Size,
+	/// Number of runs in the empty list, their memory is still resident.
+	dirty_runs: Size,
+	/// Can give the empty runs back to the OS, not the case with MAP_HUGETLB pages.
+	purgeable: bool,
+	/// Runs not in memory, never touched or given back to the OS with madvise().
+	/// They are built when reused.
+	purged: [u64; PURGED_ENTRIES],
 }
 
 impl SmallChunkContainer {
 	/// Initizalize a new small run container onto the given allocated segment.
 	/// It setup the headers and make the splitting to generate free runs and keep
 	/// track of them.
-	pub fn setup(ptr: Addr, size: Size) -> SmallChunkContainerPtr {
+	/// @param zeroed Tell if the segment is fresh memory from the OS so the runs are known to be zero.
+	pub fn setup(ptr: Addr, size: Size, zeroed: bool) -> SmallChunkContainerPtr {
 		let mut cur = SmallChunkContainerPtr::new_addr(ptr);
 		cur.list_node = ListNode::new();
 		cur.empty = List::new();
 		cur.size = size;
 		cur.reserved_runs = 0;
-		cur.setup_splitting();
+		cur.dirty_runs = 0;
+		cur.purgeable = true;
+		cur.purged = [0; PURGED_ENTRIES];
+		cur.setup_splitting(zeroed);
 		return cur;
 	}
 
@@ -61,12 +78,20 @@ impl SmallChunkContainer {
 			debug_assert!(self.reserved_runs > 0);
 			self.empty.push_back(run);
 			self.reserved_runs -= 1;
+			self.dirty_runs += 1;
 		}
 	}
 
-	/// Request and empty run, can get None if not available.
+	/// Request and empty run, can get None if not available. We prefer the
+	/// runs still in memory over the purged ones.
 	pub fn get_empty_run(&mut self) -> Option<SmallChunkRunPtr> {
-		let res = self.empty.pop_front();
+		let res = match self.empty.pop_front() {
+			Some(run) => {
+				self.dirty_runs -= 1;
+				Some(run)
+			},
+			None => self.get_purged_run(),
+		};
 
 		if res.is_some() {
 			self.reserved_runs += 1;
@@ -76,8 +101,93 @@ impl SmallChunkContainer {
 		res
 	}
 
+	/// Return the size of the empty runs still in memory.
+	pub fn get_dirty_size(&self) -> Size {
+		self.dirty_runs * SMALL_RUN_SIZE
+	}
+
+	/// Allow or not to give the empty runs back to the OS. It must be disabled
+	/// if the pages are larger than the runs or shared with other processes.
+	pub fn set_purgeable(&mut self, purgeable: bool) {
+		self.purgeable = purgeable;
+	}
+
+	/// Give the memory of the empty runs back to the OS with madvise(). The first
+	/// run is kept as it hold the container header. Return the released size.
+	pub fn purge(&mut self) -> Size {
+		//cannot release parts of the pages
+		if !self.purgeable {
+			return 0;
+		}
+
+		//vars
+		let first = self.get_first_run_addr();
+		let mut res = 0;
+
+		//move the runs from the list to the bitmap, we cannot remove while iterating
+		//so we rotate the list once
+		for _ in 0..self.dirty_runs {
+			let run = self.empty.pop_front().unwrap();
+			let id = (run.get_addr() - first) / SMALL_RUN_SIZE;
+			if id == 0 || id >= MAX_RUNS {
+				self.empty.push_back(run);
+			} else {
+				self.purged[id / 64] |= 1 << (id % 64);
+				self.dirty_runs -= 1;
+				res += SMALL_RUN_SIZE;
+			}
+		}
+
+		//nothing to do
+		if res == 0 {
+			return 0;
+		}
+
+		//madvise by contiguous ranges, the already purged ones are free to include
+		let mut start = 0;
+		for id in 1..MAX_RUNS+1 {
+			let purged = id < MAX_RUNS && self.is_purged(id);
+			if purged && start == 0 {
+				start = id;
+			} else if !purged && start != 0 {
+				osmem::madvise_dontneed(first + start * SMALL_RUN_SIZE, (id - start) * SMALL_RUN_SIZE);
+				start = 0;
+			}
+		}
+
+		res
+	}
+
+	/// Check if the given run was purged.
+	fn is_purged(&self, id: usize) -> bool {
+		self.purged[id / 64] & (1 << (id % 64)) != 0
+	}
+
+	/// Rebuild the first purged run to reuse it, its memory is known to be zero.
+	fn get_purged_run(&mut self) -> Option<SmallChunkRunPtr> {
+		for (i,entry) in self.purged.iter_mut().enumerate() {
+			if *entry != 0 {
+				let bit = entry.trailing_zeros() as usize;
+				*entry &= !(1 << bit);
+				let addr = self.get_first_run_addr() + (i * 64 + bit) * SMALL_RUN_SIZE;
+				let mut run = SmallChunkRun::setup(addr, 0, 0, SmallChunkContainerPtr::new_ref(self));
+				run.set_zeroed(true);
+				return Some(run);
+			}
+		}
+		None
+	}
+
+	/// Return the address of the first run which overlap the container header.
+	fn get_first_run_addr(&self) -> Addr {
+		let addr = (self as * const SmallChunkContainer as Addr) + mem::size_of::<SmallChunkContainer>();
+		ops::ceil_to_power_of_2(addr, SMALL_RUN_SIZE)
+	}
+
 	/// Apply the splitting by creating the runs and adding them to the free list.
-	pub fn setup_splitting(&mut self) {
+	/// With fresh memory we do not touch the pages, the runs are built on first use.
+	/// @param zeroed Mark the runs as known to be zero.
+	pub fn setup_splitting(&mut self, zeroed: bool) {
 		//vars
 		let addr = (self as * const SmallChunkContainer as Addr) + mem::size_of::<SmallChunkContainer>();
 		let ptr_start = ops::ceil_to_power_of_2(addr, SMALL_RUN_SIZE);
@@ -98,12 +208,20 @@ impl SmallChunkContainer {
 			}
 			debug_assert!(skip < SMALL_RUN_SIZE);
 
+			//keep for later
+			if zeroed && skip == 0 && i < MAX_RUNS {
+				self.purged[i / 64] |= 1 << (i % 64);
+				continue;
+			}
+
 			//create run
 			let container = SmallChunkContainerPtr::new_ref(self);
-			let run = SmallChunkRun::setup(cur, skip as u16, 0, container);
+			let mut run = SmallChunkRun::setup(cur, skip as u16, 0, container);
+			run.set_zeroed(zeroed);
 
 			//insert
 			self.empty.push_back(run);
+			self.dirty_runs += 1;
 		}
 	}
 }
@@ -139,7 +257,7 @@ mod tests
 	fn setup() {
 		let ptr = osmem::mmap(0, 2*1024*1024);
 
-		let container = SmallChunkContainer::setup(ptr, 2*1024*1024);
+		let container = SmallChunkContainer::setup(ptr, 2*1024*1024, false);
 		assert_eq!(container.is_empty(), true);
 
 		osmem::munmap(ptr, 2*1024*1024);
@@ -149,7 +267,7 @@ mod tests
 	fn is_empty() {
 		let ptr = osmem::mmap(0, 2*1024*1024);
 
-		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024);
+		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024, false);
 		assert_eq!(container.is_empty(), true);
 
 		let run = container.get_empty_run();
@@ -166,7 +284,7 @@ mod tests
 	fn get_empty_run_1() {
 		let ptr = osmem::mmap(0, 2*1024*1024);
 
-		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024);
+		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024, false);
 		assert_eq!(container.is_empty(), true);
 
 		let run1 = container.get_empty_run();
@@ -184,7 +302,7 @@ mod tests
 	fn get_empty_run_2() {
 		let ptr = osmem::mmap(0, 2*1024*1024);
 
-		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024);
+		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024, false);
 		assert_eq!(container.is_empty(), true);
 
 		let mut cnt = 0;
@@ -206,7 +324,7 @@ mod tests
 	fn get_empty_run_3() {
 		let ptr = osmem::mmap(0, 2*1024*1024);
 
-		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024);
+		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024, false);
 		assert_eq!(container.is_empty(), true);
 
 		let mut run = container.get_empty_run().unwrap();
@@ -223,4 +341,78 @@ mod tests
 		
 		osmem::munmap(ptr, 2*1024*1024);
 	}
+
+	#[test]
+	fn setup_zeroed() {
+		let ptr = osmem::mmap(0, 2*1024*1024);
+
+		//only the header run is touched
+		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024, true);
+		assert_eq!(container.get_dirty_size(), SMALL_RUN_SIZE);
+
+		let mut cnt = 0;
+		while container.get_empty_run().is_some() {
+			cnt += 1;
+		}
+		assert_eq!(2*1024*1024 / SMALL_RUN_SIZE, cnt);
+
+		osmem::munmap(ptr, 2*1024*1024);
+	}
+
+	#[test]
+	fn purge() {
+		let ptr = osmem::mmap(0, 2*1024*1024);
+
+		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024, false);
+		assert_eq!(container.get_dirty_size(), 2*1024*1024);
+
+		//keep one in use and dirty another one
+		let mut run1 = container.get_empty_run().unwrap();
+		let mut run2 = container.get_empty_run().unwrap();
+		run2.set_splitting(16);
+		let (c,_) = run2.malloc(16,16,false);
+		unsafe{*(c as * mut u64) = 42};
+		run2.free(c);
+		run2.set_splitting(0);
+		container.reg_empty(run2.clone());
+
+		//the first run with the header is kept
+		assert_eq!(container.purge(), 2*1024*1024 - SMALL_RUN_SIZE);
+		assert_eq!(container.get_dirty_size(), 0);
+		assert_eq!(container.is_empty(), false);
+		assert_eq!(unsafe{*(c as * const u64)}, 0);
+		container.reg_empty(run1.clone());
+		assert_eq!(container.get_dirty_size(), SMALL_RUN_SIZE);
+		assert_eq!(container.purge(), 0);
+		assert_eq!(container.is_empty(), true);
+
+		//resident first then the purged ones, cleared
+		assert_eq!(container.get_empty_run().unwrap().get_addr(), run1.get_addr());
+		run1 = container.get_empty_run().unwrap();
+		run1.set_splitting(16);
+		let (c,zero) = run1.malloc(16,16,true);
+		assert!(c != NULL);
+		assert_eq!(zero, true);
+
+		//all still there
+		let mut cnt = 2;
+		while container.get_empty_run().is_some() {
+			cnt += 1;
+		}
+		assert_eq!(2*1024*1024 / SMALL_RUN_SIZE, cnt);
+
+		osmem::munmap(ptr, 2*1024*1024);
+	}
+
+	#[test]
+	fn purge_disabled() {
+		let ptr = osmem::mmap(0, 2*1024*1024);
+
+		let mut container = SmallChunkContainer::setup(ptr, 2*1024*1024, false);
+		container.set_purgeable(false);
+		assert_eq!(container.purge(), 0);
+		assert_eq!(container.get_dirty_size(), 2*1024*1024);
+
+		osmem::munmap(ptr, 2*1024*1024);
+	}
 }
\ No newline at end of file
@@ -16,18 +16,27 @@ use registry::registry::RegionRegistry;
 use common::types::{Addr,Size};
 use common::consts::*;
 use common::ops;
+use common::config;
 use common::shared::SharedPtrBox;
 use core::mem;
 use registry::segment::RegionSegment;
 use portability::libc;
 use common::list::List;
+use common::stats::{DetailedReport,StatsReport};
 use chunk::small::run::{SmallChunkRun,SmallChunkRunPtr,SMALL_RUN_SIZE};
 use chunk::small::container::{SmallChunkContainer};
 
 //consts
-const NB_SIZE_CLASS: usize = 10;
 pub const SMALL_CHUNK_MAX_SIZE: usize = 128;
-const SMALL_SIZE_CLASSES: [Size;NB_SIZE_CLASS] = [8, 16, 24, 32, 48, 64, 80, 96, 112, 128];
+//all the classes must be multiple of BASIC_ALIGN
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub const NB_SIZE_CLASS: usize = 8;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub const SMALL_SIZE_CLASSES: [Size;NB_SIZE_CLASS] = [16, 32, 48, 64, 80, 96, 112, 128];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub const NB_SIZE_CLASS: usize = 10;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub const SMALL_SIZE_CLASSES: [Size;NB_SIZE_CLASS] = [8, 16, 24, 32, 48, 64, 80, 96, 112, 128];
 //8 16 24 32 48 64 80 96 128
 
 /// Group content to protect by spinlock
@@ -36,6 +45,8 @@ struct SmallChunkManagerLocked {
 	active_runs: [Option<SmallChunkRunPtr>; NB_SIZE_CLASS],
 	in_use: [List<SmallChunkRun>; NB_SIZE_CLASS],
 	containers: List<SmallChunkContainer>,
+	/// Size of empty runs a container keep before purging them, 0 to never do it automatically.
+	purge_threashold: Size,
 }
 
 /// Implement the small chunk allocator based on MediumFreePool
@@ -56,9 +67,10 @@ impl SmallChunkManager {
 		Self {
 			locked: SpinLock::new(SmallChunkManagerLocked {
 				mmsource: mmsource,
-				active_runs: [None,None,None,None,None,None,None,None,None,None],
+				active_runs: Default::default(),
 				in_use: [List::new(); NB_SIZE_CLASS],
 				containers: List::new(), 
+				purge_threashold: config::get().small_purge_threashold,
 			}),
 			use_lock: use_lock,
 			parent: None,
@@ -67,54 +79,166 @@ impl SmallChunkManager {
 
 	/// Allocate a new segment.
 	/// @param size Define the size to allocate. It will be rounded to the class sizes.
-	/// @param align Define alignement. Currently support only BASIC_ALIGN
-	/// @param zero_filled Ask for memory cleared to zero or not. This parameter is currently ignored
-	/// and all chunks will be returned non zeroed and the caller has to do it.
-	pub fn malloc(&mut self, mut size: Size, align:Size, zero_filled: bool) -> (Addr,bool) {
-		//check align
-		if align != BASIC_ALIGN {
-			panic!("TODO support align");
+	/// @param align Define alignement, it must be a power of 2 not larger than SMALL_CHUNK_MAX_SIZE.
+	/// @param zero_filled Ask for memory cleared to zero or not. It is never cleared here, the
+	/// returned status tell if the chunk comes from a run never touched since we got it from the OS.
+	pub fn malloc(&mut self, size: Size, align:Size, zero_filled: bool) -> (Addr,bool) {
+		//we always guarantee the basic alignement
+		let align = if align < BASIC_ALIGN {BASIC_ALIGN} else {align};
+
+		//get related size class
+		let size_class = Self::get_size_class_for(size, align);
+
+		//lock
+		let (res,zero);
+		{
+			let mut handler = self.locked.optional_lock(self.use_lock);
+			let (a,z) = handler.malloc_in_class(size_class, align, zero_filled, ChunkManagerPtr::new_ref(self));
+			res = a;
+			zero = z;
 		}
 
-		//round if smallest size to avoid checking warning of filling ratio in SmallChunkRun
-		if size < SMALL_SIZE_CLASSES[0] {
-			size = SMALL_SIZE_CLASSES[0];
+		//check
+		debug_assert!(res == NULL || res % align == 0);
+
+		//ret
+		return (res,res != NULL && zero);
+	}
+
+	/// Allocate several chunks of the given size class taking the lock only once.
+	/// This is used to refill the thread caches. Return the number of chunks
+	/// obtained which can be less than requested if we run out of memory.
+	///
+	/// @param size_class The size class to allocate from.
+	/// @param out Where to store the chunk addresses, we try to fill it completely.
+	pub fn malloc_batch(&mut self, size_class: usize, out: &mut [Addr]) -> usize {
+		//errors
+		debug_assert!(size_class < NB_SIZE_CLASS);
+
+		//lock once for all
+		let mut handler = self.locked.optional_lock(self.use_lock);
+		for (i,it) in out.iter_mut().enumerate() {
+			let (res,_) = handler.malloc_in_class(size_class, BASIC_ALIGN, false, ChunkManagerPtr::new_ref(self));
+			if res == NULL {
+				return i;
+			}
+			*it = res;
 		}
 
+		out.len()
+	}
+
+	/// Free several chunks taking the lock only once. This is used to flush the
+	/// thread caches.
+	///
+	/// @param ptrs The chunks to free, they must all belong to this manager.
+	pub fn free_batch(&mut self, ptrs: &[Addr]) {
+		let mut handler = self.locked.optional_lock(self.use_lock);
+		for ptr in ptrs.iter() {
+			let run = self.get_run(*ptr);
+			debug_assert!(run.is_some());
+			match run {
+				Some(run) => handler.free_in_run(run, *ptr),
+				None => {},
+			}
+		}
+	}
+
+	/// Return the size class in which the given chunk was allocated.
+	///
+	/// @param ptr Address of a chunk allocated by this manager.
+	pub fn get_chunk_size_class(&self, ptr: Addr) -> usize {
+		let run = self.get_run(ptr);
+		debug_assert!(run.is_some());
+		Self::get_size_class(run.unwrap().get_splitting() as usize)
+	}
+
+	/// Get the size class to use for the given size and alignement. The chunks of
+	/// the returned class are always aligned as requested.
+	///
+	/// @param size The requested size, it must not be larger than SMALL_CHUNK_MAX_SIZE.
+	/// @param align Define alignement, it must be a power of 2 not larger than SMALL_CHUNK_MAX_SIZE.
+	pub fn get_size_class_for(size: Size, align: Size) -> usize {
+		//errors
+		debug_assert!(align <= SMALL_CHUNK_MAX_SIZE);
+		debug_assert!(align.is_power_of_two());
+
+		//round if smallest size to avoid checking warning of filling ratio in SmallChunkRun
+		let size = if size < SMALL_SIZE_CLASSES[0] {SMALL_SIZE_CLASSES[0]} else {size};
+
 		//get related size class
-		let size_class = Self::get_size_class(size);
+		let mut size_class = Self::get_size_class(size);
+
+		//runs are aligned on SMALL_RUN_SIZE so the chunks are aligned on the
+		//largest power of 2 dividing the class size, move to the next class until
+		//it match (the last one is aligned on SMALL_CHUNK_MAX_SIZE)
+		while SMALL_SIZE_CLASSES[size_class] % align != 0 {
+			size_class += 1;
+		}
 		debug_assert!(SMALL_SIZE_CLASSES[size_class] % align == 0);
 
-		//lock
-		let mut res = NULL;
-		{
-			let mut handler = self.locked.optional_lock(self.use_lock);
+		size_class
+	}
+
+	/// Add the usage of the runs currently split for a size class to the report.
+	pub fn collect_stats(&self, report: &mut StatsReport) {
+		let handler = self.locked.optional_lock(self.use_lock);
+		for class in 0..NB_SIZE_CLASS {
+			//active one
+			match handler.active_runs[class] {
+				Some(ref run) => Self::collect_run_stats(run, report),
+				None => {},
+			}
 
-			//get active run for class
-			{
-				let run = &mut (handler.active_runs[size_class]);
+			//others
+			for run in handler.in_use[class].iter() {
+				Self::collect_run_stats(&run, report);
+			}
+		}
+	}
 
-				//try to alloc
-				match run {
-					Some(ref mut run) => res = run.malloc(size,align,zero_filled).0,
-					None => {},
-				}
+	/// Add the usage of the runs of each size class to the report.
+	pub fn collect_details(&self, details: &mut DetailedReport) {
+		let handler = self.locked.optional_lock(self.use_lock);
+		for class in 0..NB_SIZE_CLASS {
+			let report = &mut details.small[class];
+			report.size = SMALL_SIZE_CLASSES[class] as u64;
+
+			//active one
+			match handler.active_runs[class] {
+				Some(ref run) => {
+					report.runs += 1;
+					report.chunks += run.get_max_cnt_alloc() as u64;
+					report.chunks_used += run.get_cnt_alloc() as u64;
+				},
+				None => {},
 			}
 
-			if res == NULL {
-				let run = handler.update_active_run_for_size(size_class,ChunkManagerPtr::new_ref(self));
-				match run {
-					Some(mut run) => res = run.malloc(size,align,zero_filled).0,
-					None => {},
-				}
+			//others
+			for run in handler.in_use[class].iter() {
+				report.runs += 1;
+				report.chunks += run.get_max_cnt_alloc() as u64;
+				report.chunks_used += run.get_cnt_alloc() as u64;
 			}
 		}
+	}
 
-		//check
-		debug_assert!(res == NULL || res % align == 0);
+	/// Add the usage of one run to the report.
+	fn collect_run_stats(run: &SmallChunkRunPtr, report: &mut StatsReport) {
+		report.small_runs += 1;
+		report.small_chunks += run.get_max_cnt_alloc() as u64;
+		report.small_chunks_used += run.get_cnt_alloc() as u64;
+	}
 
-		//ret
-		return (res,false);
+	/// Return the fully empty containers to the memory source and the empty runs of
+	/// the others to the OS. Return the released size.
+	pub fn trim(&mut self) -> Size {
+		let mut handler = self.locked.optional_lock(self.use_lock);
+		let mut res = handler.release_empty_containers();
+		for mut container in handler.containers.iter() {
+			res += container.purge();
+		}
+		res
 	}
 
 	/// Change the memory source attached to the manager.
@@ -145,7 +269,7 @@ impl SmallChunkManager {
 		}
 		
 		//setup run container
-		let container = SmallChunkContainer::setup(addr,size);
+		let container = SmallChunkContainer::setup(addr,size,false);
 
 		//reg in list
 		{
@@ -169,16 +293,16 @@ impl SmallChunkManager {
 		}
 		
 		//if too small
-		if size < 8 {
-			size = 8;
+		if size < SMALL_SIZE_CLASSES[0] {
+			size = SMALL_SIZE_CLASSES[0];
 		}
 		
-		//calc from 8 to 32
+		//calc by steps of BASIC_ALIGN up to 32 then by steps of 16
 		let res;
 		if size <= 32 {
-			res = (size - 1) / 8;
+			res = (size - 1) / BASIC_ALIGN;
 		} else {
-			res = (size - 1) / 16 + 2;
+			res = (size - 1) / 16 + 32 / BASIC_ALIGN - 2;
 		}
 
 		debug_assert!(SMALL_SIZE_CLASSES[res] >= size);
@@ -207,6 +331,24 @@ impl SmallChunkManager {
 			return None;
 		}
 	}
+
+	/// Get the small chunk manager owning the given chunk without walking the region
+	/// registry. The run points its container which is stored at the begining of the
+	/// segment content so we can read the manager from the segment header.
+	/// This only apply to segments obtained from the memory source or registered by fill().
+	/// @param ptr Address of a chunk allocated by a small chunk manager.
+	pub fn get_owner(ptr: Addr) -> Option<ChunkManagerPtr> {
+		//errors
+		debug_assert!(ptr != NULL);
+
+		//round addr
+		let run = SmallChunkRunPtr::new_addr(ops::ceil_to_power_of_2(ptr, SMALL_RUN_SIZE));
+		debug_assert!(run.contain(ptr));
+
+		//go up to the segment
+		let container = run.get_container();
+		RegionSegment::get_from_content_ptr(container.get_addr()).get_manager()
+	}
 }
 
 impl ChunkManager for SmallChunkManager {
@@ -223,17 +365,9 @@ impl ChunkManager for SmallChunkManager {
 
 		//if found
 		match run {
-			Some(mut run) => {
-				//lock
+			Some(run) => {
 				let mut handler = self.locked.optional_lock(self.use_lock);
-
-				//free
-				run.free(ptr);
-				
-				//if empty move to empty list
-				if run.is_empty() {
-					handler.mark_run_as_free(run);
-				}
+				handler.free_in_run(run, ptr);
 			},
 			None => {},
 		}
@@ -339,6 +473,40 @@ impl ChunkManager for SmallChunkManager {
 }
 
 impl SmallChunkManagerLocked {
+	/// Allocate a chunk in the active run of the given size class, switch to another
+	/// run if it is full.
+	fn malloc_in_class(&mut self, size_class: usize, align: Size, zero_filled: bool, manager:ChunkManagerPtr) -> (Addr,bool) {
+		let size = SMALL_SIZE_CLASSES[size_class];
+
+		//try in active run
+		match self.active_runs[size_class] {
+			Some(ref mut run) => {
+				let (res,zero) = run.malloc(size,align,zero_filled);
+				if res != NULL {
+					return (res,zero);
+				}
+			},
+			None => {},
+		}
+
+		//need a new one
+		match self.update_active_run_for_size(size_class,manager) {
+			Some(mut run) => run.malloc(size,align,zero_filled),
+			None => (NULL,false),
+		}
+	}
+
+	/// Free a chunk in its run and move the run to the empty list if it was the last one.
+	fn free_in_run(&mut self, mut run: SmallChunkRunPtr, ptr: Addr) {
+		//free
+		run.free(ptr);
+		
+		//if empty move to empty list
+		if run.is_empty() {
+			self.mark_run_as_free(run);
+		}
+	}
+
 	/// Refill the memory of the manager by requesting memory to the memory source.
 	fn refill(&mut self,manager:ChunkManagerPtr) {
 		//trivial
@@ -347,7 +515,7 @@ impl SmallChunkManagerLocked {
 		}
 		
 		//request mem
-		let (segment,_) = self.mmsource.as_mut().unwrap().map(REGION_SPLITTING-mem::size_of::<RegionSegment>(),false,Some(manager));
+		let (segment,zero) = self.mmsource.as_mut().unwrap().map(REGION_SPLITTING-mem::size_of::<RegionSegment>(),false,Some(manager));
 		if segment.is_null() {
 			return;
 		}
@@ -360,7 +528,8 @@ impl SmallChunkManagerLocked {
 		let inner_size = segment.get_inner_size();
 		
 		//setup run container
-		let container = SmallChunkContainer::setup(ptr,inner_size);
+		let mut container = SmallChunkContainer::setup(ptr,inner_size,zero);
+		container.set_purgeable(segment.is_purgeable());
 
 		//register to list
 		self.containers.push_back(container);
@@ -429,6 +598,36 @@ impl SmallChunkManagerLocked {
 		return run;
 	}
 
+	/// Return all the containers which have no more reserved runs to the memory source.
+	fn release_empty_containers(&mut self) -> Size {
+		//trivial
+		if self.mmsource.is_none() {
+			return 0;
+		}
+
+		//loop as we cannot remove while iterating
+		let mut res = 0;
+		loop {
+			let mut found = None;
+			for it in self.containers.iter() {
+				if it.is_empty() {
+					found = Some(it);
+					break;
+				}
+			}
+
+			match found {
+				Some(mut container) => {
+					List::remove(&mut container);
+					let segment = RegionSegment::get_from_content_ptr(container.get_addr());
+					res += segment.get_total_size();
+					self.mmsource.as_mut().unwrap().unmap(segment);
+				},
+				None => return res,
+			}
+		}
+	}
+
 	/// Mark the given run as empty and register it in the reuse list for any size class.
 	fn mark_run_as_free(&mut self, mut run: SmallChunkRunPtr) {
 		//errors
@@ -466,10 +665,12 @@ impl SmallChunkManagerLocked {
 		run.set_splitting(0);
 		container.reg_empty(run);
 		
-		//if container is empty, remove it
+		//if container is empty, remove it, otherwise purge if keeping too much
 		if container.is_empty() && self.mmsource.is_some() {
 			List::remove(&mut container);
 			self.mmsource.as_mut().unwrap().unmap(RegionSegment::get_from_content_ptr(container.get_addr()));
+		} else if self.purge_threashold > 0 && container.get_dirty_size() > self.purge_threashold {
+			container.purge();
 		}
 	}
 }
@@ -515,9 +716,9 @@ mod tests
 		let mem = osmem::mmap(NULL, SMALL_PAGE_SIZE);
 		manager.fill(mem, SMALL_PAGE_SIZE, None);
 		let (ptr,_) = manager.malloc(4, BASIC_ALIGN, false);
-		assert_eq!(ptr, mem+63*8);
+		assert_eq!(ptr, mem+63*SMALL_SIZE_CLASSES[0]);
 		let (ptr,_) = manager.malloc(4, BASIC_ALIGN, false);
-		assert_eq!(ptr, mem+62*8);
+		assert_eq!(ptr, mem+62*SMALL_SIZE_CLASSES[0]);
 		osmem::munmap(mem, SMALL_PAGE_SIZE);
 	}
 
@@ -549,7 +750,7 @@ mod tests
 			}
 		}
 		
-		assert_eq!(cnt, SMALL_PAGE_SIZE / 16-11);
+		assert_eq!(cnt, SMALL_PAGE_SIZE / 16-17);
 
 		osmem::munmap(mem, SMALL_PAGE_SIZE);
 	}
@@ -570,7 +771,7 @@ mod tests
 			}
 		}
 		
-		assert_eq!(cnt, 2*(SMALL_PAGE_SIZE / 16-11) + 5);
+		assert_eq!(cnt, (SMALL_PAGE_SIZE / 16-17) + (SMALL_PAGE_SIZE / 16-6));
 
 		osmem::munmap(mem, 2*SMALL_PAGE_SIZE);
 	}
@@ -592,7 +793,7 @@ mod tests
 			}
 		}
 		
-		assert_eq!(cnt, SMALL_PAGE_SIZE / 16-13);
+		assert_eq!(cnt, SMALL_PAGE_SIZE / 16-19);
 
 		osmem::munmap(mem, SMALL_PAGE_SIZE);
 	}
@@ -621,6 +822,20 @@ mod tests
 		osmem::munmap(mem, REGION_SPLITTING);
 	}
 
+	#[test]
+	fn get_owner() {
+		let mmsource = DummyMMSource::new(None);
+		let mut manager = SmallChunkManager::new(true, Some(MemorySourcePtr::new_ref(&mmsource)));
+
+		//check on several runs
+		for size in SMALL_SIZE_CLASSES.iter() {
+			let (ptr,_) = manager.malloc(*size, BASIC_ALIGN, false);
+			assert!(ptr != NULL);
+			assert!(SmallChunkManager::get_owner(ptr) == Some(ChunkManagerPtr::new_ref(&manager)));
+			manager.free(ptr);
+		}
+	}
+
 	#[test]
 	fn refill() {
 		let mmsource = DummyMMSource::new(None);
@@ -632,6 +847,80 @@ mod tests
 		}
 	}
 
+	#[test]
+	fn malloc_zeroed() {
+		let mmsource = DummyMMSource::new(None);
+		let mut manager = SmallChunkManager::new(true, Some(MemorySourcePtr::new_ref(&mmsource)));
+
+		//fresh run
+		let (ptr1,zeroed) = manager.malloc(32, BASIC_ALIGN, true);
+		assert!(ptr1 != NULL);
+		assert_eq!(zeroed, true);
+		let (ptr2,zeroed) = manager.malloc(32, BASIC_ALIGN, true);
+		assert_eq!(zeroed, true);
+		unsafe{*(ptr2 as * mut u8) = 1};
+
+		//after a free the run is dirty
+		manager.free(ptr2);
+		let (ptr2,zeroed) = manager.malloc(32, BASIC_ALIGN, true);
+		assert!(ptr2 != NULL);
+		assert_eq!(zeroed, false);
+
+		manager.free(ptr1);
+		manager.free(ptr2);
+	}
+
+	#[test]
+	fn trim() {
+		let mmsource = DummyMMSource::new(None);
+		let mut manager = SmallChunkManager::new(true, Some(MemorySourcePtr::new_ref(&mmsource)));
+
+		//keep one to not release the container
+		let (keep,_) = manager.malloc(16, BASIC_ALIGN, false);
+		let mut ptrs = [NULL; 4096];
+		for ptr in ptrs.iter_mut() {
+			*ptr = manager.malloc(16, BASIC_ALIGN, false).0;
+			assert!(*ptr != NULL);
+		}
+		for ptr in ptrs.iter() {
+			manager.free(*ptr);
+		}
+
+		//the empty runs are purged
+		assert!(manager.trim() >= 16 * SMALL_RUN_SIZE);
+		assert_eq!(manager.trim(), 0);
+		let (ptr,zeroed) = manager.malloc(128, BASIC_ALIGN, true);
+		assert!(ptr != NULL);
+		assert_eq!(zeroed, true);
+		manager.free(ptr);
+		manager.free(keep);
+
+		//container released
+		assert_eq!(manager.trim(), 0);
+		assert!(manager.locked.lock().containers.is_empty());
+	}
+
+	#[test]
+	fn purge_threashold() {
+		let mmsource = DummyMMSource::new(None);
+		let mut manager = SmallChunkManager::new(true, Some(MemorySourcePtr::new_ref(&mmsource)));
+		manager.locked.lock().purge_threashold = 4 * SMALL_RUN_SIZE;
+
+		let (keep,_) = manager.malloc(16, BASIC_ALIGN, false);
+		let mut ptrs = [NULL; 4096];
+		for ptr in ptrs.iter_mut() {
+			*ptr = manager.malloc(16, BASIC_ALIGN, false).0;
+		}
+		for ptr in ptrs.iter() {
+			manager.free(*ptr);
+		}
+
+		//never keep more than the threashold
+		assert!(manager.locked.lock().containers.front().unwrap().get_dirty_size() <= 4 * SMALL_RUN_SIZE);
+		assert!(manager.trim() <= 4 * SMALL_RUN_SIZE);
+		manager.free(keep);
+	}
+
 	#[test]
 	fn free_1() {
 		let mut manager = SmallChunkManager::new(true, None);
@@ -681,7 +970,7 @@ mod tests
 			}
 		}
 		
-		assert_eq!(cnt, 2*(SMALL_PAGE_SIZE / 16-11) + 5);
+		assert_eq!(cnt, (SMALL_PAGE_SIZE / 16-17) + (SMALL_PAGE_SIZE / 16-6));
 
 		for i in 0..2*(SMALL_PAGE_SIZE / 16-10 + 5) {
 			manager.free(ptrs[i]);
@@ -743,4 +1032,77 @@ mod tests
 		let manager = SmallChunkManager::new(true, None);
 		assert_eq!(true, manager.is_thread_safe());
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn malloc_align() {
+		let mmsource = DummyMMSource::new(None);
+		let mut manager = SmallChunkManager::new(true, Some(MemorySourcePtr::new_ref(&mmsource)));
+
+		let mut align = BASIC_ALIGN;
+		while align <= SMALL_CHUNK_MAX_SIZE {
+			for size in SMALL_SIZE_CLASSES.iter() {
+				for _ in 0..100 {
+					let (ptr,_) = manager.malloc(*size, align, false);
+					assert!(ptr != NULL);
+					assert_eq!(ptr % align, 0);
+					assert!(manager.get_inner_size(ptr) >= *size);
+				}
+			}
+			align *= 2;
+		}
+	}
+
+	#[test]
+	fn malloc_align_free() {
+		let mut manager = SmallChunkManager::new(true, None);
+		let mem = osmem::mmap(NULL, 2*SMALL_PAGE_SIZE);
+		manager.fill(mem, 2*SMALL_PAGE_SIZE, None);
+
+		//24 is only 8 bytes aligned so 32 is used
+		let (ptr,_) = manager.malloc(24, 16, false);
+		assert_eq!(ptr % 16, 0);
+		assert_eq!(manager.get_inner_size(ptr), 32);
+		manager.free(ptr);
+
+		//a small chunk in the last class
+		let (ptr,_) = manager.malloc(8, 128, false);
+		assert_eq!(ptr % 128, 0);
+		assert_eq!(manager.get_inner_size(ptr), 128);
+		manager.free(ptr);
+
+		osmem::munmap(mem, 2*SMALL_PAGE_SIZE);
+	}
+
+	#[test]
+	fn malloc_free_batch() {
+		let mut manager = SmallChunkManager::new(true, None);
+		let mem = osmem::mmap(NULL, SMALL_PAGE_SIZE);
+		manager.fill(mem, SMALL_PAGE_SIZE, None);
+
+		//get them all in two batchs
+		let mut ptrs = [NULL; 32];
+		assert_eq!(manager.malloc_batch(0, &mut ptrs), 32);
+		assert_eq!(ptrs[0], mem+63*16);
+		assert_eq!(ptrs[1], mem+62*16);
+		let mut ptrs2 = [NULL; 256];
+		assert_eq!(manager.malloc_batch(0, &mut ptrs2), SMALL_PAGE_SIZE / 16 - 17 - 32);
+		for ptr in ptrs.iter() {
+			assert_eq!(manager.get_chunk_size_class(*ptr), 0);
+		}
+
+		//return them and get the same back
+		manager.free_batch(&ptrs);
+		let (ptr,_) = manager.malloc(16, BASIC_ALIGN, false);
+		assert!(ptrs.contains(&ptr));
+
+		osmem::munmap(mem, SMALL_PAGE_SIZE);
+	}
+
+	#[test]
+	fn get_size_class_for() {
+		assert_eq!(SmallChunkManager::get_size_class_for(1, BASIC_ALIGN), 0);
+		assert_eq!(SMALL_SIZE_CLASSES[SmallChunkManager::get_size_class_for(24, 16)], 32);
+		assert_eq!(SMALL_SIZE_CLASSES[SmallChunkManager::get_size_class_for(8, 128)], 128);
+		assert_eq!(SMALL_SIZE_CLASSES[SmallChunkManager::get_size_class_for(100, BASIC_ALIGN)], 112);
+	}
+}
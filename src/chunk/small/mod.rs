@@ -13,4 +13,5 @@
 /// export
 pub mod run;
 pub mod container;
-pub mod manager;
\ No newline at end of file
+pub mod manager;
+pub mod tcache;
\ No newline at end of file
@@ -38,6 +38,8 @@ const STORAGE_SIZE: usize = STORAGE_ENTRIES * MACRO_ENTRY_SIZE;
 /// when placing this into macro blocs we need to skip the macro bloc
 /// header which reside at begenning of the segment so we just have to maek
 /// this overlapping part as allocated to ignore it in the run.
+/// The storage must stay at the run address as the chunk alignement rely on it.
+#[repr(C)]
 pub struct SmallChunkRun {
 	data:[MacroEntry; STORAGE_ENTRIES],
 	list_node: ListNode,
@@ -47,6 +49,8 @@ pub struct SmallChunkRun {
 	splitting: SmallSize,
 	bitmap_entries: SmallSize,
 	max_cnt_alloc: SmallSize,
+	/// The free chunks are known to be zero as nothing was freed since we got the memory from the OS.
+	zeroed: bool,
 }
 
 /// Used to point
@@ -70,6 +74,7 @@ impl SmallChunkRun {
 		cur.bitmap_entries = 0;
 		cur.container = container;
 		cur.list_node = ListNode::new();
+		cur.zeroed = false;
 		if splitting > 0 {
 			cur.set_splitting(splitting);
 		}
@@ -144,6 +149,21 @@ impl SmallChunkRun {
 		assert_eq!(self.max_cnt_alloc, self.count_avail_chunks());
 	}
 
+	/// Mark the run storage as known to be zero. It is reset on the first free.
+	pub fn set_zeroed(&mut self, zeroed: bool) {
+		self.zeroed = zeroed;
+	}
+
+	/// Return the number of chunks currently allocated in the run.
+	pub fn get_cnt_alloc(&self) -> SmallSize {
+		self.cnt_alloc
+	}
+
+	/// Return the number of chunks the run can contain with its current splitting.
+	pub fn get_max_cnt_alloc(&self) -> SmallSize {
+		self.max_cnt_alloc
+	}
+
 	/// Check if the run is empty and contain no allocated segments.
 	pub fn is_empty(&self) -> bool {
 		self.cnt_alloc == 0
@@ -174,6 +194,7 @@ impl SmallChunkRun {
 	/// @param size Define the size of the chunk we want to allocated
 	/// @param align Define the alignement we want, which should be equal to splitting size currently.
 	/// @param zero_filled Define if we want a zero filled segment or not
+	/// @return The chunk address and true if it is known to be zero.
 	pub fn malloc(&mut self,size: Size, align: Size, zero_filled: bool) -> (Addr,bool) {
 		//check size
 		if size > self.splitting as usize {
@@ -201,7 +222,7 @@ impl SmallChunkRun {
 				self.cnt_alloc += 1;
 				let base_addr = (&self.data) as * const MacroEntry as Addr;
 				let addr = base_addr + self.splitting as usize * id as usize;
-				return (addr,false);
+				return (addr,self.zeroed);
 			}
 		}
 		
@@ -228,6 +249,9 @@ impl SmallChunkRun {
 		
 		//update counter
 		self.cnt_alloc -= 1;
+
+		//the chunk can be reused so content is not zero anymore
+		self.zeroed = false;
 	}
 
 	/// Return the internal size of the segment at given address. For small chunk
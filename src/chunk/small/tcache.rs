@@ -0,0 +1,199 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+/// Implement a thread cache in front of the SmallChunkManager. For each size
+/// class we keep a bounded stack (magazine) of recently freed chunks so short
+/// lived objects are served without locking or searching in the run bitmaps.
+/// The magazines are refilled and flushed by batches against the runs.
+///
+/// This must only be used by the thread owning the local allocator, remote
+/// frees directly go to the runs.
+
+//import
+use common::types::{Addr,Size};
+use common::consts::*;
+use chunk::small::manager::{SmallChunkManager,NB_SIZE_CLASS};
+
+/// Stack of cached chunks for one size class.
+#[derive(Copy,Clone)]
+struct Magazine {
+	cnt: usize,
+	entries: [Addr; TCACHE_MAX_DEPTH],
+}
+
+/// The thread cache with one magazine per size class.
+pub struct SmallTCache {
+	/// Max number of chunks to keep per size class, 0 if disabled.
+	depth: usize,
+	magazines: [Magazine; NB_SIZE_CLASS],
+}
+
+impl Magazine {
+	fn new() -> Self {
+		Self {
+			cnt: 0,
+			entries: [NULL; TCACHE_MAX_DEPTH],
+		}
+	}
+}
+
+impl SmallTCache {
+	/// Build an empty cache.
+	///
+	/// @param depth Number of chunks to keep per size class, 0 to disable the cache.
+	pub fn new(depth: Size) -> Self {
+		debug_assert!(depth <= TCACHE_MAX_DEPTH);
+		Self {
+			depth: depth,
+			magazines: [Magazine::new(); NB_SIZE_CLASS],
+		}
+	}
+
+	/// Check if the cache is in use.
+	#[inline]
+	pub fn is_enabled(&self) -> bool {
+		self.depth > 0
+	}
+
+	/// Change the number of chunks kept per size class. The cache must be empty.
+	pub fn set_depth(&mut self, depth: Size) {
+		debug_assert!(depth <= TCACHE_MAX_DEPTH);
+		debug_assert!(self.get_cached() == 0);
+		self.depth = depth;
+	}
+
+	/// Return the number of chunks currently kept in the cache.
+	pub fn get_cached(&self) -> usize {
+		self.magazines.iter().map(|m| m.cnt).sum()
+	}
+
+	/// Take the last freed chunk of the size class, NULL if the magazine is empty.
+	#[inline]
+	pub fn pop(&mut self, size_class: usize) -> Addr {
+		let magazine = &mut self.magazines[size_class];
+		if magazine.cnt == 0 {
+			NULL
+		} else {
+			magazine.cnt -= 1;
+			magazine.entries[magazine.cnt]
+		}
+	}
+
+	/// Keep a freed chunk in the cache, return false if the magazine is full.
+	#[inline]
+	pub fn push(&mut self, size_class: usize, ptr: Addr) -> bool {
+		let depth = self.depth;
+		let magazine = &mut self.magazines[size_class];
+		if magazine.cnt >= depth {
+			false
+		} else {
+			magazine.entries[magazine.cnt] = ptr;
+			magazine.cnt += 1;
+			true
+		}
+	}
+
+	/// Refill half of the magazine of the size class from the runs. Return the
+	/// number of chunks obtained, 0 on out of memory.
+	///
+	/// @param size_class The size class to refill.
+	/// @param manager The small chunk manager owning the runs.
+	pub fn refill(&mut self, size_class: usize, manager: &mut SmallChunkManager) -> usize {
+		let want = (self.depth + 1) / 2;
+		let magazine = &mut self.magazines[size_class];
+		debug_assert!(magazine.cnt + want <= self.depth);
+
+		let cnt = magazine.cnt;
+		let got = manager.malloc_batch(size_class, &mut magazine.entries[cnt..cnt+want]);
+		magazine.cnt += got;
+		got
+	}
+
+	/// Return the oldest half of the magazine of the size class to the runs.
+	///
+	/// @param size_class The size class to flush.
+	/// @param manager The small chunk manager owning the runs.
+	pub fn flush(&mut self, size_class: usize, manager: &mut SmallChunkManager) {
+		let magazine = &mut self.magazines[size_class];
+		let cnt = (magazine.cnt + 1) / 2;
+		manager.free_batch(&magazine.entries[0..cnt]);
+
+		//move the most recent ones down
+		let remain = magazine.cnt - cnt;
+		magazine.entries.copy_within(cnt..cnt+remain, 0);
+		magazine.cnt = remain;
+	}
+
+	/// Return all the cached chunks to the runs.
+	///
+	/// @param manager The small chunk manager owning the runs.
+	pub fn flush_all(&mut self, manager: &mut SmallChunkManager) {
+		for magazine in self.magazines.iter_mut() {
+			manager.free_batch(&magazine.entries[0..magazine.cnt]);
+			magazine.cnt = 0;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use chunk::small::tcache::*;
+	use chunk::small::manager::SMALL_SIZE_CLASSES;
+	use mmsource::dummy::DummyMMSource;
+	use common::traits::{ChunkManager,MemorySourcePtr};
+
+	#[test]
+	fn push_pop() {
+		let mut tcache = SmallTCache::new(4);
+		assert!(tcache.is_enabled());
+		assert_eq!(tcache.pop(0), NULL);
+		for i in 1..5 {
+			assert!(tcache.push(0, i * 16));
+		}
+		assert_eq!(tcache.push(0, 80), false);
+		assert_eq!(tcache.get_cached(), 4);
+		assert_eq!(tcache.pop(0), 64);
+		assert_eq!(tcache.pop(0), 48);
+		assert_eq!(tcache.pop(1), NULL);
+	}
+
+	#[test]
+	fn disabled() {
+		let mut tcache = SmallTCache::new(0);
+		assert_eq!(tcache.is_enabled(), false);
+		assert_eq!(tcache.push(0, 16), false);
+	}
+
+	#[test]
+	fn refill_flush() {
+		let mmsource = DummyMMSource::new(None);
+		let mut manager = SmallChunkManager::new(true, Some(MemorySourcePtr::new_ref(&mmsource)));
+		let mut tcache = SmallTCache::new(8);
+
+		//refill half
+		assert_eq!(tcache.refill(2, &mut manager), 4);
+		assert_eq!(tcache.get_cached(), 4);
+		let ptr = tcache.pop(2);
+		assert_ne!(ptr, NULL);
+		assert_eq!(manager.get_inner_size(ptr), SMALL_SIZE_CLASSES[2]);
+
+		//fill up and flush the oldest half
+		assert!(tcache.push(2, ptr));
+		assert_eq!(tcache.refill(2, &mut manager), 4);
+		assert_eq!(tcache.get_cached(), 8);
+		let last = tcache.magazines[2].entries[7];
+		tcache.flush(2, &mut manager);
+		assert_eq!(tcache.get_cached(), 4);
+		assert_eq!(tcache.pop(2), last);
+
+		//all
+		tcache.flush_all(&mut manager);
+		assert_eq!(tcache.get_cached(), 0);
+	}
+}
@@ -0,0 +1,547 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+/// This module provide the runtime configuration of the allocator. It starts
+/// from the values of common::consts and can be tuned at init time with the
+/// HPC_ALLOC_CONF environment variable without requiring a rebuild, eg. :
+///
+/// HPC_ALLOC_CONF="mmsrc_max_size=64M,mmsrc_keep_residut=true"
+///
+/// Parsing is done in place without allocating memory as it runs before the
+/// allocator is ready.
+
+//import
+use common::types::{SSize,Size};
+use common::consts::*;
+use common::ops;
+use common::oom;
+use portability::libc;
+use portability::osmem;
+use core::fmt::Write;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr,Ordering};
+
+/// Name of the environment variable to read (NULL terminated for getenv).
+const CONFIG_ENV_NAME: &[u8] = b"HPC_ALLOC_CONF\0";
+
+/// Format of the statistics report to print at exit.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ReportMode {
+	None,
+	Text,
+	Xml,
+	Json,
+}
+
+/// Action to take when the OS cannot provide memory anymore.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum OomPolicy {
+	/// Return NULL with errno set to ENOMEM.
+	Null,
+	/// Print a diagnostic on stderr and abort.
+	Abort,
+	/// Call the handler registered with hpc_alloc_set_oom_handler(), return NULL if none.
+	Handler,
+}
+
+/// Kind of pages used to back the macro blocs.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum HugePages {
+	/// Standard pages only.
+	None,
+	/// Align the macro blocs on HUGE_PAGE_SIZE and ask for transparent huge pages.
+	Thp,
+	/// Use MAP_HUGETLB, fallback on Thp if there is no huge pages available.
+	HugeTlb,
+}
+
+/// Runtime configuration of the allocator.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Config {
+	/// Maximum size of total memory stored into the memory source.
+	pub mmsrc_max_size: Size,
+	/// Maximum size of segments to keep in the memory source.
+	pub mmsrc_threashold: Size,
+	/// Keep non used part of segment when required less (if big enougth).
+	pub mmsrc_keep_residut: bool,
+	/// Milliseconds before releasing the cached macro blocs with MADV_FREE, 0 to never do it.
+	pub mmsrc_dirty_decay: Size,
+	/// Milliseconds before returning the cached macro blocs to the OS, 0 to keep them.
+	pub mmsrc_muzzy_decay: Size,
+	/// Apply the decay from a background thread and not only on memory source calls.
+	pub mmsrc_purge_thread: bool,
+	/// Kind of pages used to back the macro blocs.
+	pub mmsrc_huge_pages: HugePages,
+	/// Size from which we use the huge chunk manager.
+	pub huge_alloc_threashold: Size,
+	/// Minimal size to generate a realloc in huge and medium chunk manager.
+	pub realloc_threashold: SSize,
+	/// Print the statistics report on stderr at exit.
+	pub report_at_exit: ReportMode,
+	/// What to do when running out of memory.
+	pub oom_policy: OomPolicy,
+	/// Small chunks kept per size class in the thread caches, 0 to disable them.
+	pub tcache_depth: Size,
+	/// Size of empty small runs kept in memory per container, 0 to only release them on trim.
+	pub small_purge_threashold: Size,
+}
+
+/// Errors which can be reported while parsing a configuration string.
+#[derive(Debug,PartialEq)]
+pub enum ConfigError<'a> {
+	/// The key is not a known configuration entry.
+	UnknownKey(&'a [u8]),
+	/// The entry has no '=' to provide the value.
+	MissingValue(&'a [u8]),
+	/// The value cannot be parsed for the given key.
+	InvalidValue(&'a [u8], &'a [u8]),
+	/// The values are fine one by one but not together.
+	Inconsistent(&'static str),
+	/// The value of the key is over the given limit.
+	TooLarge(&'static str, Size),
+}
+
+//mallopt() parameters as defined by glibc
+const M_TRIM_THRESHOLD: i32 = -1;
+const M_TOP_PAD: i32 = -2;
+const M_MMAP_THRESHOLD: i32 = -3;
+const M_MMAP_MAX: i32 = -4;
+const M_CHECK_ACTION: i32 = -5;
+const M_ARENA_TEST: i32 = -7;
+const M_ARENA_MAX: i32 = -8;
+
+/// Default configuration built from common::consts.
+const DEFAULT_CONFIG: Config = Config {
+	mmsrc_max_size: MMSRC_MAX_SIZE,
+	mmsrc_threashold: MMSRC_THREASHOLD,
+	mmsrc_keep_residut: MMSRC_KEEP_RESIDUT,
+	mmsrc_dirty_decay: MMSRC_DIRTY_DECAY,
+	mmsrc_muzzy_decay: MMSRC_MUZZY_DECAY,
+	mmsrc_purge_thread: false,
+	mmsrc_huge_pages: HugePages::None,
+	huge_alloc_threashold: HUGE_ALLOC_THREASHOLD,
+	realloc_threashold: REALLOC_THREASHOLD,
+	report_at_exit: ReportMode::None,
+	oom_policy: OomPolicy::Null,
+	tcache_depth: TCACHE_DEPTH,
+	small_purge_threashold: SMALL_PURGE_THREASHOLD,
+};
+
+/// Configuration loaded by init_from_env() before any other thread is started.
+static mut GBL_INIT_CONFIG: Config = DEFAULT_CONFIG;
+/// Configuration published at runtime, NULL to use GBL_INIT_CONFIG. Other threads
+/// can hold references to the previous one so it is never modified nor freed.
+static GBL_CONFIG: AtomicPtr<Config> = AtomicPtr::new(0 as * mut Config);
+
+impl Config {
+	/// Build a configuration with the default values from common::consts.
+	pub fn new() -> Self {
+		DEFAULT_CONFIG
+	}
+
+	/// Apply a configuration string made of `key=value` entries separated by
+	/// commas. The current config is left untouched on error.
+	///
+	/// @param conf The configuration string to parse.
+	pub fn parse<'a>(&mut self, conf: &'a [u8]) -> Result<(), ConfigError<'a>> {
+		//work on a copy to not apply half of the config
+		let mut res = *self;
+
+		//loop on entries
+		for entry in conf.split(|c| *c == b',') {
+			//ignore empty entries
+			let entry = trim(entry);
+			if entry.is_empty() {
+				continue;
+			}
+
+			//split key & value
+			let pos = match entry.iter().position(|c| *c == b'=') {
+				Some(x) => x,
+				None => return Err(ConfigError::MissingValue(entry)),
+			};
+			let key = trim(&entry[..pos]);
+			let value = trim(&entry[pos+1..]);
+
+			//apply
+			res.set(key, value)?;
+		}
+
+		//check & commit
+		res.validate()?;
+		*self = res;
+		Ok(())
+	}
+
+	/// Set one entry of the configuration.
+	fn set<'a>(&mut self, key: &'a [u8], value: &'a [u8]) -> Result<(), ConfigError<'a>> {
+		let invalid = ConfigError::InvalidValue(key, value);
+		match key {
+			b"mmsrc_max_size" => self.mmsrc_max_size = parse_size(value).ok_or(invalid)?,
+			b"mmsrc_threashold" => self.mmsrc_threashold = parse_size(value).ok_or(invalid)?,
+			b"mmsrc_keep_residut" => self.mmsrc_keep_residut = parse_bool(value).ok_or(invalid)?,
+			b"mmsrc_dirty_decay" => self.mmsrc_dirty_decay = parse_size(value).ok_or(invalid)?,
+			b"mmsrc_muzzy_decay" => self.mmsrc_muzzy_decay = parse_size(value).ok_or(invalid)?,
+			b"mmsrc_purge_thread" => self.mmsrc_purge_thread = parse_bool(value).ok_or(invalid)?,
+			b"mmsrc_huge_pages" => self.mmsrc_huge_pages = parse_huge_pages(value).ok_or(invalid)?,
+			b"huge_alloc_threashold" => self.huge_alloc_threashold = parse_size(value).ok_or(invalid)?,
+			b"realloc_threashold" => {
+				let size = parse_size(value).ok_or(ConfigError::InvalidValue(key, value))?;
+				if size > SSize::max_value() as Size {
+					return Err(invalid);
+				}
+				self.realloc_threashold = size as SSize;
+			},
+			b"report_at_exit" => self.report_at_exit = parse_report_mode(value).ok_or(invalid)?,
+			b"oom_policy" => self.oom_policy = parse_oom_policy(value).ok_or(invalid)?,
+			b"tcache_depth" => self.tcache_depth = parse_size(value).ok_or(invalid)?,
+			b"small_purge_threashold" => self.small_purge_threashold = parse_size(value).ok_or(invalid)?,
+			_ => return Err(ConfigError::UnknownKey(key)),
+		}
+		Ok(())
+	}
+
+	/// Apply a mallopt() parameter, return false if not supported or invalid.
+	///
+	/// - M_MMAP_THRESHOLD set the size from which we use the huge chunk manager,
+	///   up to HUGE_ALLOC_MAX_THREASHOLD.
+	/// - M_TRIM_THRESHOLD set the maximum memory kept in the memory source cache.
+	/// - M_TOP_PAD, M_CHECK_ACTION, M_ARENA_TEST and M_ARENA_MAX are accepted
+	///   but have no effect as there is no main heap and one arena per thread.
+	///
+	/// @param param The M_* parameter.
+	/// @param value The value to apply.
+	pub fn apply_mallopt(&mut self, param: i32, value: i32) -> bool {
+		//work on a copy to not apply invalid values
+		let mut res = *self;
+		match param {
+			M_MMAP_THRESHOLD => {
+				if value < 0 {
+					return false;
+				}
+				res.huge_alloc_threashold = value as Size;
+			},
+			M_TRIM_THRESHOLD => {
+				if value < 0 {
+					return false;
+				}
+				res.mmsrc_max_size = value as Size;
+				if res.mmsrc_threashold > res.mmsrc_max_size {
+					res.mmsrc_threashold = res.mmsrc_max_size;
+				}
+			},
+			M_TOP_PAD | M_CHECK_ACTION | M_ARENA_TEST | M_ARENA_MAX => return true,
+			M_MMAP_MAX => return false,
+			_ => return false,
+		}
+
+		//check & commit
+		if res.validate().is_err() {
+			return false;
+		}
+		*self = res;
+		true
+	}
+
+	/// Check the consistency of the values.
+	pub fn validate(&self) -> Result<(), ConfigError<'static>> {
+		if self.mmsrc_threashold > self.mmsrc_max_size {
+			Err(ConfigError::Inconsistent("mmsrc_threashold cannot be larger than mmsrc_max_size"))
+		} else if self.huge_alloc_threashold < SMALL_PAGE_SIZE {
+			Err(ConfigError::Inconsistent("huge_alloc_threashold must be at least a page"))
+		} else if self.huge_alloc_threashold > HUGE_ALLOC_MAX_THREASHOLD {
+			Err(ConfigError::TooLarge("huge_alloc_threashold", HUGE_ALLOC_MAX_THREASHOLD))
+		} else if self.tcache_depth > TCACHE_MAX_DEPTH {
+			Err(ConfigError::TooLarge("tcache_depth", TCACHE_MAX_DEPTH))
+		} else {
+			Ok(())
+		}
+	}
+}
+
+/// Remove spaces at begin and end of the given string.
+fn trim(value: &[u8]) -> &[u8] {
+	let start = value.iter().position(|c| *c != b' ').unwrap_or(value.len());
+	let end = value.iter().rposition(|c| *c != b' ').map_or(start, |x| x + 1);
+	&value[start..end]
+}
+
+/// Parse a size with an optional K, M or G unit.
+fn parse_size(value: &[u8]) -> Option<Size> {
+	//extract unit
+	let (digits, unit): (&[u8], Size) = match value.last() {
+		Some(b'K') | Some(b'k') => (&value[..value.len()-1], 1024),
+		Some(b'M') | Some(b'm') => (&value[..value.len()-1], 1024*1024),
+		Some(b'G') | Some(b'g') => (&value[..value.len()-1], 1024*1024*1024),
+		_ => (value, 1),
+	};
+
+	//trivial
+	if digits.is_empty() {
+		return None;
+	}
+
+	//convert
+	let mut res: Size = 0;
+	for c in digits {
+		if *c < b'0' || *c > b'9' {
+			return None;
+		}
+		res = res.checked_mul(10)?.checked_add((*c - b'0') as Size)?;
+	}
+
+	res.checked_mul(unit)
+}
+
+/// Parse a boolean value.
+fn parse_bool(value: &[u8]) -> Option<bool> {
+	match value {
+		b"true" | b"1" | b"yes" => Some(true),
+		b"false" | b"0" | b"no" => Some(false),
+		_ => None,
+	}
+}
+
+/// Parse the format of the exit report.
+fn parse_report_mode(value: &[u8]) -> Option<ReportMode> {
+	match value {
+		b"none" | b"false" | b"0" => Some(ReportMode::None),
+		b"text" | b"true" | b"1" => Some(ReportMode::Text),
+		b"xml" => Some(ReportMode::Xml),
+		b"json" => Some(ReportMode::Json),
+		_ => None,
+	}
+}
+
+/// Parse the out of memory policy.
+fn parse_oom_policy(value: &[u8]) -> Option<OomPolicy> {
+	match value {
+		b"null" => Some(OomPolicy::Null),
+		b"abort" => Some(OomPolicy::Abort),
+		b"handler" => Some(OomPolicy::Handler),
+		_ => None,
+	}
+}
+
+/// Parse the kind of pages for the macro blocs (none, thp or hugetlb).
+fn parse_huge_pages(value: &[u8]) -> Option<HugePages> {
+	match value {
+		b"none" => Some(HugePages::None),
+		b"thp" => Some(HugePages::Thp),
+		b"hugetlb" => Some(HugePages::HugeTlb),
+		_ => None,
+	}
+}
+
+/// Return the current configuration.
+#[inline]
+pub fn get() -> &'static Config {
+	let cur = GBL_CONFIG.load(Ordering::Acquire);
+	if cur.is_null() {
+		unsafe{&GBL_INIT_CONFIG}
+	} else {
+		unsafe{&*cur}
+	}
+}
+
+/// Replace the global configuration, only components built after this
+/// call will use it unless they are updated by the caller.
+pub fn set(config: Config) {
+	update(|cfg| {*cfg = config; true});
+}
+
+/// Apply the given function to a copy of the current configuration and publish
+/// it if the function returns true. The copy is placed in its own pages which
+/// are never released as other threads can still read the previous one, so it
+/// is only for rare updates like mallopt(). Return the configuration in use
+/// after the call or None if the function refused the change. The publication
+/// has release ordering so the stores done before the call are visible to the
+/// readers of the new configuration.
+///
+/// @param func Function modifying the configuration.
+pub fn update<F: Fn(&mut Config) -> bool>(func: F) -> Option<&'static Config> {
+	loop {
+		//build the new one
+		let cur = GBL_CONFIG.load(Ordering::Acquire);
+		let old = get();
+		let mut config = *old;
+		if !func(&mut config) {
+			return None;
+		} else if config == *old {
+			return Some(old);
+		}
+
+		//publish, retry if someone else did it before us
+		let size = ops::up_to_power_of_2(mem::size_of::<Config>(), SMALL_PAGE_SIZE);
+		let addr = osmem::mmap(0, size);
+		if addr == NULL {
+			oom::fatal("the configuration");
+		}
+		let new = addr as * mut Config;
+		unsafe{ptr::write(new, config)};
+		if GBL_CONFIG.compare_exchange(cur, new, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+			return Some(unsafe{&*new});
+		}
+		osmem::munmap(addr, size);
+	}
+}
+
+/// Load the configuration from the environment, to be called by init()
+/// before building any component. Abort with an error message if the
+/// configuration string is invalid.
+pub fn init_from_env() {
+	//nothing to do
+	let conf = match libc::getenv(CONFIG_ENV_NAME) {
+		Some(x) => x,
+		None => return,
+	};
+
+	//parse
+	let mut config = *get();
+	match config.parse(conf) {
+		Ok(()) => unsafe{GBL_INIT_CONFIG = config},
+		Err(err) => {
+			report_error(&err);
+			libc::abort();
+		},
+	}
+}
+
+/// Print the error in a human readable way on stderr.
+fn report_error(err: &ConfigError) {
+	libc::write_stderr(b"HPC_ALLOC_CONF: ");
+	match *err {
+		ConfigError::UnknownKey(key) => {
+			libc::write_stderr(b"unknown key '");
+			libc::write_stderr(key);
+			libc::write_stderr(b"', valid keys are mmsrc_max_size, mmsrc_threashold, mmsrc_keep_residut, mmsrc_dirty_decay, mmsrc_muzzy_decay, mmsrc_purge_thread, mmsrc_huge_pages, huge_alloc_threashold, realloc_threashold, report_at_exit, oom_policy, tcache_depth, small_purge_threashold");
+		},
+		ConfigError::MissingValue(entry) => {
+			libc::write_stderr(b"missing value in '");
+			libc::write_stderr(entry);
+			libc::write_stderr(b"', expect key=value");
+		},
+		ConfigError::InvalidValue(key, value) => {
+			libc::write_stderr(b"invalid value '");
+			libc::write_stderr(value);
+			libc::write_stderr(b"' for key '");
+			libc::write_stderr(key);
+			libc::write_stderr(b"'");
+		},
+		ConfigError::Inconsistent(msg) => libc::write_stderr(msg.as_bytes()),
+		ConfigError::TooLarge(key, max) => {
+			let _ = write!(libc::FdWriter::new(2), "{} cannot be larger than {}", key, max);
+		},
+	}
+	libc::write_stderr(b"\n");
+}
+
+#[cfg(test)]
+mod tests
+{
+	use common::config::*;
+
+	#[test]
+	fn defaults() {
+		let config = Config::new();
+		assert_eq!(config.mmsrc_max_size, MMSRC_MAX_SIZE);
+		assert_eq!(config.huge_alloc_threashold, HUGE_ALLOC_THREASHOLD);
+		assert_eq!(config.validate(), Ok(()));
+	}
+
+	#[test]
+	fn parse() {
+		let mut config = Config::new();
+		assert_eq!(config.parse(b" mmsrc_max_size=64M, mmsrc_threashold = 4096,mmsrc_keep_residut=true,,realloc_threashold=128"), Ok(()));
+		assert_eq!(config.mmsrc_max_size, 64*1024*1024);
+		assert_eq!(config.mmsrc_threashold, 4096);
+		assert_eq!(config.mmsrc_keep_residut, true);
+		assert_eq!(config.mmsrc_dirty_decay, MMSRC_DIRTY_DECAY);
+		assert_eq!(config.parse(b"mmsrc_dirty_decay=500,mmsrc_muzzy_decay=0,mmsrc_purge_thread=true"), Ok(()));
+		assert_eq!(config.mmsrc_dirty_decay, 500);
+		assert_eq!(config.mmsrc_muzzy_decay, 0);
+		assert_eq!(config.mmsrc_purge_thread, true);
+		assert_eq!(config.mmsrc_huge_pages, HugePages::None);
+		assert_eq!(config.parse(b"mmsrc_huge_pages=hugetlb"), Ok(()));
+		assert_eq!(config.mmsrc_huge_pages, HugePages::HugeTlb);
+		assert_eq!(config.realloc_threashold, 128);
+		assert_eq!(config.report_at_exit, ReportMode::None);
+		assert_eq!(config.parse(b"report_at_exit=xml"), Ok(()));
+		assert_eq!(config.report_at_exit, ReportMode::Xml);
+		assert_eq!(config.parse(b"report_at_exit=json"), Ok(()));
+		assert_eq!(config.report_at_exit, ReportMode::Json);
+		assert_eq!(config.oom_policy, OomPolicy::Null);
+		assert_eq!(config.parse(b"oom_policy=abort"), Ok(()));
+		assert_eq!(config.oom_policy, OomPolicy::Abort);
+		assert_eq!(config.tcache_depth, TCACHE_DEPTH);
+		assert_eq!(config.parse(b"tcache_depth=0"), Ok(()));
+		assert_eq!(config.tcache_depth, 0);
+		assert_eq!(config.parse(b"small_purge_threashold=64K"), Ok(()));
+		assert_eq!(config.small_purge_threashold, 64*1024);
+		assert_eq!(config.huge_alloc_threashold, HUGE_ALLOC_THREASHOLD);
+	}
+
+	#[test]
+	fn parse_errors() {
+		let mut config = Config::new();
+		assert_eq!(config.parse(b"mmsrc_max_size=64M,unknown=2"), Err(ConfigError::UnknownKey(b"unknown")));
+		assert_eq!(config.parse(b"mmsrc_max_size"), Err(ConfigError::MissingValue(b"mmsrc_max_size")));
+		assert_eq!(config.parse(b"mmsrc_max_size=12Z"), Err(ConfigError::InvalidValue(b"mmsrc_max_size", b"12Z")));
+		assert_eq!(config.parse(b"mmsrc_keep_residut=maybe"), Err(ConfigError::InvalidValue(b"mmsrc_keep_residut", b"maybe")));
+		assert_eq!(config.parse(b"mmsrc_max_size=99999999999999999999"), Err(ConfigError::InvalidValue(b"mmsrc_max_size", b"99999999999999999999")));
+		assert!(config.parse(b"mmsrc_max_size=1M,mmsrc_threashold=2M").is_err());
+		assert!(config.parse(b"huge_alloc_threashold=16").is_err());
+		assert_eq!(config.parse(b"report_at_exit=yaml"), Err(ConfigError::InvalidValue(b"report_at_exit", b"yaml")));
+		assert_eq!(config.parse(b"oom_policy=retry"), Err(ConfigError::InvalidValue(b"oom_policy", b"retry")));
+		assert_eq!(config.parse(b"mmsrc_huge_pages=1G"), Err(ConfigError::InvalidValue(b"mmsrc_huge_pages", b"1G")));
+		assert_eq!(config.parse(b"tcache_depth=65"), Err(ConfigError::TooLarge("tcache_depth", TCACHE_MAX_DEPTH)));
+		assert_eq!(config.parse(b"huge_alloc_threashold=33M"), Err(ConfigError::TooLarge("huge_alloc_threashold", HUGE_ALLOC_MAX_THREASHOLD)));
+
+		//nothing applied
+		assert_eq!(config, Config::new());
+	}
+
+	#[test]
+	fn apply_mallopt() {
+		let mut config = Config::new();
+		assert!(config.apply_mallopt(M_MMAP_THRESHOLD, 256*1024));
+		assert_eq!(config.huge_alloc_threashold, 256*1024);
+		assert!(config.apply_mallopt(M_TRIM_THRESHOLD, 1024*1024));
+		assert_eq!(config.mmsrc_max_size, 1024*1024);
+		assert_eq!(config.mmsrc_threashold, 1024*1024);
+		assert!(config.apply_mallopt(M_ARENA_MAX, 4));
+
+		//invalid
+		let before = config;
+		assert_eq!(config.apply_mallopt(M_MMAP_THRESHOLD, -1), false);
+		assert_eq!(config.apply_mallopt(M_MMAP_THRESHOLD, 16), false);
+		assert_eq!(config.apply_mallopt(M_MMAP_THRESHOLD, (HUGE_ALLOC_MAX_THREASHOLD + 1) as i32), false);
+		assert_eq!(config.apply_mallopt(M_MMAP_MAX, 0), false);
+		assert_eq!(config.apply_mallopt(42, 0), false);
+		assert_eq!(config, before);
+	}
+
+	#[test]
+	fn update() {
+		//refused or without change, nothing is published
+		let before = get() as * const Config;
+		assert!(super::update(|_| false).is_none());
+		assert_eq!(super::update(|_| true).map(|cfg| cfg as * const Config), Some(before));
+		assert_eq!(get() as * const Config, before);
+	}
+
+	#[test]
+	fn parse_size() {
+		assert_eq!(super::parse_size(b"10"), Some(10));
+		assert_eq!(super::parse_size(b"2k"), Some(2048));
+		assert_eq!(super::parse_size(b"1G"), Some(1024*1024*1024));
+		assert_eq!(super::parse_size(b"M"), None);
+		assert_eq!(super::parse_size(b""), None);
+	}
+}
@@ -9,14 +9,22 @@
 /// This module define all the basic constants to be used by
 /// the allocator
 
-use core::mem;
 use common::types::{SSize,Size,Addr};
 
 //global values
 /// Define basic alignement handled by standared chunk manager for any size larger than this one.
-pub const BASIC_ALIGN: Size = mem::size_of::<usize>();
+/// It is the alignement guaranteed by malloc() so it follow the ABI of the target.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub const BASIC_ALIGN: Size = 16;
+/// Define basic alignement handled by standared chunk manager for any size larger than this one.
+/// It is the alignement guaranteed by malloc() so it follow the ABI of the target.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub const BASIC_ALIGN: Size = ::core::mem::size_of::<usize>();
 /// Define the standard page size which is 4k on most systems.
 pub const SMALL_PAGE_SIZE: Size = 4096;
+/// Define the size of the huge pages used with THP and MAP_HUGETLB, it is the
+/// default one on x86_64 and aarch64 with 4k pages.
+pub const HUGE_PAGE_SIZE: Size = 2*1024*1024;
 /// To be stored into magick field of medium chunks
 pub const MAGICK_VALUE: u32 = 42;
 /// Max number of free list to handle.
@@ -29,12 +37,17 @@ pub const REALLOC_THREASHOLD: SSize = 64;
 /// Define the basic macro bloc size, used to split the region registry. This is
 /// The minimum size we can allocate inside the memory source.
 pub const MACRO_BLOC_SIZE: Size = 2*1024*1024;
-/// Minimal inner size for medium chunks.
+/// Minimal inner size for medium chunks, it is also the rounding of their
+/// sizes so it must be a multiple of BASIC_ALIGN.
 pub const MEDIUM_MIN_INNER_SIZE: Size = 16;
 //#define ADDR_NULL 0
 //#define ALLOC_DO_WARNING true
 //Define when to use the huge allocator (roughtly direct mmap via memory source)
 pub const HUGE_ALLOC_THREASHOLD: Size = MACRO_BLOC_SIZE / 2;
+/// Largest value accepted for the huge allocator threashold, same as the glibc
+/// maximum of M_MMAP_THRESHOLD on 64 bits so larger chunks keep being cached
+/// and returned to the OS by the huge allocator.
+pub const HUGE_ALLOC_MAX_THREASHOLD: Size = 32*1024*1024;
 pub const NULL: Addr = 0;
 
 //about region mecanism
@@ -62,6 +75,30 @@ pub const MMSRC_MAX_SIZE: Size = 16*1024*1024;
 pub const MMSRC_THREASHOLD: Size = 8*1024*1204;
 ///Keep non used part of segment when required less (if big enougth)
 pub const MMSRC_KEEP_RESIDUT: bool = false;
+///Define after how many milliseconds in the cache a macro bloc is released with MADV_FREE, 0 to never do it.
+pub const MMSRC_DIRTY_DECAY: Size = 10000;
+///Define after how many milliseconds in the cache a macro bloc is returned to the OS, 0 to keep it.
+pub const MMSRC_MUZZY_DECAY: Size = 30000;
+///Minimal period in milliseconds between two decay passes on a memory source.
+pub const MMSRC_DECAY_PERIOD: Size = 100;
+///Maximum address range a reserved memory source can handle, it defines the size of its bloc bitmap.
+pub const MMSRC_RESERVE_MAX_SIZE: Size = 64*1024*1024*1024;
+
+//about thread cache
+/// Maximum number of small chunks each thread keep per size class in its cache.
+pub const TCACHE_MAX_DEPTH: Size = 64;
+/// Default number of small chunks kept per size class, 0 disable the cache.
+pub const TCACHE_DEPTH: Size = 32;
+
+//about small chunks
+/// Size of the empty runs a small chunk container keep in memory before giving
+/// them back to the OS.
+pub const SMALL_PURGE_THREASHOLD: Size = MACRO_BLOC_SIZE / 2;
+
+//about numa
+/// Maximum number of NUMA nodes for which we build a dedicated memory source.
+/// Threads bound to a node over this limit use the shared one.
+pub const NUMA_MAX_NODES: Size = 64;
 
 ///Magick number used by padded chunks.
 pub const PADDED_CHUNK_MAGICK: u8 = 0x42;
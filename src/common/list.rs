@@ -209,6 +209,15 @@ impl <T> Clone for List<T>
 	}
 }
 
+/// To be able to init arrays of lists by default.
+impl <T> Default for List<T>
+	where T: Listable<T>
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 /// Implement the list operations.
 impl <T> List<T> 
 	where T: Listable<T>
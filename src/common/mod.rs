@@ -11,8 +11,11 @@
 //import
 pub mod types;
 pub mod consts;
+pub mod config;
 pub mod ops;
 pub mod traits;
 pub mod shared;
 pub mod list;
-pub mod mpscf_queue;
\ No newline at end of file
+pub mod mpscf_queue;
+pub mod stats;
+pub mod oom;
\ No newline at end of file
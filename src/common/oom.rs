@@ -0,0 +1,105 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+/// Apply the out of memory policy selected by the oom_policy entry of the
+/// runtime configuration when an allocation cannot be served. The handler
+/// follows the C++ new_handler semantic, it can release some memory and ask
+/// to retry the allocation by returning a non zero value.
+
+//import
+use core::fmt::Write;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use common::types::Size;
+use common::config::{self,OomPolicy};
+use portability::libc::{self,FdWriter};
+
+/// Signature of the handler to be called on out of memory. It get the
+/// requested size and return non zero to retry the allocation.
+pub type OomHandler = extern "C" fn(size: Size) -> i32;
+
+/// Registered handler, 0 if none.
+static OOM_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Register the handler to call on out of memory and return the previous one.
+///
+/// @param handler The new handler, None to unregister.
+pub fn set_handler(handler: Option<OomHandler>) -> Option<OomHandler> {
+	let value = match handler {
+		Some(x) => x as usize,
+		None => 0,
+	};
+	to_handler(OOM_HANDLER.swap(value, Ordering::AcqRel))
+}
+
+/// Return the current handler.
+pub fn get_handler() -> Option<OomHandler> {
+	to_handler(OOM_HANDLER.load(Ordering::Acquire))
+}
+
+/// Convert the stored value to a function pointer.
+fn to_handler(value: usize) -> Option<OomHandler> {
+	if value == 0 {
+		None
+	} else {
+		Some(unsafe{mem::transmute::<usize, OomHandler>(value)})
+	}
+}
+
+/// To be called when an allocation failed, apply the configured policy.
+/// Return true if the caller has to retry the allocation.
+///
+/// @param size The size which was requested.
+pub fn on_failure(size: Size) -> bool {
+	match config::get().oom_policy {
+		OomPolicy::Null => false,
+		OomPolicy::Abort => {
+			let _ = write!(FdWriter::new(2), "hpc_allocator: out of memory, failed to allocate {} bytes\n", size);
+			libc::abort();
+		},
+		OomPolicy::Handler => {
+			match get_handler() {
+				Some(handler) => handler(size) != 0,
+				None => false,
+			}
+		},
+	}
+}
+
+/// Abort with a diagnostic when the internal structures of the allocator
+/// cannot be allocated, there is no way to recover from this.
+///
+/// @param what Name of the structure we tried to allocate.
+pub fn fatal(what: &str) -> ! {
+	let _ = write!(FdWriter::new(2), "hpc_allocator: out of memory, failed to allocate {}\n", what);
+	libc::abort();
+}
+
+#[cfg(test)]
+mod tests
+{
+	use common::oom::*;
+
+	extern "C" fn retry(_size: Size) -> i32 {
+		1
+	}
+
+	#[test]
+	fn handler() {
+		let old = set_handler(Some(retry));
+		assert!(get_handler().is_some());
+		assert_eq!(get_handler().unwrap()(16), 1);
+		set_handler(old);
+	}
+
+	#[test]
+	fn null_policy() {
+		//default policy
+		assert_eq!(on_failure(16), false);
+	}
+}
@@ -31,6 +31,12 @@ pub fn up_to_power_of_2(size:Size,align:Size) -> Size {
 	ret
 }
 
+/// Check if the given value is a power of 2, 0 is not considered as one.
+#[inline]
+pub fn is_power_of_2(value:Size) -> bool {
+	value != 0 && value & (value-1) == 0
+}
+
 /// Helper function to be used by some functions, it convert an optional reference to pointer.
 /// If Option is None, then the pointer is set to NULL.
 #[inline]
@@ -80,6 +86,16 @@ mod tests
 		assert_eq!(ops::ceil_to_power_of_2(10,8),8);
 	}
 
+	#[test]
+	fn is_power_of_2() {
+		assert_eq!(ops::is_power_of_2(0),false);
+		assert_eq!(ops::is_power_of_2(1),true);
+		assert_eq!(ops::is_power_of_2(2),true);
+		assert_eq!(ops::is_power_of_2(3),false);
+		assert_eq!(ops::is_power_of_2(24),false);
+		assert_eq!(ops::is_power_of_2(4096),true);
+	}
+
 	#[test]
 	fn up_to_power_of_2() {
 		assert_eq!(ops::up_to_power_of_2(0,1),0);
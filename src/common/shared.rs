@@ -160,7 +160,9 @@ impl <T: ?Sized> SharedPtrBox<T> {
 
 impl <T: ?Sized>  PartialEq for SharedPtrBox<T> {
 	fn eq(&self, other: &Self) -> bool {
-		self.data == other.data
+		//compare only the addresses, the vtables of trait objects are not
+		//guaranteed to be uniq so the same object can get different ones
+		self.data as * const u8 == other.data as * const u8
 	}
 }
 
@@ -0,0 +1,381 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+/// This module provide the statistic counters of the allocator. Each local
+/// allocator and memory source keep its own lock free counters which are
+/// aggregated on demand into a StatsReport by the NumaAllocator.
+
+//import
+use common::types::Size;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use chunk::small::manager::NB_SIZE_CLASS;
+use common::consts::NB_FREE_LIST;
+
+/// Version of the StatsReport layout, increased each time fields are appended.
+pub const STATS_VERSION: u32 = 2;
+
+/// Counters attached to each local allocator. They are only updated by the
+/// thread owning the allocator so they use a load and a store instead of
+/// fetch_add() to avoid the locked instructions on the hot path. They stay
+/// atomic to be read by the threads building the reports.
+pub struct AllocStats {
+	allocs: AtomicUsize,
+	frees: AtomicUsize,
+	remote_frees: AtomicUsize,
+	reallocs: AtomicUsize,
+	requested_bytes: AtomicUsize,
+	inner_bytes: AtomicUsize,
+	total_bytes: AtomicUsize,
+	freed_inner_bytes: AtomicUsize,
+	freed_total_bytes: AtomicUsize,
+	tcache_hits: AtomicUsize,
+	tcache_refills: AtomicUsize,
+	tcache_flushes: AtomicUsize,
+}
+
+/// Counters of the OS calls made by a memory source.
+pub struct MMSourceStats {
+	mmap_calls: AtomicUsize,
+	munmap_calls: AtomicUsize,
+	mremap_calls: AtomicUsize,
+}
+
+/// Aggregated statistics exposed to C. Fields can only be appended to keep
+/// compatibility, the caller give the size of the struct it knows in `size`
+/// and we fill only this part.
+///
+/// All the bytes and calls counters are cumulative since startup, except
+/// the ones ending with `in_use` and `mmsrc_cached_bytes`.
+#[repr(C)]
+#[derive(Copy,Clone,Debug,Default)]
+pub struct StatsReport {
+	/// Version of the layout filled by the allocator (STATS_VERSION).
+	pub version: u32,
+	/// Size of the struct as known by the caller.
+	pub size: u32,
+	/// Number of local allocators (live and parked threads).
+	pub threads: u64,
+	pub allocs: u64,
+	pub frees: u64,
+	/// Frees pushed to the remote free queue of another thread.
+	pub remote_frees: u64,
+	pub reallocs: u64,
+	/// Bytes asked by the user.
+	pub requested_bytes: u64,
+	/// Bytes usable by the user (inner size of chunks).
+	pub inner_bytes: u64,
+	/// Bytes consumed including headers and padding.
+	pub total_bytes: u64,
+	pub inner_bytes_in_use: u64,
+	pub total_bytes_in_use: u64,
+	/// Number of small runs currently split for a size class.
+	pub small_runs: u64,
+	/// Number of chunks which can be allocated in those runs.
+	pub small_chunks: u64,
+	/// Number of chunks currently allocated in those runs.
+	pub small_chunks_used: u64,
+	pub mmap_calls: u64,
+	pub munmap_calls: u64,
+	pub mremap_calls: u64,
+	/// Bytes currently kept in the memory source caches.
+	pub mmsrc_cached_bytes: u64,
+	/// Small allocations served by the thread caches without touching the runs.
+	pub tcache_hits: u64,
+	/// Batches of chunks moved from the runs to the thread caches.
+	pub tcache_refills: u64,
+	/// Batches of chunks moved back from the thread caches to the runs.
+	pub tcache_flushes: u64,
+}
+
+/// Usage of the runs of one small size class.
+#[derive(Copy,Clone,Debug,Default)]
+pub struct SmallClassReport {
+	pub size: u64,
+	pub runs: u64,
+	pub chunks: u64,
+	pub chunks_used: u64,
+}
+
+/// Occupancy of one medium free list.
+#[derive(Copy,Clone,Debug,Default)]
+pub struct MediumListReport {
+	/// Upper bound of the chunk sizes stored in the list.
+	pub max_size: u64,
+	pub chunks: u64,
+	pub bytes: u64,
+}
+
+/// Detailed report with per size class breakdown, used to print human
+/// readable or structured dumps.
+#[derive(Copy,Clone)]
+pub struct DetailedReport {
+	pub summary: StatsReport,
+	pub small: [SmallClassReport; NB_SIZE_CLASS],
+	pub medium: [MediumListReport; NB_FREE_LIST],
+	pub huge_segments: u64,
+	pub huge_bytes: u64,
+	pub mmsrc_blocs: u64,
+}
+
+impl AllocStats {
+	pub fn new() -> Self {
+		Self {
+			allocs: AtomicUsize::new(0),
+			frees: AtomicUsize::new(0),
+			remote_frees: AtomicUsize::new(0),
+			reallocs: AtomicUsize::new(0),
+			requested_bytes: AtomicUsize::new(0),
+			inner_bytes: AtomicUsize::new(0),
+			total_bytes: AtomicUsize::new(0),
+			freed_inner_bytes: AtomicUsize::new(0),
+			freed_total_bytes: AtomicUsize::new(0),
+			tcache_hits: AtomicUsize::new(0),
+			tcache_refills: AtomicUsize::new(0),
+			tcache_flushes: AtomicUsize::new(0),
+		}
+	}
+
+	/// Increment a counter, only the owner thread write them.
+	#[inline]
+	fn add(counter: &AtomicUsize, value: Size) {
+		counter.store(counter.load(Ordering::Relaxed).wrapping_add(value), Ordering::Relaxed);
+	}
+
+	/// Account a new chunk.
+	#[inline]
+	pub fn on_alloc(&self, requested: Size, inner: Size, total: Size) {
+		Self::add(&self.allocs, 1);
+		Self::add(&self.requested_bytes, requested);
+		Self::add(&self.inner_bytes, inner);
+		Self::add(&self.total_bytes, total);
+	}
+
+	/// Account a chunk returned to its chunk manager.
+	#[inline]
+	pub fn on_free(&self, inner: Size, total: Size) {
+		Self::add(&self.frees, 1);
+		Self::add(&self.freed_inner_bytes, inner);
+		Self::add(&self.freed_total_bytes, total);
+	}
+
+	/// Account a chunk resized in place by its chunk manager.
+	#[inline]
+	pub fn on_realloc(&self, requested: Size, old_inner: Size, old_total: Size, inner: Size, total: Size) {
+		Self::add(&self.reallocs, 1);
+		Self::add(&self.requested_bytes, requested);
+		Self::add(&self.inner_bytes, inner);
+		Self::add(&self.total_bytes, total);
+		Self::add(&self.freed_inner_bytes, old_inner);
+		Self::add(&self.freed_total_bytes, old_total);
+	}
+
+	/// Account a free pushed to the remote free queue of another thread.
+	#[inline]
+	pub fn on_remote_free(&self) {
+		Self::add(&self.remote_frees, 1);
+	}
+
+	/// Account a small allocation served by the thread cache.
+	#[inline]
+	pub fn on_tcache_hit(&self) {
+		Self::add(&self.tcache_hits, 1);
+	}
+
+	/// Account a batch of chunks moved from the runs to the thread cache.
+	#[inline]
+	pub fn on_tcache_refill(&self) {
+		Self::add(&self.tcache_refills, 1);
+	}
+
+	/// Account a batch of chunks moved back from the thread cache to the runs.
+	#[inline]
+	pub fn on_tcache_flush(&self) {
+		Self::add(&self.tcache_flushes, 1);
+	}
+
+	/// Add the counters to the given report.
+	pub fn accumulate(&self, report: &mut StatsReport) {
+		let inner = self.inner_bytes.load(Ordering::Relaxed);
+		let total = self.total_bytes.load(Ordering::Relaxed);
+		let freed_inner = self.freed_inner_bytes.load(Ordering::Relaxed);
+		let freed_total = self.freed_total_bytes.load(Ordering::Relaxed);
+
+		report.allocs += self.allocs.load(Ordering::Relaxed) as u64;
+		report.frees += self.frees.load(Ordering::Relaxed) as u64;
+		report.remote_frees += self.remote_frees.load(Ordering::Relaxed) as u64;
+		report.reallocs += self.reallocs.load(Ordering::Relaxed) as u64;
+		report.requested_bytes += self.requested_bytes.load(Ordering::Relaxed) as u64;
+		report.tcache_hits += self.tcache_hits.load(Ordering::Relaxed) as u64;
+		report.tcache_refills += self.tcache_refills.load(Ordering::Relaxed) as u64;
+		report.tcache_flushes += self.tcache_flushes.load(Ordering::Relaxed) as u64;
+		report.inner_bytes += inner as u64;
+		report.total_bytes += total as u64;
+
+		//chunks can be freed by another thread than the one which allocated them
+		//so per allocator values can be negative, only the sum make sense.
+		report.inner_bytes_in_use = report.inner_bytes_in_use.wrapping_add(inner.wrapping_sub(freed_inner) as u64);
+		report.total_bytes_in_use = report.total_bytes_in_use.wrapping_add(total.wrapping_sub(freed_total) as u64);
+	}
+}
+
+impl MMSourceStats {
+	pub fn new() -> Self {
+		Self {
+			mmap_calls: AtomicUsize::new(0),
+			munmap_calls: AtomicUsize::new(0),
+			mremap_calls: AtomicUsize::new(0),
+		}
+	}
+
+	#[inline]
+	pub fn on_mmap(&self) {
+		self.mmap_calls.fetch_add(1, Ordering::Relaxed);
+	}
+
+	#[inline]
+	pub fn on_munmap(&self) {
+		self.munmap_calls.fetch_add(1, Ordering::Relaxed);
+	}
+
+	#[inline]
+	pub fn on_mremap(&self) {
+		self.mremap_calls.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Add the counters to the given report.
+	pub fn accumulate(&self, report: &mut StatsReport) {
+		report.mmap_calls += self.mmap_calls.load(Ordering::Relaxed) as u64;
+		report.munmap_calls += self.munmap_calls.load(Ordering::Relaxed) as u64;
+		report.mremap_calls += self.mremap_calls.load(Ordering::Relaxed) as u64;
+	}
+}
+
+impl DetailedReport {
+	pub fn new() -> Self {
+		Self {
+			summary: StatsReport::new(),
+			small: [SmallClassReport::default(); NB_SIZE_CLASS],
+			medium: [MediumListReport::default(); NB_FREE_LIST],
+			huge_segments: 0,
+			huge_bytes: 0,
+			mmsrc_blocs: 0,
+		}
+	}
+}
+
+impl StatsReport {
+	/// Build an empty report for the current version.
+	pub fn new() -> Self {
+		let mut res = Self::default();
+		res.version = STATS_VERSION;
+		res.size = mem::size_of::<Self>() as u32;
+		res
+	}
+
+	/// Copy the report into the struct given by the user, only filling the
+	/// fields it knows from the size it gives as it might be compiled against
+	/// an older version. Return false if the size is too small to contain
+	/// the header.
+	///
+	/// @param out The struct to fill, with its size field set by the caller.
+	pub fn copy_to(&self, out: * mut StatsReport) -> bool {
+		//check size
+		let user_size = unsafe{(*out).size} as Size;
+		let header_size = 2 * mem::size_of::<u32>();
+		if user_size < header_size {
+			return false;
+		}
+
+		//copy the fields the user knows
+		let copy_size = if user_size < mem::size_of::<Self>() {user_size} else {mem::size_of::<Self>()};
+		unsafe {
+			let from = self as * const Self as * const u8;
+			let to = out as * mut u8;
+			core::ptr::copy_nonoverlapping(from.offset(header_size as isize), to.offset(header_size as isize), copy_size - header_size);
+			(*out).version = STATS_VERSION;
+		}
+
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use common::stats::*;
+
+	#[test]
+	fn alloc_stats() {
+		let stats = AllocStats::new();
+		stats.on_alloc(10, 16, 32);
+		stats.on_alloc(100, 112, 128);
+		stats.on_free(16, 32);
+		stats.on_realloc(200, 112, 128, 208, 224);
+		stats.on_remote_free();
+		stats.on_tcache_hit();
+		stats.on_tcache_hit();
+		stats.on_tcache_refill();
+
+		let mut report = StatsReport::new();
+		stats.accumulate(&mut report);
+		assert_eq!(report.allocs, 2);
+		assert_eq!(report.frees, 1);
+		assert_eq!(report.reallocs, 1);
+		assert_eq!(report.remote_frees, 1);
+		assert_eq!(report.tcache_hits, 2);
+		assert_eq!(report.tcache_refills, 1);
+		assert_eq!(report.tcache_flushes, 0);
+		assert_eq!(report.requested_bytes, 310);
+		assert_eq!(report.inner_bytes_in_use, 208);
+		assert_eq!(report.total_bytes_in_use, 224);
+	}
+
+	#[test]
+	fn in_use_cross_thread() {
+		let alloc_thread = AllocStats::new();
+		let free_thread = AllocStats::new();
+		alloc_thread.on_alloc(10, 16, 32);
+		free_thread.on_free(16, 32);
+
+		let mut report = StatsReport::new();
+		free_thread.accumulate(&mut report);
+		alloc_thread.accumulate(&mut report);
+		assert_eq!(report.inner_bytes_in_use, 0);
+		assert_eq!(report.total_bytes_in_use, 0);
+	}
+
+	#[test]
+	fn copy_to() {
+		let mut report = StatsReport::new();
+		report.allocs = 10;
+		report.mmsrc_cached_bytes = 20;
+		report.tcache_flushes = 30;
+
+		//full
+		let mut out = StatsReport::default();
+		out.size = mem::size_of::<StatsReport>() as u32;
+		assert!(report.copy_to(&mut out as * mut StatsReport));
+		assert_eq!(out.version, STATS_VERSION);
+		assert_eq!(out.allocs, 10);
+		assert_eq!(out.mmsrc_cached_bytes, 20);
+		assert_eq!(out.tcache_flushes, 30);
+
+		//older caller not knowing last field
+		let mut out = StatsReport::default();
+		out.size = (mem::size_of::<StatsReport>() - 8) as u32;
+		assert!(report.copy_to(&mut out as * mut StatsReport));
+		assert_eq!(out.allocs, 10);
+		assert_eq!(out.mmsrc_cached_bytes, 20);
+		assert_eq!(out.tcache_flushes, 0);
+
+		//invalid
+		let mut out = StatsReport::default();
+		assert_eq!(report.copy_to(&mut out as * mut StatsReport), false);
+	}
+}
@@ -14,16 +14,42 @@ extern crate libc;
 //use registry::registry::RegionRegistry;
 //use mmsource::cached::CachedMMSource;
 //use common::traits::{MemorySourcePtr};
-use core::panic::PanicInfo;
-use core::intrinsics;
 use common::types::*;
+use common::consts::NULL;
+use common::ops;
 //use chunk::huge::HugeChunkManager;
 //use chunk::medium::manager::MediumChunkManager;
 //use chunk::small::manager::SmallChunkManager;
 //use common::consts::*;
 //use common::shared::SharedPtrBox;
 //use posix::seq::SeqAllocator;
-use posix::numa::ThreadNumaAllocatorHandler;
+use posix::numa::{NumaAllocatorHandler,ThreadNumaAllocatorHandler};
+use common::stats::StatsReport;
+use posix::report;
+use portability::libc::{FdWriter,FileWriter,ENOMEM,EINVAL,set_errno};
+use arena::HpcArena;
+use common::oom::{self,OomHandler};
+use common::config::{self,OomPolicy};
+use core::mem::ManuallyDrop;
+use core::cmp;
+
+/// Run the allocation and apply the out of memory policy if it fails, it is
+/// retried as long as the handler ask for it. Set errno to ENOMEM on failure.
+///
+/// @param size The requested size to give to the handler.
+/// @param alloc The allocation to run.
+fn oom_guard<F: FnMut() -> Addr>(size: Size, mut alloc: F) -> Addr {
+	loop {
+		let res = alloc();
+		if res != NULL {
+			return res;
+		}
+		if !oom::on_failure(size) {
+			set_errno(ENOMEM);
+			return NULL;
+		}
+	}
+}
 
 // Entry point for this program
 #[no_mangle]
@@ -43,43 +69,59 @@ pub extern fn malloc(size: libc::size_t) -> *mut libc::c_void {
 		huge_manager.malloc(size,BASIC_ALIGN,false).0 as *mut libc::c_void
 	}*/
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.malloc(size as Size) as *mut libc::c_void;
+	return oom_guard(size as Size, || allocator.malloc(size as Size)) as *mut libc::c_void;
 }
 
 #[no_mangle]
 pub extern "C" fn calloc(nmemb: libc::size_t, size: libc::size_t) -> *mut libc::c_void {
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.calloc(nmemb as Size, size as Size) as *mut libc::c_void;
+	let total = match (nmemb as Size).checked_mul(size as Size) {
+		Some(total) => total,
+		None => {
+			set_errno(ENOMEM);
+			return NULL as *mut libc::c_void;
+		},
+	};
+	return oom_guard(total, || allocator.calloc(nmemb as Size, size as Size)) as *mut libc::c_void;
 }
 
 #[no_mangle]
 pub extern "C" fn posix_memalign(memptr: * mut *mut libc::c_void,align: libc::size_t,size: libc::size_t) -> libc::int32_t {
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.posix_memalign(memptr as *mut *mut Addr, align as Size, size as Size) as libc::int32_t;
+	loop {
+		let res = allocator.posix_memalign(memptr as *mut *mut Addr, align as Size, size as Size);
+		if res != ENOMEM || !oom::on_failure(size as Size) {
+			return res as libc::int32_t;
+		}
+	}
 }
 
 #[no_mangle]
 pub extern "C" fn aligned_alloc(align: libc::size_t, size: libc::size_t) -> *mut libc::c_void {
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.aligned_alloc(align as Size, size as Size) as *mut libc::c_void;
+	if !ops::is_power_of_2(align as Size) {
+		set_errno(EINVAL);
+		return NULL as *mut libc::c_void;
+	}
+	return oom_guard(size as Size, || allocator.aligned_alloc(align as Size, size as Size)) as *mut libc::c_void;
 }
 
 #[no_mangle]
 pub extern "C" fn valloc(size: libc::size_t) -> *mut libc::c_void {
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.valloc(size as Size) as *mut libc::c_void;
+	return oom_guard(size as Size, || allocator.valloc(size as Size)) as *mut libc::c_void;
 }
 
 #[no_mangle]
 pub extern "C" fn memalign(align: libc::size_t, size: libc::size_t) -> *mut libc::c_void {
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.memalign(align as Size, size as Size) as *mut libc::c_void;
+	return oom_guard(size as Size, || allocator.memalign(align as Size, size as Size)) as *mut libc::c_void;
 }
 
 #[no_mangle]
 pub extern "C" fn pvalloc(size: libc::size_t) -> *mut libc::c_void {
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.pvalloc(size as Size) as *mut libc::c_void;
+	return oom_guard(size as Size, || allocator.pvalloc(size as Size)) as *mut libc::c_void;
 }
 
 #[no_mangle]
@@ -88,10 +130,46 @@ pub extern "C" fn free(addr: *mut libc::c_void) {
 	allocator.free(addr as Addr);
 }
 
+/// C23 free with the size given at allocation time.
+#[no_mangle]
+pub extern "C" fn free_sized(addr: *mut libc::c_void, size: libc::size_t) {
+	let mut allocator = ThreadNumaAllocatorHandler::new();
+	allocator.free_sized(addr as Addr, size as Size);
+}
+
+/// C23 free with the alignement and size given to aligned_alloc(), the larger one
+/// tells the size class of the chunk.
+#[no_mangle]
+pub extern "C" fn free_aligned_sized(addr: *mut libc::c_void, align: libc::size_t, size: libc::size_t) {
+	debug_assert!(ops::is_power_of_2(align as Size) && addr as Addr % align as Size == 0);
+	let mut allocator = ThreadNumaAllocatorHandler::new();
+	allocator.free_sized(addr as Addr, cmp::max(size, align) as Size);
+}
+
 #[no_mangle]
 pub extern "C" fn realloc(ptr: *mut libc::c_void,size: libc::size_t) -> *mut libc::c_void {
 	let mut allocator = ThreadNumaAllocatorHandler::new();
-	return allocator.realloc(ptr as Addr, size as Size) as *mut libc::c_void;
+
+	//realloc(ptr, 0) free the chunk and return NULL
+	if size == 0 {
+		return allocator.realloc(ptr as Addr, 0) as *mut libc::c_void;
+	}
+
+	//on failure the old chunk is left untouched
+	return oom_guard(size as Size, || allocator.realloc(ptr as Addr, size as Size)) as *mut libc::c_void;
+}
+
+/// Same than realloc() for an array, fail with ENOMEM without touching the old
+/// chunk if nmemb*size overflow.
+#[no_mangle]
+pub extern "C" fn reallocarray(ptr: *mut libc::c_void, nmemb: libc::size_t, size: libc::size_t) -> *mut libc::c_void {
+	match (nmemb as Size).checked_mul(size as Size) {
+		Some(total) => realloc(ptr, total as libc::size_t),
+		None => {
+			set_errno(ENOMEM);
+			NULL as *mut libc::c_void
+		},
+	}
 }
 
 #[no_mangle]
@@ -112,25 +190,147 @@ pub extern "C" fn get_requested_size(ptr: *mut libc::c_void) -> libc::size_t {
 	return allocator.get_requested_size(ptr as Addr) as libc::size_t;
 }
 
+/// To be called by threads which get bound to a NUMA node after their first
+/// call to malloc so their following allocations are served from this node.
 #[no_mangle]
-pub extern "C" fn _Unwind_Resume()
-{
+pub extern "C" fn mpc_alloc_numa_rebind() {
+	let mut allocator = ThreadNumaAllocatorHandler::new();
+	allocator.numa_rebind();
+}
 
+/// Fill the given struct with the statistics aggregated over all threads.
+/// The caller must set the `size` field to the size of the struct it knows,
+/// return 0 on success and -1 if the struct is invalid.
+#[no_mangle]
+pub extern "C" fn hpc_alloc_get_stats(stats: * mut StatsReport) -> libc::c_int {
+	if stats.is_null() {
+		return -1;
+	}
+
+	let handler = NumaAllocatorHandler::new();
+	if handler.collect_stats().copy_to(stats) {
+		return 0;
+	} else {
+		return -1;
+	}
 }
 
-// These functions and traits are used by the compiler, but not
-// for a bare-bones hello world. These are normally
-// provided by libstd.
-#[lang = "eh_personality"] 
 #[no_mangle]
-pub extern fn eh_personality() {}
+pub extern "C" fn malloc_usable_size(ptr: *mut libc::c_void) -> libc::size_t {
+	let allocator = ThreadNumaAllocatorHandler::new();
+	return allocator.get_inner_size(ptr as Addr) as libc::size_t;
+}
 
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-	intrinsics::abort()
+/// Return unused memory to the OS. Unlike glibc there is no main heap so
+/// the pad argument is ignored. Return 1 if some memory was released.
+#[no_mangle]
+pub extern "C" fn malloc_trim(_pad: libc::size_t) -> libc::c_int {
+	let mut handler = NumaAllocatorHandler::new();
+	if handler.trim() > 0 {
+		return 1;
+	} else {
+		return 0;
+	}
 }
 
-/*#[lang = "eh_unwind_resume"]
+/// Adjust the runtime tunables, return 1 on success and 0 if the parameter
+/// is not supported or the value is invalid.
 #[no_mangle]
-pub extern fn rust_eh_unwind_resume() {
-}*/
+pub extern "C" fn mallopt(param: libc::c_int, value: libc::c_int) -> libc::c_int {
+	let mut handler = NumaAllocatorHandler::new();
+	if handler.mallopt(param as i32, value as i32) {
+		return 1;
+	} else {
+		return 0;
+	}
+}
+
+/// Register the handler called when running out of memory and return the
+/// previous one. Registering a handler switch the oom_policy to handler,
+/// unregistering it with NULL switch it back to return NULL.
+#[no_mangle]
+pub extern "C" fn hpc_alloc_set_oom_handler(handler: Option<OomHandler>) -> Option<OomHandler> {
+	let policy = match handler {
+		Some(_) => OomPolicy::Handler,
+		None => OomPolicy::Null,
+	};
+
+	//store the handler first so a failure seeing the new policy finds it,
+	//the config is published with release ordering
+	let old = oom::set_handler(handler);
+	config::update(|cfg| {cfg.oom_policy = policy; true});
+	old
+}
+
+/// Print a human readable report of the allocator state on stderr.
+#[no_mangle]
+pub extern "C" fn malloc_stats() {
+	let details = NumaAllocatorHandler::new().collect_details();
+	let _ = report::print_text(&mut FdWriter::new(2), &details);
+}
+
+/// Dump an XML report of the allocator state in the given stream. As for
+/// glibc, options must be 0.
+#[no_mangle]
+pub extern "C" fn malloc_info(options: libc::c_int, stream: * mut libc::FILE) -> libc::c_int {
+	if options != 0 || stream.is_null() {
+		set_errno(EINVAL);
+		return -1;
+	}
+
+	let details = NumaAllocatorHandler::new().collect_details();
+	match report::print_xml(&mut FileWriter::new(stream), &details) {
+		Ok(()) => return 0,
+		Err(_) => return -1,
+	}
+}
+
+/// Create a private heap. All the memory allocated into it is returned to the
+/// OS in one call with hpc_heap_destroy().
+#[no_mangle]
+pub extern "C" fn hpc_heap_create() -> *mut libc::c_void {
+	match HpcArena::try_new() {
+		Some(arena) => arena.into_raw() as *mut libc::c_void,
+		None => {
+			set_errno(ENOMEM);
+			NULL as *mut libc::c_void
+		},
+	}
+}
+
+/// Rebuild a heap from its handle without taking its ownership.
+fn get_heap(heap: *mut libc::c_void) -> ManuallyDrop<HpcArena> {
+	debug_assert!(!heap.is_null());
+	ManuallyDrop::new(unsafe{HpcArena::from_raw(heap as Addr)})
+}
+
+#[no_mangle]
+pub extern "C" fn hpc_heap_malloc(heap: *mut libc::c_void, size: libc::size_t) -> *mut libc::c_void {
+	let heap = get_heap(heap);
+	return oom_guard(size as Size, || heap.malloc(size as Size)) as *mut libc::c_void;
+}
+
+/// Free a chunk, it must have been allocated in the same heap.
+#[no_mangle]
+pub extern "C" fn hpc_heap_free(heap: *mut libc::c_void, ptr: *mut libc::c_void) {
+	get_heap(heap).free(ptr as Addr);
+}
+
+/// Realloc a chunk, it must have been allocated in the same heap.
+#[no_mangle]
+pub extern "C" fn hpc_heap_realloc(heap: *mut libc::c_void, ptr: *mut libc::c_void, size: libc::size_t) -> *mut libc::c_void {
+	let heap = get_heap(heap);
+	if size == 0 {
+		return heap.realloc(ptr as Addr, 0) as *mut libc::c_void;
+	}
+	return oom_guard(size as Size, || heap.realloc(ptr as Addr, size as Size)) as *mut libc::c_void;
+}
+
+/// Destroy the heap and unmap all its memory without walking the chunks
+/// which are still allocated.
+#[no_mangle]
+pub extern "C" fn hpc_heap_destroy(heap: *mut libc::c_void) {
+	if !heap.is_null() {
+		unsafe{ManuallyDrop::drop(&mut get_heap(heap))};
+	}
+}
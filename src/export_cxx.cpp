@@ -0,0 +1,73 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+//Implement the throwing C++ operator new family. This cannot be done on the
+//Rust side as std::bad_alloc has to unwind through the frames of the caller
+//and cannot go through Rust frames. The allocation itself is done by the
+//nothrow entry point hpc_alloc_cxx_new() from export_cxx.rs.
+//It is linked in the shared library by build.rs, the weak definitions of
+//export_cxx.rs are used otherwise.
+
+//import
+#include <new>
+#include <cstddef>
+
+//requiered functions of libstdc++, linked as weak symbols so the allocator
+//can still be loaded in pure C programs where libstdc++ is not present
+namespace std
+{
+	new_handler get_new_handler() noexcept __attribute__((weak));
+	void __throw_bad_alloc() __attribute__((weak, noreturn));
+}
+
+//from export_cxx.rs
+extern "C" void * hpc_alloc_cxx_new(std::size_t size, std::size_t align, bool has_new_handler);
+
+//Implement the operator new semantic. On failure call the handler registered
+//with std::set_new_handler() and retry until it succeed. If there is none the
+//oom_policy is applied by hpc_alloc_cxx_new() then throw std::bad_alloc.
+static void * cxx_new(std::size_t size, std::size_t align)
+{
+	for (;;) {
+		//try
+		std::new_handler handler = std::get_new_handler();
+		void * res = hpc_alloc_cxx_new(size, align, handler != NULL);
+		if (res != NULL)
+			return res;
+
+		//failure
+		if (handler != NULL)
+			handler();
+		else
+			std::__throw_bad_alloc();
+	}
+}
+
+//operator new(size_t)
+void * operator new(std::size_t size)
+{
+	return cxx_new(size, __STDCPP_DEFAULT_NEW_ALIGNMENT__);
+}
+
+//operator new[](size_t)
+void * operator new[](std::size_t size)
+{
+	return cxx_new(size, __STDCPP_DEFAULT_NEW_ALIGNMENT__);
+}
+
+//operator new(size_t, std::align_val_t)
+void * operator new(std::size_t size, std::align_val_t align)
+{
+	return cxx_new(size, static_cast<std::size_t>(align));
+}
+
+//operator new[](size_t, std::align_val_t)
+void * operator new[](std::size_t size, std::align_val_t align)
+{
+	return cxx_new(size, static_cast<std::size_t>(align));
+}
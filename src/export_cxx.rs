@@ -0,0 +1,223 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+///Export the C++ operator new/delete family with their Itanium mangled names
+///so C++ programs do not go through the libstdc++ ones which call malloc()
+///and loose the size and alignement informations. The throwing operator new
+///are implemented by the C++ shim export_cxx.cpp built by build.rs.
+
+//import
+extern crate libc;
+
+use common::types::*;
+use common::consts::*;
+use common::oom;
+use core::cmp;
+use posix::numa::ThreadNumaAllocatorHandler;
+use portability::libc::{ENOMEM,set_errno};
+use portability::libstdcxx;
+
+/// Nothrow allocation entry point of the throwing operator new implemented in
+/// export_cxx.cpp, std::bad_alloc cannot be thrown from Rust. Return NULL with
+/// errno set to ENOMEM on failure, after having applied our oom_policy if there
+/// is no std::new_handler to be called by the caller.
+///
+/// @param size Size to allocate.
+/// @param align Alignement requested with std::align_val_t, BASIC_ALIGN otherwise.
+/// @param has_new_handler A handler is registered with std::set_new_handler().
+#[no_mangle]
+pub extern "C" fn hpc_alloc_cxx_new(size: libc::size_t, align: libc::size_t, has_new_handler: bool) -> *mut libc::c_void {
+	let mut allocator = ThreadNumaAllocatorHandler::new();
+	loop {
+		//use the alignement machinery only if needed
+		let res = if align as Size <= BASIC_ALIGN {
+			allocator.malloc(size as Size)
+		} else {
+			allocator.memalign(align as Size, size as Size)
+		};
+		if res != NULL {
+			return res as *mut libc::c_void;
+		}
+
+		//failure, the new_handler comes first
+		if has_new_handler || !oom::on_failure(size as Size) {
+			set_errno(ENOMEM);
+			return NULL as *mut libc::c_void;
+		}
+	}
+}
+
+/// Implement the nothrow operator new semantic. On failure call the handler registered
+/// with std::set_new_handler() and retry until it succeed. If there is no handler apply
+/// our oom_policy then return NULL.
+///
+/// @param size Size to allocate.
+/// @param align Alignement requested with std::align_val_t, BASIC_ALIGN otherwise.
+fn cxx_new_nothrow(size: libc::size_t, align: libc::size_t) -> *mut libc::c_void {
+	loop {
+		let handler = libstdcxx::get_new_handler();
+		let res = hpc_alloc_cxx_new(size, align, handler.is_some());
+		match handler {
+			Some(handler) if res == NULL as *mut libc::c_void => handler(),
+			_ => return res,
+		}
+	}
+}
+
+/// Fallback of the throwing operator new if export_cxx.cpp is not linked, we
+/// cannot throw std::bad_alloc so abort on failure.
+///
+/// @param size Size to allocate.
+/// @param align Alignement requested with std::align_val_t, BASIC_ALIGN otherwise.
+fn cxx_new_or_abort(size: libc::size_t, align: libc::size_t) -> *mut libc::c_void {
+	let res = cxx_new_nothrow(size, align);
+	if res == NULL as *mut libc::c_void {
+		oom::fatal("a C++ object");
+	}
+	res
+}
+
+/// Implement operator delete, use the sized free path if the size is known.
+///
+/// @param ptr Address to free.
+/// @param size Size of the object if given by the compiler, or the alignment if larger.
+fn cxx_delete(ptr: *mut libc::c_void, size: Option<Size>) {
+	let mut allocator = ThreadNumaAllocatorHandler::new();
+	match size {
+		Some(size) => allocator.free_sized(ptr as Addr, size),
+		None => allocator.free(ptr as Addr),
+	}
+}
+
+//The throwing operator new are implemented in export_cxx.cpp, these weak
+//definitions are overriden by the ones of the C++ shim, they list the symbols
+//in the exports of the shared library and are used if it is not linked.
+
+//operator new(size_t)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn _Znwm(size: libc::size_t) -> *mut libc::c_void {
+	cxx_new_or_abort(size, BASIC_ALIGN)
+}
+
+//operator new[](size_t)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn _Znam(size: libc::size_t) -> *mut libc::c_void {
+	cxx_new_or_abort(size, BASIC_ALIGN)
+}
+
+//operator new(size_t, std::align_val_t)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn _ZnwmSt11align_val_t(size: libc::size_t, align: libc::size_t) -> *mut libc::c_void {
+	cxx_new_or_abort(size, align)
+}
+
+//operator new[](size_t, std::align_val_t)
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn _ZnamSt11align_val_t(size: libc::size_t, align: libc::size_t) -> *mut libc::c_void {
+	cxx_new_or_abort(size, align)
+}
+
+//operator new(size_t, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZnwmRKSt9nothrow_t(size: libc::size_t, _nothrow: *const libc::c_void) -> *mut libc::c_void {
+	cxx_new_nothrow(size, BASIC_ALIGN)
+}
+
+//operator new[](size_t, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZnamRKSt9nothrow_t(size: libc::size_t, _nothrow: *const libc::c_void) -> *mut libc::c_void {
+	cxx_new_nothrow(size, BASIC_ALIGN)
+}
+
+//operator new(size_t, std::align_val_t, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZnwmSt11align_val_tRKSt9nothrow_t(size: libc::size_t, align: libc::size_t, _nothrow: *const libc::c_void) -> *mut libc::c_void {
+	cxx_new_nothrow(size, align)
+}
+
+//operator new[](size_t, std::align_val_t, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZnamSt11align_val_tRKSt9nothrow_t(size: libc::size_t, align: libc::size_t, _nothrow: *const libc::c_void) -> *mut libc::c_void {
+	cxx_new_nothrow(size, align)
+}
+
+//operator delete(void*)
+#[no_mangle]
+pub extern "C" fn _ZdlPv(ptr: *mut libc::c_void) {
+	cxx_delete(ptr, None);
+}
+
+//operator delete[](void*)
+#[no_mangle]
+pub extern "C" fn _ZdaPv(ptr: *mut libc::c_void) {
+	cxx_delete(ptr, None);
+}
+
+//operator delete(void*, size_t)
+#[no_mangle]
+pub extern "C" fn _ZdlPvm(ptr: *mut libc::c_void, size: libc::size_t) {
+	cxx_delete(ptr, Some(size as Size));
+}
+
+//operator delete[](void*, size_t)
+#[no_mangle]
+pub extern "C" fn _ZdaPvm(ptr: *mut libc::c_void, size: libc::size_t) {
+	cxx_delete(ptr, Some(size as Size));
+}
+
+//operator delete(void*, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZdlPvRKSt9nothrow_t(ptr: *mut libc::c_void, _nothrow: *const libc::c_void) {
+	cxx_delete(ptr, None);
+}
+
+//operator delete[](void*, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZdaPvRKSt9nothrow_t(ptr: *mut libc::c_void, _nothrow: *const libc::c_void) {
+	cxx_delete(ptr, None);
+}
+
+//operator delete(void*, std::align_val_t)
+#[no_mangle]
+pub extern "C" fn _ZdlPvSt11align_val_t(ptr: *mut libc::c_void, _align: libc::size_t) {
+	cxx_delete(ptr, None);
+}
+
+//operator delete[](void*, std::align_val_t)
+#[no_mangle]
+pub extern "C" fn _ZdaPvSt11align_val_t(ptr: *mut libc::c_void, _align: libc::size_t) {
+	cxx_delete(ptr, None);
+}
+
+//operator delete(void*, size_t, std::align_val_t)
+#[no_mangle]
+pub extern "C" fn _ZdlPvmSt11align_val_t(ptr: *mut libc::c_void, size: libc::size_t, align: libc::size_t) {
+	cxx_delete(ptr, Some(cmp::max(size, align) as Size));
+}
+
+//operator delete[](void*, size_t, std::align_val_t)
+#[no_mangle]
+pub extern "C" fn _ZdaPvmSt11align_val_t(ptr: *mut libc::c_void, size: libc::size_t, align: libc::size_t) {
+	cxx_delete(ptr, Some(cmp::max(size, align) as Size));
+}
+
+//operator delete(void*, std::align_val_t, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZdlPvSt11align_val_tRKSt9nothrow_t(ptr: *mut libc::c_void, _align: libc::size_t, _nothrow: *const libc::c_void) {
+	cxx_delete(ptr, None);
+}
+
+//operator delete[](void*, std::align_val_t, const std::nothrow_t&)
+#[no_mangle]
+pub extern "C" fn _ZdaPvSt11align_val_tRKSt9nothrow_t(ptr: *mut libc::c_void, _align: libc::size_t, _nothrow: *const libc::c_void) {
+	cxx_delete(ptr, None);
+}
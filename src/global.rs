@@ -0,0 +1,133 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+///Provide the Rust global allocator front-end. To use it :
+///
+///```ignore
+///extern crate hpc_allocator_rust;
+///#[global_allocator]
+///static GLOBAL: hpc_allocator_rust::global::HpcAllocator = hpc_allocator_rust::global::HpcAllocator;
+///```
+
+//import
+use core::alloc::{GlobalAlloc,Layout};
+use core::cmp;
+use common::types::{Addr,Size};
+use common::consts::*;
+use portability::libc;
+use posix::numa::ThreadNumaAllocatorHandler;
+
+/// Implement GlobalAlloc on top of the thread local NUMA allocators.
+pub struct HpcAllocator;
+
+unsafe impl GlobalAlloc for HpcAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		if layout.align() <= BASIC_ALIGN {
+			allocator.malloc(layout.size() as Size) as *mut u8
+		} else {
+			allocator.memalign(layout.align() as Size, layout.size() as Size) as *mut u8
+		}
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		allocator.free_sized(ptr as Addr, cmp::max(layout.size(), layout.align()) as Size);
+	}
+
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		if layout.align() <= BASIC_ALIGN {
+			allocator.calloc(1, layout.size() as Size) as *mut u8
+		} else {
+			let ptr = allocator.memalign(layout.align() as Size, layout.size() as Size);
+			if ptr != NULL {
+				libc::memset(ptr, 0, layout.size() as Size);
+			}
+			ptr as *mut u8
+		}
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+
+		//basic alignement is guaranteed by realloc
+		if layout.align() <= BASIC_ALIGN {
+			return allocator.realloc(ptr as Addr, new_size as Size) as *mut u8;
+		}
+
+		//otherwise move by hand to keep alignement
+		let new_ptr = allocator.memalign(layout.align() as Size, new_size as Size);
+		if new_ptr != NULL {
+			libc::memcpy(new_ptr, ptr as Addr, cmp::min(layout.size(), new_size) as Size);
+			allocator.free(ptr as Addr);
+		}
+		new_ptr as *mut u8
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use global::*;
+
+	#[test]
+	fn alloc_dealloc() {
+		let allocator = HpcAllocator;
+		let layout = Layout::from_size_align(1024, 8).unwrap();
+		unsafe {
+			let ptr = allocator.alloc(layout);
+			assert!(!ptr.is_null());
+			*ptr = 1;
+			allocator.dealloc(ptr, layout);
+		}
+	}
+
+	#[test]
+	fn alloc_aligned() {
+		let allocator = HpcAllocator;
+		let layout = Layout::from_size_align(1024, 256).unwrap();
+		unsafe {
+			let ptr = allocator.alloc(layout);
+			assert!(!ptr.is_null());
+			assert_eq!(ptr as usize % 256, 0);
+			allocator.dealloc(ptr, layout);
+		}
+	}
+
+	#[test]
+	fn alloc_zeroed() {
+		let allocator = HpcAllocator;
+		let layout = Layout::from_size_align(4096, 64).unwrap();
+		unsafe {
+			let ptr = allocator.alloc_zeroed(layout);
+			assert!(!ptr.is_null());
+			for i in 0..4096 {
+				assert_eq!(*ptr.offset(i), 0);
+			}
+			allocator.dealloc(ptr, layout);
+		}
+	}
+
+	#[test]
+	fn realloc() {
+		let allocator = HpcAllocator;
+		for align in [8, 512].iter() {
+			let layout = Layout::from_size_align(1024, *align).unwrap();
+			unsafe {
+				let ptr = allocator.alloc(layout);
+				*ptr.offset(1023) = 42;
+				let ptr = allocator.realloc(ptr, layout, 64*1024);
+				assert!(!ptr.is_null());
+				assert_eq!(ptr as usize % *align, 0);
+				assert_eq!(*ptr.offset(1023), 42);
+				allocator.dealloc(ptr, Layout::from_size_align(64*1024, *align).unwrap());
+			}
+		}
+	}
+}
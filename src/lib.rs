@@ -10,10 +10,17 @@
 #![feature(lang_items,libc)]
 //#![feature(panic_implementation)]
 #![feature(core_intrinsics)]
+#![feature(allocator_api)]
+#![feature(linkage,thread_local)]
 #![no_std]
 #![allow(dead_code)]
 #![feature(llvm_asm)]
 
+//when used as global allocator we are linked in a std program so take
+//the panic handler from it
+#[cfg(feature = "global-alloc")]
+extern crate std;
+
 //load modules
 mod common;
 mod registry;
@@ -22,5 +29,13 @@ mod chunk;
 mod mmsource;
 mod posix;
 
-#[cfg(not(test))]
+#[cfg(all(not(test), feature = "c-export"))]
 pub mod export;
+#[cfg(all(not(test), feature = "c-export", target_pointer_width = "64"))]
+#[allow(non_snake_case)]
+pub mod export_cxx;
+pub mod arena;
+#[cfg(feature = "global-alloc")]
+pub mod global;
+#[cfg(all(not(test), not(feature = "global-alloc")))]
+mod lang;
@@ -9,39 +9,60 @@
 /// Implement a memory source with behave as a cache by keeping macro blocs into memory
 /// to reduce exchanges with the OS and pay less the price of first touch page
 /// faults.
+///
+/// The cached blocs decay with time: after dirty_decay their pages are released
+/// with MADV_FREE (muzzy) and after muzzy_decay they are returned to the OS.
+///
+/// On request the macro blocs are aligned on HUGE_PAGE_SIZE to be backed by
+/// transparent huge pages or directly mapped with MAP_HUGETLB.
 
 //import
 use common::consts::*;
+use common::config;
+use common::config::HugePages;
 use common::types::{Addr,Size};
 use common::list::{List,ListNode,Listable};
 use common::shared::SharedPtrBox;
 use common::traits::{ChunkManagerPtr,MemorySource};
 use common::ops;
+use common::stats::{DetailedReport,MMSourceStats,StatsReport};
 use registry::registry::RegionRegistry;
 use registry::segment::{RegionSegment,RegionSegmentPtr};
 use portability::spinlock::SpinLock;
 use portability::osmem;
+use portability::libnuma;
+use portability::libc;
 use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of size buckets in the cache, they are MACRO_BLOC_SIZE wide and the
+/// last one keeps all the larger blocs. Must fit in the bits of a usize.
+const NB_BUCKETS: usize = 32;
 
 /// Implement the header to track state of free macro blocs we keep in the cache.
 struct FreeMacroBloc {
 	node: ListNode,
 	total_size: Size,
+	/// Time in milliseconds at which the bloc entered the cache.
+	timestamp: Size,
+	/// Pages already released with MADV_FREE.
+	muzzy: bool,
+	/// Size of the pages backing the bloc.
+	page_size: Size,
 }
 
 /// Alias to ease code.
 type FreeMacroBlocList = List<FreeMacroBloc>;
 
-/// Internal values which will be protected by spinlock.
-struct InternalThreadProtected {
-	list: FreeMacroBlocList,
-	current_size: Size,
-}
-
 /// Implement the cached memory source.
 pub struct CachedMMSource {
-	/// Store the free macro bloc list for future reuse, protected by a spinlock.
-	freelist: SpinLock<InternalThreadProtected>,
+	/// Store the free macro blocs for future reuse sorted by size, each bucket
+	/// is protected by its own spinlock.
+	buckets: [SpinLock<FreeMacroBlocList>; NB_BUCKETS],
+	/// Bitmap of the non empty buckets to quickly find the nearest one.
+	used_buckets: AtomicUsize,
+	/// Total size of the macro blocs kept in the cache.
+	current_size: AtomicUsize,
 	/// Maximal authozied size for the cache.
 	max_size: Size,
 	/// Do not keep macro blocs larger than this.
@@ -50,6 +71,18 @@ pub struct CachedMMSource {
 	keep_residut: bool,
 	/// Ref to registry to register the new macro blocs before giving them to the caller.
 	registry: Option<SharedPtrBox<RegionRegistry>>,
+	/// NUMA node on which to bind the macro blocs we get from the OS, -1 to keep the OS policy.
+	numa_node: i32,
+	/// Milliseconds before releasing the pages of cached blocs with MADV_FREE, 0 to never do it.
+	dirty_decay: Size,
+	/// Milliseconds before returning the cached blocs to the OS, 0 to keep them.
+	muzzy_decay: Size,
+	/// Time of the next decay pass so only one thread does it from time to time.
+	next_decay: AtomicUsize,
+	/// Kind of pages to back the macro blocs with.
+	huge_pages: HugePages,
+	/// Count the calls to the OS.
+	stats: MMSourceStats,
 }
 
 //Implement free macro bloc
@@ -58,11 +91,15 @@ impl FreeMacroBloc {
 	///
 	/// @param addr: Define the base address of the free macro bloc. Also where to write the free macro bloc header.
 	/// @param total_size: Define the total size of the free macro bloc.
-	pub fn new(addr: Addr, total_size: Size) -> SharedPtrBox<FreeMacroBloc> {
+	/// @param page_size: Define the size of the pages backing the bloc.
+	pub fn new(addr: Addr, total_size: Size, page_size: Size) -> SharedPtrBox<FreeMacroBloc> {
 		let mut ptr: SharedPtrBox<FreeMacroBloc> = SharedPtrBox::new_addr(addr);
 		*ptr.get_mut() = FreeMacroBloc {
 			node: ListNode::new(),
 			total_size: total_size,
+			timestamp: 0,
+			muzzy: false,
+			page_size: page_size,
 		};
 		ptr
 	}
@@ -107,142 +144,460 @@ impl CachedMMSource {
 	/// @param keep_residut Define if we keep the ending part of macro blocs when reuse (if they are big enougth to be a macro bloc.)
 	pub fn new(registry:Option<SharedPtrBox<RegionRegistry>>,max_size:Size,threashold:Size,keep_residut:bool) -> Self {
 		Self {
-			freelist: SpinLock::new(InternalThreadProtected{
-				list: FreeMacroBlocList::new(),
-				current_size: 0,
-			}),
+			buckets: Default::default(),
+			used_buckets: AtomicUsize::new(0),
+			current_size: AtomicUsize::new(0),
 			max_size: max_size,
 			threashold: threashold,
 			keep_residut: keep_residut,
 			registry: registry,
+			numa_node: -1,
+			dirty_decay: 0,
+			muzzy_decay: 0,
+			next_decay: AtomicUsize::new(0),
+			huge_pages: HugePages::None,
+			stats: MMSourceStats::new(),
 		}
 	}
 
-	/// Same than new but with default values for configuration
+	/// Same than new but with values from the runtime configuration
 	pub fn new_default(registry:Option<SharedPtrBox<RegionRegistry>>) -> Self {
-		Self::new(registry,MMSRC_MAX_SIZE,MMSRC_THREASHOLD,MMSRC_KEEP_RESIDUT)
+		let config = config::get();
+		let mut res = Self::new(registry,config.mmsrc_max_size,config.mmsrc_threashold,config.mmsrc_keep_residut);
+		res.set_decay(config.mmsrc_dirty_decay,config.mmsrc_muzzy_decay);
+		res.set_huge_pages(config.mmsrc_huge_pages);
+		res
+	}
+
+	/// Same than new_default but bind all the macro blocs obtained from the OS
+	/// to the given NUMA node.
+	///
+	/// @param registry Define the regisitry to be used for bloc registration. Can be None to ignore.
+	/// @param numa_node Define the NUMA node to bind memory on, -1 to not bind.
+	pub fn new_numa(registry:Option<SharedPtrBox<RegionRegistry>>,numa_node: i32) -> Self {
+		let mut res = Self::new_default(registry);
+		res.numa_node = numa_node;
+		res
+	}
+
+	/// Return the NUMA node the memory source is bound to, -1 if not bound.
+	pub fn get_numa_node(&self) -> i32 {
+		self.numa_node
+	}
+
+	/// Bind the given fresh memory range to the NUMA node of the memory source if it has one.
+	fn bind_numa(&self, ptr: Addr, size: Size) {
+		if self.numa_node >= 0 {
+			libnuma::numa_bind_memory(ptr,size,self.numa_node);
+		}
+	}
+
+	/// Return the amount of memory currently kept in the cache.
+	pub fn get_cached_size(&self) -> Size {
+		self.current_size.load(Ordering::Relaxed)
+	}
+
+	/// Add the counters of the memory source to the report.
+	pub fn collect_stats(&self, report: &mut StatsReport) {
+		self.stats.accumulate(report);
+		report.mmsrc_cached_bytes += self.get_cached_size() as u64;
+	}
+
+	/// Add the number of macro blocs kept in the cache to the report.
+	pub fn collect_details(&self, details: &mut DetailedReport) {
+		for bucket in self.buckets.iter() {
+			details.mmsrc_blocs += bucket.lock().iter().count() as u64;
+		}
+	}
+
+	/// Change the limits of the cache, blocs already cached are kept.
+	///
+	/// @param max_size Define the maximum size of the cache.
+	/// @param threashold Define the maximum size of macro blocs to keep inside the cache.
+	pub fn set_limits(&mut self, max_size: Size, threashold: Size) {
+		self.max_size = max_size;
+		self.threashold = threashold;
+	}
+
+	/// Change the decay policy of the cached blocs, it is disabled by default.
+	///
+	/// @param dirty_decay Milliseconds before releasing the pages with MADV_FREE, 0 to never do it.
+	/// @param muzzy_decay Milliseconds before returning the blocs to the OS, 0 to keep them.
+	pub fn set_decay(&mut self, dirty_decay: Size, muzzy_decay: Size) {
+		self.dirty_decay = dirty_decay;
+		self.muzzy_decay = muzzy_decay;
+	}
+
+	/// Change the kind of pages used for the new macro blocs, it is standard
+	/// pages by default.
+	///
+	/// @param huge_pages Ask for transparent huge pages or MAP_HUGETLB.
+	pub fn set_huge_pages(&mut self, huge_pages: HugePages) {
+		self.huge_pages = huge_pages;
+	}
+
+	/// Compute the size of the segment to map for the given inner size. With huge
+	/// pages it is rounded to HUGE_PAGE_SIZE so the whole bloc can use them.
+	///
+	/// @param inner_size The size requested by the caller.
+	fn get_total_size(&self, inner_size: Size) -> Size {
+		//add header
+		let mut total_size = inner_size + mem::size_of::<RegionSegment>();
+
+		//if to small
+		if total_size < REGION_SPLITTING {
+			total_size = REGION_SPLITTING;
+		}
+
+		//round to multiple of page size
+		match self.huge_pages {
+			HugePages::None => ops::up_to_power_of_2(total_size,SMALL_PAGE_SIZE),
+			_ => ops::up_to_power_of_2(total_size,HUGE_PAGE_SIZE),
+		}
+	}
+
+	/// Map new memory from the OS with the kind of pages requested. Fallback on
+	/// transparent huge pages if there is no MAP_HUGETLB pages available.
+	/// Return the address, NULL on failure, and the size of the pages.
+	///
+	/// @param total_size Size to map.
+	fn os_map(&self, total_size: Size) -> (Addr, Size) {
+		//try hugetlb first
+		if self.huge_pages == HugePages::HugeTlb {
+			let ptr = osmem::mmap_hugetlb(total_size);
+			if ptr != NULL {
+				return (ptr, HUGE_PAGE_SIZE);
+			}
+		}
+
+		//aligned for THP or standard
+		let ptr = match self.huge_pages {
+			HugePages::None => osmem::mmap(0,total_size),
+			_ => osmem::mmap_aligned(total_size,HUGE_PAGE_SIZE),
+		};
+		if ptr != NULL && self.huge_pages != HugePages::None {
+			osmem::madvise_hugepage(ptr,total_size);
+		}
+		(ptr, SMALL_PAGE_SIZE)
+	}
+
+	/// Resize a mapping made of standard pages by keeping it aligned on HUGE_PAGE_SIZE
+	/// when huge pages are requested. Return the new address, NULL on failure, then
+	/// the old mapping is left untouched.
+	///
+	/// @param addr Address of the mapping.
+	/// @param old_size Current size of the mapping.
+	/// @param new_size Size to get.
+	fn os_remap(&self, addr: Addr, old_size: Size, new_size: Size) -> Addr {
+		//simple case
+		if self.huge_pages == HugePages::None {
+			return osmem::mremap(addr,old_size,new_size,0);
+		}
+
+		//reserve an aligned range and move on it
+		let dest = osmem::mmap_aligned(new_size,HUGE_PAGE_SIZE);
+		if dest == NULL {
+			return NULL;
+		}
+		let ptr = osmem::mremap(addr,old_size,new_size,dest);
+		if ptr == NULL {
+			osmem::munmap(dest,new_size);
+			return NULL;
+		}
+		osmem::madvise_hugepage(ptr,new_size);
+		ptr
+	}
+
+	/// Apply the decay policy on the cached blocs. Return the size returned to the OS.
+	///
+	/// @param now Current time in milliseconds from libc::get_time_ms().
+	pub fn decay(&self, now: Size) -> Size {
+		let mut res = 0;
+		for (id,bucket) in self.buckets.iter().enumerate() {
+			//blocs to unmap out of the lock
+			let mut expired = FreeMacroBlocList::new();
+
+			//critical section
+			{
+				let mut list = bucket.lock();
+
+				//blocs are inserted at front so the oldest are at the back
+				if self.muzzy_decay > 0 {
+					while let Some(mut bloc) = list.back_mut() {
+						if now.saturating_sub(bloc.timestamp) < self.muzzy_decay {
+							break;
+						}
+						List::remove(&mut bloc);
+						self.current_size.fetch_sub(bloc.get_total_size(), Ordering::Relaxed);
+						expired.push_back(bloc);
+					}
+					if list.is_empty() {
+						self.used_buckets.fetch_and(!(1 << id), Ordering::Relaxed);
+					}
+				}
+
+				//release the pages but keep the header, it must be done under the
+				//lock as another thread could reuse the bloc meanwhile
+				if self.dirty_decay > 0 {
+					for mut bloc in list.iter() {
+						if !bloc.muzzy && now.saturating_sub(bloc.timestamp) >= self.dirty_decay {
+							osmem::madvise_free(bloc.get_root_addr() + bloc.page_size, bloc.get_total_size() - bloc.page_size);
+							bloc.muzzy = true;
+						}
+					}
+				}
+			}
+
+			//return to the OS
+			while let Some(bloc) = expired.pop_front() {
+				let size = bloc.get_total_size();
+				osmem::munmap(bloc.get_root_addr(),size);
+				self.stats.on_munmap();
+				res += size;
+			}
+		}
+		res
+	}
+
+	/// Apply the decay if enabled and not already done recently by another thread.
+	fn decay_if_needed(&self) {
+		//disabled or nothing to do
+		if (self.dirty_decay == 0 && self.muzzy_decay == 0) || self.used_buckets.load(Ordering::Relaxed) == 0 {
+			return;
+		}
+
+		//only one thread from time to time
+		let now = libc::get_time_ms();
+		let next = self.next_decay.load(Ordering::Relaxed);
+		if now < next || self.next_decay.compare_exchange(next, now + MMSRC_DECAY_PERIOD, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+			return;
+		}
+
+		self.decay(now);
 	}
 
 	/// Free all the memory stored into the cache.
 	pub fn free_all(&mut self) {
-		let mut tmp = self.freelist.lock();
-
-		while !tmp.list.is_empty() {
-			let seg = tmp.list.pop_front();
-			match seg {
-				Some(x) => {
-					let x = x.get();
-					let size = x.get_total_size();
-					tmp.current_size -= size;
-					osmem::munmap(x.get_root_addr(),size);
+		for (id,bucket) in self.buckets.iter().enumerate() {
+			let mut list = bucket.lock();
+			while let Some(bloc) = list.pop_front() {
+				let size = bloc.get_total_size();
+				self.current_size.fetch_sub(size, Ordering::Relaxed);
+				osmem::munmap(bloc.get_root_addr(),size);
+				self.stats.on_munmap();
+			}
+			self.used_buckets.fetch_and(!(1 << id), Ordering::Relaxed);
+		}
+	}
+
+	/// Return the bucket in which to store macro blocs of the given size.
+	///
+	/// @param total_size Size of the macro bloc, at least REGION_SPLITTING.
+	fn get_bucket(total_size: Size) -> usize {
+		debug_assert!(total_size >= MACRO_BLOC_SIZE);
+		let id = total_size / MACRO_BLOC_SIZE - 1;
+		if id < NB_BUCKETS {
+			id
+		} else {
+			NB_BUCKETS - 1
+		}
+	}
+
+	/// Insert a free macro bloc in the cache.
+	///
+	/// @param bloc The bloc to insert.
+	fn insert(&self, mut bloc: SharedPtrBox<FreeMacroBloc>) {
+		let size = bloc.get_total_size();
+		bloc.timestamp = libc::get_time_ms();
+		let id = Self::get_bucket(size);
+		let mut list = self.buckets[id].lock();
+		list.push_front(bloc);
+		self.used_buckets.fetch_or(1 << id, Ordering::Relaxed);
+		self.current_size.fetch_add(size, Ordering::Relaxed);
+	}
+
+	/// Extract a macro bloc from the given bucket, the one of the exact size if
+	/// there is one, otherwise the closest larger or smaller one.
+	///
+	/// @param id The bucket to search in.
+	/// @param total_size Define the size we want accouting headers.
+	/// @param larger Search for a larger bloc if true, a smaller one otherwise.
+	fn take_from_bucket(&self, id: usize, total_size: Size, larger: bool) -> Option<SharedPtrBox<FreeMacroBloc>> {
+		//take lock
+		let list = self.buckets[id].lock();
+
+		//search most adapted
+		let mut best: Option<SharedPtrBox<FreeMacroBloc>> = None;
+		let mut best_size = 0;
+		for bloc in list.iter() {
+			let size = bloc.get_total_size();
+
+			//stop if match exactly
+			if size == total_size {
+				best = Some(bloc);
+				break;
+			}
+
+			//check if match better
+			let better = if larger {
+				size > total_size && (best.is_none() || size < best_size)
+			} else {
+				size < total_size && size > best_size
+			};
+			if better {
+				best = Some(bloc);
+				best_size = size;
+			}
+		}
+
+		//extract from list
+		match best {
+			Some(mut bloc) => {
+				List::remove(&mut bloc);
+				if list.is_empty() {
+					self.used_buckets.fetch_and(!(1 << id), Ordering::Relaxed);
 				}
-				None => panic!("There is a bug !"),
+				self.current_size.fetch_sub(bloc.get_total_size(), Ordering::Relaxed);
+				Some(bloc)
+			},
+			None => None,
+		}
+	}
+
+	/// Extract the macro bloc to reuse for the given size from the cache. Prefer
+	/// the exact size, then the nearest larger one to be split and at last the
+	/// nearest smaller one to be enlarged.
+	///
+	/// @param total_size Define the size we want accouting headers.
+	fn take(&self, total_size: Size) -> Option<SharedPtrBox<FreeMacroBloc>> {
+		//exact or larger in the related bucket
+		let id = Self::get_bucket(total_size);
+		let res = self.take_from_bucket(id, total_size, true);
+		if res.is_some() {
+			return res;
+		}
+
+		//nearest larger buckets, the bitmap can change while we search
+		let above = usize::max_value().checked_shl(id as u32 + 1).unwrap_or(0);
+		let mut mask = self.used_buckets.load(Ordering::Relaxed) & above;
+		while mask != 0 {
+			let cur = mask.trailing_zeros() as usize;
+			let res = self.take_from_bucket(cur, total_size, true);
+			if res.is_some() {
+				return res;
 			}
+			mask &= !(1 << cur);
 		}
-		
-		debug_assert!(tmp.current_size == 0);
+
+		//nearest smaller ones, including the related bucket
+		let mut mask = self.used_buckets.load(Ordering::Relaxed) & !above;
+		while mask != 0 {
+			let cur = (mem::size_of::<usize>() * 8 - 1) - mask.leading_zeros() as usize;
+			let res = self.take_from_bucket(cur, total_size, false);
+			if res.is_some() {
+				return res;
+			}
+			mask &= !(1 << cur);
+		}
+
+		None
 	}
 
-	/// Search a free macro bloc which can match in the cache (free list).
-	/// It can remap an existing smaller or larger segment after searching the closer one in term of size.
+	/// Search a free macro bloc which can match in the cache.
+	/// It can remap an existing smaller or larger segment if there is none of the requested size.
 	/// If keep_residut is set it will store the ending part of the segment after splitting the macro bloc.
+	/// When zero filled memory is requested the pages are reset with MADV_DONTNEED instead of
+	/// being cleared by hand so the first touch place them again.
 	///
 	/// @param total_size Define the size we want accouting headers.
+	/// @param zero_filled Ask for a macro bloc reset to zero.
 	/// @param manager Define the chunk manager to attach to the segment
-	fn search_in_cache(&mut self,total_size:Size, manager: Option<ChunkManagerPtr>) -> Option<RegionSegmentPtr> {
+	/// @return The segment and a boolean telling if its content is zero.
+	fn search_in_cache(&mut self,total_size:Size, zero_filled: bool, manager: Option<ChunkManagerPtr>) -> Option<(RegionSegmentPtr,bool)> {
 		//errors
 		debug_assert!(total_size >= REGION_SPLITTING);
 		debug_assert!(total_size <= self.threashold);
 		debug_assert!(total_size % SMALL_PAGE_SIZE == 0);
 		
-		//quickly check if list is empty
+		//quickly check if cache is empty
 		//be non exact but find, this limit contention
-		let is_empty = self.freelist.nolock_safe_read().list.is_empty();
-		
-		//not found 
-		if is_empty {
+		if self.used_buckets.load(Ordering::Relaxed) == 0 {
 			return None;
 		}
 		
-		//vars
-		let mut best: SharedPtrBox<FreeMacroBloc> = SharedPtrBox::new_null();
-		let mut best_delta = Size::max_value();
-
-		//critical section
-		{
-			//take lock
-			let mut tmp = self.freelist.lock();
-
-			//search most adapted
-			for bloc in tmp.list.iter() {
-				let delta = (bloc.get().get_total_size() as i64 - total_size as i64).abs() as Size;
-				//check if match better
-				if delta < best_delta {
-					best_delta = delta;
-					best = bloc;
-				}
-
-				//stop if match exactly
-				if delta == 0 {
-					break;
-				}
-			}
+		//extract the best one
+		let mut best = match self.take(total_size) {
+			Some(bloc) => bloc,
+			None => return None,
+		};
 
-			//extract from list
-			if !best.is_null() {
-				List::remove(&mut best);
-				tmp.current_size -= best.get().get_total_size();
+		//if to large, split or increase if too small
+		if best.get_total_size() != total_size {
+			best = self.fix_reuse_size(best,total_size);
+			if best.is_null() {
+				return None;
 			}
 		}
-		
-		if best.is_null() {
-			return None
-		} else {
-			//if to large, split or increase if too small
-			if best_delta != 0 {
-				best = self.fix_reuse_size(best,total_size);
-			}
 
-			//retu
-			return Some(RegionSegment::new(best.get_root_addr(),best.get_total_size(),manager))
-		}
+		//reset content before writing the segment header
+		let addr = best.get_root_addr();
+		let size = best.get_total_size();
+		let page_size = best.page_size;
+		let zero = zero_filled && osmem::madvise_dontneed(addr,size);
+
+		//retu
+		let mut segment = RegionSegment::new(addr,size,manager);
+		segment.set_page_size(page_size);
+		segment.set_numa_node(self.numa_node);
+		Some((segment,zero))
 	}
 
-
 	/// When reusing segment this function is used to resize the segment (shrink of enlarge).
 	/// If the residut is large enought it might keep it for latter use.
 	///
 	/// @param bloc Define the bloc to resize.
 	/// @param total_size Define the expected size of segment (considering header size).
+	/// @return The resized bloc or null if it cannot be enlarged, it is then put back in the cache.
+	/// MAP_HUGETLB blocs are never enlarged.
 	fn fix_reuse_size(&mut self, bloc: SharedPtrBox<FreeMacroBloc>, total_size: Size) -> SharedPtrBox<FreeMacroBloc> {
 		//errors
 		debug_assert!(!bloc.is_null());
 		
 		//extract size
 		let size = bloc.get_total_size();
+		let page_size = bloc.page_size;
 		debug_assert!(size != total_size);
+		debug_assert!(total_size % page_size == 0);
 		
 		//if too small, mremap, otherwise split
 		let ret;
 		if size < total_size {
-			let ptr = osmem::mremap(bloc.get_root_addr(),size,total_size,0);
-			ret = FreeMacroBloc::new(ptr,total_size);
+			let ptr = if page_size == SMALL_PAGE_SIZE {
+				self.os_remap(bloc.get_root_addr(),size,total_size)
+			} else {
+				NULL
+			};
+			if ptr == NULL {
+				self.insert(bloc);
+				return SharedPtrBox::new_null();
+			}
+			self.stats.on_mremap();
+			self.bind_numa(ptr,total_size);
+			ret = FreeMacroBloc::new(ptr,total_size,page_size);
 		} else {
 			//split
-			ret = FreeMacroBloc::new(bloc.get_root_addr(),total_size);
+			ret = FreeMacroBloc::new(bloc.get_root_addr(),total_size,page_size);
 			
 			//point next
 			let next = ret.get_root_addr() + total_size;
 			let next_size = size - total_size;
 
 			//keep next for reuse of return to OS
-			if self.keep_residut && next_size <= self.threashold {
-				let residut = FreeMacroBloc::new(next,next_size);
-				let mut tmp = self.freelist.lock();
-				tmp.list.push_front(residut.clone());
-				tmp.current_size += residut.get().get_total_size();
+			if self.keep_residut && next_size <= self.threashold && next_size >= MACRO_BLOC_SIZE {
+				self.insert(FreeMacroBloc::new(next,next_size,page_size));
 			} else {
 				osmem::munmap(next,next_size);
+				self.stats.on_munmap();
 			}
 		}
 		
@@ -251,20 +606,15 @@ impl CachedMMSource {
 }
 
 impl MemorySource for CachedMMSource {
-	fn map(&mut self,inner_size: Size, _zero_filled: bool, manager: Option<ChunkManagerPtr>) -> (RegionSegmentPtr, bool) {
+	fn map(&mut self,inner_size: Size, zero_filled: bool, manager: Option<ChunkManagerPtr>) -> (RegionSegmentPtr, bool) {
 		//errors
 		debug_assert!(inner_size > 0);
 		
 		//compute total size
-		let mut total_size = inner_size + mem::size_of::<RegionSegment>();
-		
-		//if to small
-		if total_size < REGION_SPLITTING {
-			total_size = REGION_SPLITTING;
-		}
-		
-		//roudn to multiple of page size
-		total_size = ops::up_to_power_of_2(total_size,SMALL_PAGE_SIZE);
+		let total_size = self.get_total_size(inner_size);
+
+		//give back old blocs
+		self.decay_if_needed();
 
 		//manage zero status
 		let mut zero: bool = false;
@@ -272,22 +622,39 @@ impl MemorySource for CachedMMSource {
 
 		//search in cache if smaller than threashold
 		if total_size <= self.threashold {
-			res = self.search_in_cache(total_size,manager.clone());
-			zero = false;
+			match self.search_in_cache(total_size,zero_filled,manager.clone()) {
+				Some((seg,z)) => {
+					res = Some(seg);
+					zero = z;
+				},
+				None => {},
+			}
 		}
 		
 		//if not found of too large, do real mmap
 		if res.is_none() {
-			let ptr = osmem::mmap(0,total_size);
-			//TODO support again
-			//allocCondWarning(ptr != NULL,"Failed to get memory from OS with mmap, maybe get OOM.");
+			let (mut ptr,mut page_size) = self.os_map(total_size);
+
+			//on failure give back the cache to the OS and retry once
+			if ptr == NULL && self.get_cached_size() > 0 {
+				self.free_all();
+				let (retry,retry_page_size) = self.os_map(total_size);
+				ptr = retry;
+				page_size = retry_page_size;
+			}
+
+			//out of memory
+			if ptr == NULL {
+				return (RegionSegmentPtr::new_null(),false);
+			}
+
+			self.stats.on_mmap();
+			self.bind_numa(ptr,total_size);
 			zero = true;
-			
-			//not found
-			//if (ptr == NULL)
-			//    return NULL;
-			//else
-			res = Some(RegionSegment::new(ptr,total_size,manager.clone()));
+			let mut segment = RegionSegment::new(ptr,total_size,manager.clone());
+			segment.set_page_size(page_size);
+			segment.set_numa_node(self.numa_node);
+			res = Some(segment);
 		}
 		
 		//register
@@ -303,11 +670,9 @@ impl MemorySource for CachedMMSource {
 		old_segment.sanity_check();
 		
 		//checkup size
-		let mut total_size = new_inner_size + mem::size_of::<RegionSegment>();
-		if total_size < REGION_SPLITTING {
-			total_size = REGION_SPLITTING;
-		}
-		total_size = ops::up_to_power_of_2(total_size,SMALL_PAGE_SIZE);
+		let total_size = self.get_total_size(new_inner_size);
+		let old_size = old_segment.get_total_size();
+		let old_page_size = old_segment.get_page_size();
 
 		//unregister
 		if self.registry.is_some(){
@@ -317,15 +682,41 @@ impl MemorySource for CachedMMSource {
 			}
 		}
 
-		//remap
-		let ptr = osmem::mremap(old_segment.get_root_addr(),old_segment.get_total_size(),total_size,0);
+		//remap, MAP_HUGETLB segments are copied as mremap() does not handle them everywhere
+		let (ptr,page_size) = if old_page_size == SMALL_PAGE_SIZE {
+			(self.os_remap(old_segment.get_root_addr(),old_size,total_size),SMALL_PAGE_SIZE)
+		} else {
+			self.os_map(total_size)
+		};
+
+		//failure, register back the old one
+		if ptr == NULL {
+			if self.registry.is_some() && old_segment.get_manager().is_some() {
+				self.registry.as_mut().unwrap().set_segment_entry(old_segment);
+			}
+			return RegionSegmentPtr::new_null();
+		}
+
+		//move the content if not done by mremap
+		if old_page_size == SMALL_PAGE_SIZE {
+			self.stats.on_mremap();
+		} else {
+			libc::memcpy(ptr,old_segment.get_root_addr(),if old_size < total_size {old_size} else {total_size});
+			osmem::munmap(old_segment.get_root_addr(),old_size);
+			self.stats.on_mmap();
+			self.stats.on_munmap();
+		}
+		self.bind_numa(ptr,total_size);
 
 		//register
-		if self.registry.is_some() && manager.is_some() {
+		let mut segment = if self.registry.is_some() && manager.is_some() {
 			self.registry.as_mut().unwrap().set_entry(ptr,total_size,manager.unwrap())
 		} else {
 			RegionSegment::new(ptr,total_size,manager)
-		}
+		};
+		segment.set_page_size(page_size);
+		segment.set_numa_node(self.numa_node);
+		segment
 	}
 	
 	fn unmap(&mut self,segment: RegionSegmentPtr) {
@@ -340,14 +731,18 @@ impl MemorySource for CachedMMSource {
 		//if small, keep, other wise unmap
 		//we don't take lock to check current_size as it is fine if we are not strict on it.
 		//This avoid to take twice of to have the lock kept arround syscall munmap.
+		//Blocs bound to another NUMA node, freed after a thread rebind, are not kept.
 		let size = segment.get_total_size();
-		if size > self.threashold || size + self.freelist.nolock_safe_read().current_size > self.max_size {
+		if size > self.threashold || size + self.get_cached_size() > self.max_size || segment.get_numa_node() != self.numa_node {
 			osmem::munmap(segment.get_root_addr(),size);
+			self.stats.on_munmap();
 		} else {
-			let mut tmp = self.freelist.lock();
-			tmp.list.push_front(FreeMacroBloc::new(segment.get_root_addr(),size));
-			tmp.current_size += size;
+			let page_size = segment.get_page_size();
+			self.insert(FreeMacroBloc::new(segment.get_root_addr(),size,page_size));
 		}
+
+		//give back old blocs
+		self.decay_if_needed();
 	}
 }
 
@@ -357,6 +752,7 @@ mod tests
 	use chunk::dummy::*;
 	use registry::registry::*;
 	use mmsource::cached::*;
+	use portability::libc;
 
 	#[test]
 	fn create() {
@@ -429,7 +825,7 @@ mod tests
 		//free & realloc
 		source.unmap(seg);
 
-		let (seg,zeroed) = source.map(4*1024*1024,true,Some(SharedPtrBox::new_ptr_mut(&mut manager)));
+		let (seg,zeroed) = source.map(4*1024*1024,false,Some(SharedPtrBox::new_ptr_mut(&mut manager)));
 		assert_eq!(zeroed,false);
 		assert!(seg.get_inner_size() >= 4*1024*1024);
 		assert_eq!(registry.get_segment(seg.get_root_addr()).is_some(),true);
@@ -438,6 +834,32 @@ mod tests
 		source.unmap(seg);
 	}
 
+	#[test]
+	fn simple_reuse_zero() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source = CachedMMSource::new(Some(SharedPtrBox::new_ref(&registry)),MMSRC_MAX_SIZE,MMSRC_THREASHOLD,MMSRC_KEEP_RESIDUT);
+
+		//allocate & dirty
+		let (seg,_) = source.map(4*1024*1024,false,Some(SharedPtrBox::new_ptr_mut(&mut manager)));
+		let addr = seg.get_root_addr();
+		let content = seg.get_content_addr();
+		let size = seg.get_inner_size();
+		libc::memset(content, 1, size);
+		source.unmap(seg);
+
+		//reuse reset the content
+		let (seg,zeroed) = source.map(4*1024*1024,true,Some(SharedPtrBox::new_ptr_mut(&mut manager)));
+		assert_eq!(zeroed,true);
+		assert_eq!(seg.get_root_addr(),addr);
+		assert_eq!(registry.get_segment(seg.get_root_addr()).is_some(),true);
+		for i in 0..size {
+			assert_eq!(unsafe{*((content + i) as * const u8)}, 0);
+		}
+
+		source.unmap(seg);
+	}
+
 	#[test]
 	fn simple_remap() {
 		let registry = RegionRegistry::new();
@@ -484,7 +906,7 @@ mod tests
 		source.unmap(seg);
 
 		let (seg,zeroed) = source.map(2*1024*1024,true,Some(SharedPtrBox::new_ptr_mut(&mut manager)));
-		assert_eq!(zeroed,false);
+		assert_eq!(zeroed,true);
 		assert!(seg.get_inner_size() >= 2*1024*1024 && seg.get_inner_size() <= 2*1024*1024+SMALL_PAGE_SIZE);
 		assert_eq!(registry.get_segment(seg.get_root_addr()).is_some(),true);
 		assert_eq!(seg.get_root_addr(),addr);
@@ -492,7 +914,7 @@ mod tests
 		source.unmap(seg);
 
 		let (seg,zeroed) = source.map(2*1024*1024,true,Some(SharedPtrBox::new_ptr_mut(&mut manager)));
-		assert_eq!(zeroed,false);
+		assert_eq!(zeroed,true);
 		assert!(seg.get_inner_size() >= 2*1024*1024 && seg.get_inner_size() <= 2*1024*1024+SMALL_PAGE_SIZE);
 		assert_eq!(registry.get_segment(seg.get_root_addr()).is_some(),true);
 
@@ -520,7 +942,7 @@ mod tests
 		source.unmap(seg);
 
 		let (seg,zeroed) = source.map(2*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
-		assert_eq!(zeroed,false);
+		assert_eq!(zeroed,true);
 		assert!(seg.get_inner_size() >= 2*1024*1024 && seg.get_inner_size() <= 2*1024*1024+SMALL_PAGE_SIZE);
 		assert_eq!(registry.get_segment(seg.get_root_addr()).is_some(),true);
 		assert_eq!(seg.get_root_addr(),addr);
@@ -535,4 +957,236 @@ mod tests
 
 		source.free_all();
 	}
+
+	#[test]
+	fn get_bucket() {
+		assert_eq!(CachedMMSource::get_bucket(REGION_SPLITTING), 0);
+		assert_eq!(CachedMMSource::get_bucket(2*MACRO_BLOC_SIZE - SMALL_PAGE_SIZE), 0);
+		assert_eq!(CachedMMSource::get_bucket(4*MACRO_BLOC_SIZE + SMALL_PAGE_SIZE), 3);
+		assert_eq!(CachedMMSource::get_bucket(1024*MACRO_BLOC_SIZE), NB_BUCKETS - 1);
+	}
+
+	#[test]
+	fn reuse_best_fit() {
+		let mut source = CachedMMSource::new(None,64*1024*1024,64*1024*1024,false);
+		let header = mem::size_of::<RegionSegment>();
+
+		//fill the cache with several sizes
+		let (seg2,_) = source.map(2*1024*1024 - header,false,None);
+		let (seg4,_) = source.map(4*1024*1024 - header,false,None);
+		let (seg8,_) = source.map(8*1024*1024 - header,false,None);
+		let (addr4,addr8) = (seg4.get_root_addr(),seg8.get_root_addr());
+		source.unmap(seg2);
+		source.unmap(seg4);
+		source.unmap(seg8);
+		assert_eq!(source.get_cached_size(), 14*1024*1024);
+		let mut details = DetailedReport::new();
+		source.collect_details(&mut details);
+		assert_eq!(details.mmsrc_blocs, 3);
+
+		//exact match
+		let (seg,_) = source.map(4*1024*1024 - header,false,None);
+		assert_eq!(seg.get_root_addr(), addr4);
+		source.unmap(seg);
+
+		//split the nearest larger one instead of enlarging the smaller one
+		let (seg,_) = source.map(6*1024*1024 - header,false,None);
+		assert_eq!(seg.get_root_addr(), addr8);
+		assert_eq!(seg.get_total_size(), 6*1024*1024);
+		assert_eq!(source.get_cached_size(), 6*1024*1024);
+
+		//enlarge when there is nothing larger
+		let (seg4,_) = source.map(4*1024*1024 - header,false,None);
+		let (seg3,_) = source.map(3*1024*1024 - header,false,None);
+		assert_eq!(seg4.get_root_addr(), addr4);
+		assert_eq!(seg3.get_total_size(), 3*1024*1024);
+		assert_eq!(source.get_cached_size(), 0);
+		let mut report = StatsReport::new();
+		source.collect_stats(&mut report);
+		assert_eq!(report.mremap_calls, 1);
+
+		source.unmap(seg);
+		source.unmap(seg3);
+		source.unmap(seg4);
+		source.free_all();
+		assert_eq!(source.get_cached_size(), 0);
+	}
+
+	#[test]
+	fn decay() {
+		let mut source = CachedMMSource::new(None,64*1024*1024,64*1024*1024,false);
+		source.set_decay(1000,2000);
+		let header = mem::size_of::<RegionSegment>();
+
+		//cache two blocs
+		let (seg1,_) = source.map(2*1024*1024 - header,false,None);
+		let (seg2,_) = source.map(4*1024*1024 - header,false,None);
+		let content = seg1.get_content_addr();
+		libc::memset(content, 1, 2*1024*1024 - header);
+		source.unmap(seg1);
+		source.unmap(seg2);
+		let now = libc::get_time_ms();
+
+		//too young
+		assert_eq!(source.decay(now), 0);
+		assert_eq!(source.get_cached_size(), 6*1024*1024);
+
+		//muzzy, still there
+		assert_eq!(source.decay(now + 1000), 0);
+		assert_eq!(source.get_cached_size(), 6*1024*1024);
+		let (seg1,zeroed) = source.map(2*1024*1024 - header,false,None);
+		assert_eq!(zeroed, false);
+		assert_eq!(seg1.get_root_addr(), content - header);
+		libc::memset(content, 2, 2*1024*1024 - header);
+		assert_eq!(unsafe{*((content + SMALL_PAGE_SIZE) as * const u8)}, 2);
+		libc::sleep_ms(2);
+		source.unmap(seg1);
+
+		//returned to the OS, the last one is younger
+		assert_eq!(source.decay(now + 2000), 4*1024*1024);
+		assert_eq!(source.get_cached_size(), 2*1024*1024);
+		let mut details = DetailedReport::new();
+		source.collect_details(&mut details);
+		assert_eq!(details.mmsrc_blocs, 1);
+
+		source.free_all();
+	}
+
+	#[test]
+	fn decay_disabled() {
+		let mut source = CachedMMSource::new(None,64*1024*1024,64*1024*1024,false);
+		let (seg,_) = source.map(2*1024*1024,false,None);
+		source.unmap(seg);
+		assert_eq!(source.decay(libc::get_time_ms() + 1000000), 0);
+		assert_eq!(source.get_cached_size(), 2*1024*1024 + SMALL_PAGE_SIZE);
+		source.free_all();
+	}
+
+	#[test]
+	fn huge_pages_thp() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source = CachedMMSource::new(Some(SharedPtrBox::new_ref(&registry)),MMSRC_MAX_SIZE,MMSRC_THREASHOLD,false);
+		source.set_huge_pages(HugePages::Thp);
+
+		//aligned and rounded
+		let (seg,zeroed) = source.map(3*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(zeroed,true);
+		assert_eq!(seg.get_root_addr() % HUGE_PAGE_SIZE,0);
+		assert_eq!(seg.get_total_size(),4*1024*1024);
+		assert_eq!(seg.get_page_size(),SMALL_PAGE_SIZE);
+		libc::memset(seg.get_content_addr(),1,seg.get_inner_size());
+
+		//enlarge keep the alignement and the content
+		let seg = source.remap(seg,7*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_root_addr() % HUGE_PAGE_SIZE,0);
+		assert_eq!(seg.get_total_size(),8*1024*1024);
+		assert_eq!(unsafe{*((seg.get_content_addr() + 3*1024*1024) as * const u8)},1);
+		assert_eq!(registry.get_segment(seg.get_root_addr()).is_some(),true);
+
+		//reuse by enlarging a cached one
+		source.unmap(seg);
+		let (seg,_) = source.map(9*1024*1024,false,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_root_addr() % HUGE_PAGE_SIZE,0);
+		assert_eq!(seg.get_total_size(),10*1024*1024);
+
+		source.unmap(seg);
+		source.free_all();
+	}
+
+	#[test]
+	fn huge_pages_hugetlb() {
+		let mut manager = DummyChunkManager::new();
+		let mut source = CachedMMSource::new(None,MMSRC_MAX_SIZE,MMSRC_THREASHOLD,false);
+		source.set_huge_pages(HugePages::HugeTlb);
+
+		//fallback on THP if there is no huge pages reserved on the system
+		let (seg,_) = source.map(1024*1024,false,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_root_addr() % HUGE_PAGE_SIZE,0);
+		assert_eq!(seg.get_total_size(),HUGE_PAGE_SIZE);
+		let addr = seg.get_root_addr();
+		let page_size = seg.get_page_size();
+
+		//page size is kept on reuse
+		source.unmap(seg);
+		let (seg,_) = source.map(1024*1024,false,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_root_addr(),addr);
+		assert_eq!(seg.get_page_size(),page_size);
+		unsafe{*(seg.get_content_addr() as * mut u8) = 42};
+
+		//remap keep the content even if copied
+		let seg = source.remap(seg,3*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_total_size(),4*1024*1024);
+		assert_eq!(unsafe{*(seg.get_content_addr() as * const u8)},42);
+
+		source.unmap(seg);
+		source.free_all();
+	}
+
+	#[test]
+	fn map_failure() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source = CachedMMSource::new(Some(SharedPtrBox::new_ref(&registry)),MMSRC_MAX_SIZE,MMSRC_THREASHOLD,MMSRC_KEEP_RESIDUT);
+
+		//too large for the address space
+		let (seg,zeroed) = source.map(1 << 60,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert!(seg.is_null());
+		assert_eq!(zeroed,false);
+
+		//remap failure keep the old segment
+		let (seg,_) = source.map(4*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		let seg2 = source.remap(seg.clone(),1 << 60,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert!(seg2.is_null());
+		assert_eq!(registry.get_segment(seg.get_root_addr()).unwrap().get_root_addr(),seg.get_root_addr());
+
+		source.unmap(seg);
+		source.free_all();
+	}
+
+	#[test]
+	fn numa_map() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source = CachedMMSource::new_numa(Some(SharedPtrBox::new_ref(&registry)),0);
+		assert_eq!(source.get_numa_node(),0);
+
+		//allocate
+		let (seg,zeroed) = source.map(4*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(zeroed,true);
+		assert!(seg.get_inner_size() >= 4*1024*1024);
+
+		//remap
+		let seg = source.remap(seg,8*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert!(seg.get_inner_size() >= 8*1024*1024);
+
+		source.unmap(seg);
+		source.free_all();
+	}
+
+	#[test]
+	fn numa_unmap_other_node() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut node0 = CachedMMSource::new_numa(Some(SharedPtrBox::new_ref(&registry)),0);
+		let mut fallback = CachedMMSource::new_numa(Some(SharedPtrBox::new_ref(&registry)),-1);
+
+		//the node is recorded in the segment
+		let (seg,_) = node0.map(4*1024*1024,false,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_numa_node(),0);
+
+		//blocs of another node are returned to the OS
+		fallback.unmap(seg);
+		assert_eq!(fallback.get_cached_size(),0);
+		let mut report = StatsReport::new();
+		fallback.collect_stats(&mut report);
+		assert_eq!(report.munmap_calls,1);
+
+		//the ones of the same node are kept
+		let (seg,_) = fallback.map(4*1024*1024,false,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_numa_node(),-1);
+		fallback.unmap(seg);
+		assert!(fallback.get_cached_size() > 0);
+		fallback.free_all();
+	}
 }
\ No newline at end of file
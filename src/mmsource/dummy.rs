@@ -56,6 +56,9 @@ impl MemorySource for DummyMMSource {
 		
 		//allocate
 		let ptr = osmem::mmap(0,total_size);
+		if ptr == NULL {
+			return (RegionSegmentPtr::new_null(),false);
+		}
 
 		//register
 		let res;
@@ -92,6 +95,14 @@ impl MemorySource for DummyMMSource {
 		//remap
 		let ptr = osmem::mremap(old_segment.get_root_addr(),old_segment.get_total_size(),total_size,0);
 
+		//failure, register back the old one
+		if ptr == NULL {
+			if self.registry.is_some() && old_segment.has_manager() {
+				self.registry.as_mut().unwrap().set_segment_entry(old_segment);
+			}
+			return RegionSegmentPtr::new_null();
+		}
+
 		//register
 		let res;
 		let has_manager = manager.is_some();
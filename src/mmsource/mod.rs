@@ -10,4 +10,6 @@
 
 //import
 pub mod dummy;
-pub mod cached;
\ No newline at end of file
+pub mod cached;
+pub mod reserved;
+pub mod shared;
\ No newline at end of file
@@ -0,0 +1,533 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+/// Implement a memory source which reserves a large PROT_NONE address range up
+/// front and commits the macro blocs inside it on demand with mprotect(). The
+/// blocs get contiguous and predictable addresses which keep the region registry
+/// densely used, and huge segments can grow in place by commiting the following
+/// blocs if they are free.
+///
+/// The range is tracked by a bitmap of MACRO_BLOC_SIZE blocs. When it is full
+/// we fallback on plain mmap() like the dummy memory source.
+
+//import
+use common::consts::*;
+use common::types::{Addr,Size};
+use common::shared::SharedPtrBox;
+use common::traits::{ChunkManagerPtr,MemorySource};
+use common::ops;
+use common::stats::{MMSourceStats,StatsReport};
+use registry::registry::RegionRegistry;
+use registry::segment::{RegionSegment,RegionSegmentPtr};
+use portability::spinlock::SpinLock;
+use portability::osmem;
+use portability::libc;
+use core::mem;
+
+/// Max number of macro blocs in the reserved range.
+const MAX_BLOCS: usize = MMSRC_RESERVE_MAX_SIZE / MACRO_BLOC_SIZE;
+/// Number of entries in the bitmap.
+const BITMAP_ENTRIES: usize = (MAX_BLOCS + 63) / 64;
+
+/// Track the macro blocs of a range in use. It only store indexes so it can be
+/// placed in memory shared between processes.
+pub struct BlocBitmap {
+	bits: [u64; BITMAP_ENTRIES],
+	/// Number of blocs in the range.
+	blocs: usize,
+}
+
+/// Implement the reserved memory source.
+pub struct ReservedMMSource {
+	/// Base address of the reserved range, NULL if the reservation failed.
+	base: Addr,
+	/// Size of the reserved range.
+	size: Size,
+	/// Blocs in use, protected by a spinlock as the source is shared between threads.
+	blocs: SpinLock<BlocBitmap>,
+	/// Ref to registry to register the new macro blocs before giving them to the caller.
+	registry: Option<SharedPtrBox<RegionRegistry>>,
+	/// Count the calls to the OS.
+	stats: MMSourceStats,
+}
+
+impl BlocBitmap {
+	/// Build an empty bitmap for the given number of blocs.
+	pub fn new(blocs: usize) -> Self {
+		debug_assert!(blocs <= MAX_BLOCS);
+		Self {
+			bits: [0; BITMAP_ENTRIES],
+			blocs: blocs,
+		}
+	}
+
+	/// Check if the given bloc is used.
+	fn is_used(&self, id: usize) -> bool {
+		self.bits[id / 64] & (1 << (id % 64)) != 0
+	}
+
+	/// Check if all the blocs of the given range are free and in the bitmap.
+	pub fn is_free(&self, start: usize, cnt: usize) -> bool {
+		start + cnt <= self.blocs && (start..start+cnt).all(|id| !self.is_used(id))
+	}
+
+	/// Mark the given range as used or free.
+	pub fn mark(&mut self, start: usize, cnt: usize, used: bool) {
+		debug_assert!(start + cnt <= self.blocs);
+		for id in start..start+cnt {
+			if used {
+				self.bits[id / 64] |= 1 << (id % 64);
+			} else {
+				self.bits[id / 64] &= !(1 << (id % 64));
+			}
+		}
+	}
+
+	/// Find the first range of free blocs of the given size, mark it used and
+	/// return its first bloc.
+	pub fn take(&mut self, cnt: usize) -> Option<usize> {
+		debug_assert!(cnt > 0);
+		let mut start = 0;
+		let mut id = 0;
+		while id < self.blocs {
+			//skip full entries
+			if id % 64 == 0 && self.bits[id / 64] == u64::max_value() {
+				id += 64;
+				start = id;
+				continue;
+			}
+
+			//extend or restart the range
+			if self.is_used(id) {
+				start = id + 1;
+			} else if id + 1 - start == cnt {
+				self.mark(start, cnt, true);
+				return Some(start);
+			}
+			id += 1;
+		}
+		None
+	}
+}
+
+impl ReservedMMSource {
+	/// Create a new memory source and reserve its address range.
+	///
+	/// @param registry Define the regisitry to be used for bloc registration. Can be None to ignore.
+	/// @param size Size of the range to reserve, at most MMSRC_RESERVE_MAX_SIZE.
+	pub fn new(registry: Option<SharedPtrBox<RegionRegistry>>, size: Size) -> Self {
+		//check
+		debug_assert!(size <= MMSRC_RESERVE_MAX_SIZE);
+
+		//reserve, on failure we will only use mmap()
+		let mut size = ops::up_to_power_of_2(size, MACRO_BLOC_SIZE);
+		let base = if size > 0 {
+			osmem::reserve_aligned(size, MACRO_BLOC_SIZE)
+		} else {
+			NULL
+		};
+		if base == NULL {
+			size = 0;
+		}
+
+		Self {
+			base: base,
+			size: size,
+			blocs: SpinLock::new(BlocBitmap::new(size / MACRO_BLOC_SIZE)),
+			registry: registry,
+			stats: MMSourceStats::new(),
+		}
+	}
+
+	/// Same than new but reserve the maximal range.
+	pub fn new_default(registry: Option<SharedPtrBox<RegionRegistry>>) -> Self {
+		Self::new(registry, MMSRC_RESERVE_MAX_SIZE)
+	}
+
+	/// Return the base address of the reserved range, NULL if none.
+	pub fn get_base(&self) -> Addr {
+		self.base
+	}
+
+	/// Check if the given segment was taken from the reserved range.
+	pub fn contains(&self, addr: Addr) -> bool {
+		addr >= self.base && addr < self.base + self.size
+	}
+
+	/// Add the counters of the memory source to the report.
+	pub fn collect_stats(&self, report: &mut StatsReport) {
+		self.stats.accumulate(report);
+	}
+
+	/// Give the whole range back to the OS, all the segments must have been unmapped.
+	pub fn release(&mut self) {
+		if self.base != NULL {
+			osmem::munmap(self.base, self.size);
+			self.base = NULL;
+			self.size = 0;
+			*self.blocs.lock() = BlocBitmap::new(0);
+		}
+	}
+
+	/// Compute the size of the segment to map for the given inner size.
+	fn get_total_size(inner_size: Size) -> Size {
+		let total_size = inner_size + mem::size_of::<RegionSegment>();
+		if total_size < REGION_SPLITTING {
+			REGION_SPLITTING
+		} else {
+			ops::up_to_power_of_2(total_size, SMALL_PAGE_SIZE)
+		}
+	}
+
+	/// Number of blocs to use for a segment of the given size.
+	fn get_blocs(total_size: Size) -> usize {
+		(total_size + MACRO_BLOC_SIZE - 1) / MACRO_BLOC_SIZE
+	}
+
+	/// Return the first bloc of the given address.
+	fn get_bloc_id(&self, addr: Addr) -> usize {
+		debug_assert!(self.contains(addr));
+		(addr - self.base) / MACRO_BLOC_SIZE
+	}
+
+	/// Take and commit a range in the reservation. Return NULL if it is full.
+	///
+	/// @param total_size Size to commit.
+	fn commit(&mut self, total_size: Size) -> Addr {
+		//take
+		let cnt = Self::get_blocs(total_size);
+		let id = match self.blocs.lock().take(cnt) {
+			Some(id) => id,
+			None => return NULL,
+		};
+
+		//commit
+		let addr = self.base + id * MACRO_BLOC_SIZE;
+		if !osmem::commit(addr, total_size) {
+			self.blocs.lock().mark(id, cnt, false);
+			return NULL;
+		}
+
+		addr
+	}
+
+	/// Try to resize a segment of the reserved range without moving it.
+	///
+	/// @param addr Address of the segment.
+	/// @param old_size Current size of the segment.
+	/// @param new_size Requested size.
+	fn resize_in_place(&mut self, addr: Addr, old_size: Size, new_size: Size) -> bool {
+		let id = self.get_bloc_id(addr);
+		let old_cnt = Self::get_blocs(old_size);
+		let new_cnt = Self::get_blocs(new_size);
+
+		if new_size <= old_size {
+			//shrink, the blocs are released after the pages
+			if new_size < old_size {
+				osmem::decommit(addr + new_size, old_size - new_size);
+				self.blocs.lock().mark(id + new_cnt, old_cnt - new_cnt, false);
+			}
+			true
+		} else {
+			//grow if the next blocs are free
+			{
+				let mut blocs = self.blocs.lock();
+				if !blocs.is_free(id + old_cnt, new_cnt - old_cnt) {
+					return false;
+				}
+				blocs.mark(id + old_cnt, new_cnt - old_cnt, true);
+			}
+			if !osmem::commit(addr + old_size, new_size - old_size) {
+				self.blocs.lock().mark(id + old_cnt, new_cnt - old_cnt, false);
+				return false;
+			}
+			true
+		}
+	}
+
+	/// Move a segment to another place. Return NULL on failure, then the old
+	/// segment is left untouched. Segments of the reservation are copied as
+	/// unmapping a part of it would let another thread mmap() in the hole
+	/// before we can reserve it again.
+	///
+	/// @param addr Address of the segment.
+	/// @param old_size Current size of the segment.
+	/// @param new_size Requested size.
+	fn move_segment(&mut self, addr: Addr, old_size: Size, new_size: Size) -> Addr {
+		//take a new range in the reservation
+		let mut ptr = self.commit(new_size);
+
+		//out of the reservation on both sides, the kernel can move the pages
+		if ptr == NULL && !self.contains(addr) {
+			ptr = osmem::mremap(addr, old_size, new_size, NULL);
+			if ptr != NULL {
+				self.stats.on_mremap();
+			}
+			return ptr;
+		}
+
+		//fallback on mmap() if the reservation is full
+		if ptr == NULL {
+			ptr = osmem::mmap(0, new_size);
+			if ptr == NULL {
+				return NULL;
+			}
+			self.stats.on_mmap();
+		}
+
+		//copy and release the old one
+		libc::memcpy(ptr, addr, old_size.min(new_size));
+		self.release_segment(addr, old_size);
+
+		ptr
+	}
+
+	/// Decommit a segment and keep the range reserved, or give it back to
+	/// the OS if it is not in the reservation.
+	///
+	/// @param addr Address of the segment.
+	/// @param size Size of the segment.
+	fn release_segment(&mut self, addr: Addr, size: Size) {
+		if self.contains(addr) {
+			osmem::decommit(addr, size);
+			self.blocs.lock().mark(self.get_bloc_id(addr), Self::get_blocs(size), false);
+		} else {
+			osmem::munmap(addr, size);
+			self.stats.on_munmap();
+		}
+	}
+}
+
+impl MemorySource for ReservedMMSource {
+	fn map(&mut self,inner_size: Size, _zero_filled: bool, manager: Option<ChunkManagerPtr>) -> (RegionSegmentPtr, bool) {
+		//errors
+		debug_assert!(inner_size > 0);
+
+		//compute total size
+		let total_size = Self::get_total_size(inner_size);
+
+		//commit in the range or fallback on mmap
+		let mut ptr = self.commit(total_size);
+		if ptr == NULL {
+			ptr = osmem::mmap(0, total_size);
+			if ptr == NULL {
+				return (RegionSegmentPtr::new_null(), false);
+			}
+			self.stats.on_mmap();
+		}
+
+		//register
+		let res = if self.registry.is_some() && manager.is_some() {
+			self.registry.as_mut().unwrap().set_entry(ptr, total_size, manager.unwrap())
+		} else {
+			RegionSegment::new(ptr, total_size, manager)
+		};
+
+		//fresh pages are always zero
+		(res, true)
+	}
+
+	fn remap(&mut self,old_segment: RegionSegmentPtr,new_inner_size: Size, manager: Option<ChunkManagerPtr>) -> RegionSegmentPtr {
+		//errors
+		old_segment.sanity_check();
+
+		//checkup size
+		let total_size = Self::get_total_size(new_inner_size);
+		let old_addr = old_segment.get_root_addr();
+		let old_size = old_segment.get_total_size();
+
+		//unregister
+		if self.registry.is_some() && old_segment.has_manager() {
+			self.registry.as_mut().unwrap().remove_from_segment(old_segment.clone());
+		}
+
+		//grow or shrink in place if possible, otherwise move
+		let ptr = if self.contains(old_addr) && self.resize_in_place(old_addr, old_size, total_size) {
+			old_addr
+		} else {
+			self.move_segment(old_addr, old_size, total_size)
+		};
+
+		//failure, register back the old one
+		if ptr == NULL {
+			if self.registry.is_some() && old_segment.has_manager() {
+				self.registry.as_mut().unwrap().set_segment_entry(old_segment);
+			}
+			return RegionSegmentPtr::new_null();
+		}
+
+		//register
+		if self.registry.is_some() && manager.is_some() {
+			self.registry.as_mut().unwrap().set_entry(ptr, total_size, manager.unwrap())
+		} else {
+			RegionSegment::new(ptr, total_size, manager)
+		}
+	}
+
+	fn unmap(&mut self,segment: RegionSegmentPtr) {
+		//errors
+		segment.sanity_check();
+
+		//unregister
+		if self.registry.is_some() && segment.has_manager() {
+			self.registry.as_mut().unwrap().remove_from_segment(segment.clone());
+		}
+
+		//decommit and keep the range reserved, or give back to the OS
+		self.release_segment(segment.get_root_addr(), segment.get_total_size());
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use mmsource::reserved::*;
+	use chunk::dummy::*;
+	use chunk::huge::HugeChunkManager;
+	use common::traits::ChunkManager;
+	use portability::libc;
+
+	#[test]
+	fn bitmap() {
+		let mut bitmap = BlocBitmap::new(130);
+		assert_eq!(bitmap.take(2), Some(0));
+		assert_eq!(bitmap.take(1), Some(2));
+		bitmap.mark(0, 2, false);
+		assert_eq!(bitmap.take(3), Some(3));
+		assert_eq!(bitmap.take(2), Some(0));
+		assert!(bitmap.is_free(6, 124));
+		assert!(!bitmap.is_free(6, 125));
+		assert_eq!(bitmap.take(124), Some(6));
+		assert_eq!(bitmap.take(1), None);
+	}
+
+	#[test]
+	fn contiguous() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source = ReservedMMSource::new(Some(SharedPtrBox::new_ref(&registry)), 64*1024*1024);
+		let base = source.get_base();
+		assert!(base != NULL);
+		assert_eq!(base % MACRO_BLOC_SIZE, 0);
+
+		//one after the other
+		let (seg1,zeroed) = source.map(1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(zeroed, true);
+		let (seg2,_) = source.map(3*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		let (seg3,_) = source.map(1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg1.get_root_addr(), base);
+		assert_eq!(seg2.get_root_addr(), base + MACRO_BLOC_SIZE);
+		assert_eq!(seg3.get_root_addr(), base + 3*MACRO_BLOC_SIZE);
+		assert_eq!(registry.get_segment(seg2.get_root_addr()).is_some(), true);
+
+		//the hole is reused and reset
+		libc::memset(seg2.get_content_addr(), 1, seg2.get_inner_size());
+		source.unmap(seg2);
+		let (seg2,_) = source.map(3*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg2.get_root_addr(), base + MACRO_BLOC_SIZE);
+		assert_eq!(unsafe{*((seg2.get_content_addr() + 1024*1024) as * const u8)}, 0);
+
+		source.unmap(seg1);
+		source.unmap(seg2);
+		source.unmap(seg3);
+		source.release();
+	}
+
+	#[test]
+	fn remap_in_place() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source = ReservedMMSource::new(Some(SharedPtrBox::new_ref(&registry)), 64*1024*1024);
+		let base = source.get_base();
+
+		//grow
+		let (seg,_) = source.map(1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		unsafe{*(seg.get_content_addr() as * mut u8) = 42};
+		let seg = source.remap(seg,5*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_root_addr(), base);
+		assert!(seg.get_inner_size() >= 5*1024*1024);
+		assert_eq!(unsafe{*(seg.get_content_addr() as * const u8)}, 42);
+		unsafe{*((seg.get_content_addr() + 5*1024*1024 - 1) as * mut u8) = 1};
+		assert_eq!(registry.get_segment(base + 4*1024*1024).unwrap().get_root_addr(), base);
+
+		//shrink give back the end
+		let seg = source.remap(seg,1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_root_addr(), base);
+		let (seg2,_) = source.map(1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg2.get_root_addr(), base + MACRO_BLOC_SIZE);
+
+		//next one is used so move
+		let seg = source.remap(seg,3*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg.get_root_addr(), base + 2*MACRO_BLOC_SIZE);
+		assert_eq!(unsafe{*(seg.get_content_addr() as * const u8)}, 42);
+		assert!(registry.get_segment(base).is_none());
+
+		//old place is reusable
+		let (seg3,_) = source.map(1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg3.get_root_addr(), base);
+		assert_eq!(unsafe{*(seg3.get_content_addr() as * const u8)}, 0);
+
+		source.unmap(seg);
+		source.unmap(seg2);
+		source.unmap(seg3);
+		source.release();
+	}
+
+	#[test]
+	fn fallback_when_full() {
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source = ReservedMMSource::new(Some(SharedPtrBox::new_ref(&registry)), 4*1024*1024);
+		let base = source.get_base();
+
+		let (seg1,_) = source.map(3*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		let (seg2,zeroed) = source.map(1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg1.get_root_addr(), base);
+		assert_eq!(zeroed, true);
+		assert!(!source.contains(seg2.get_root_addr()));
+		assert_eq!(registry.get_segment(seg2.get_root_addr()).is_some(), true);
+
+		//move out of the range
+		unsafe{*(seg1.get_content_addr() as * mut u8) = 42};
+		let seg1 = source.remap(seg1,8*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert!(!source.contains(seg1.get_root_addr()));
+		assert_eq!(unsafe{*(seg1.get_content_addr() as * const u8)}, 42);
+
+		//the range is free again
+		let (seg3,_) = source.map(3*1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(seg3.get_root_addr(), base);
+
+		//out of the range on both sides the kernel move it
+		let seg1 = source.remap(seg1,12*1024*1024,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert!(!source.contains(seg1.get_root_addr()));
+		assert_eq!(unsafe{*(seg1.get_content_addr() as * const u8)}, 42);
+
+		let mut report = StatsReport::new();
+		source.collect_stats(&mut report);
+		assert_eq!(report.mmap_calls, 2);
+		assert_eq!(report.mremap_calls, 1);
+
+		source.unmap(seg1);
+		source.unmap(seg2);
+		source.unmap(seg3);
+		source.release();
+	}
+
+	#[test]
+	fn huge_realloc_in_place() {
+		let mut registry = RegionRegistry::new();
+		let mut source = ReservedMMSource::new(Some(SharedPtrBox::new_ref_mut(&mut registry)), 64*1024*1024);
+		let mut huge = HugeChunkManager::new(SharedPtrBox::new_ref_mut(&mut source));
+
+		let (ptr,_) = huge.malloc(4*1024*1024, BASIC_ALIGN, false);
+		let ptr2 = huge.realloc(ptr, 16*1024*1024);
+		assert_eq!(ptr, ptr2);
+		assert!(huge.get_inner_size(ptr2) >= 16*1024*1024);
+		huge.free(ptr2);
+	}
+}
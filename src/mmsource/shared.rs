@@ -0,0 +1,503 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+/// Implement a memory source backed by a memory file (memfd_create() or shm_open())
+/// so several processes, eg. the MPI ranks of a node, can map the same heap and
+/// exchange buffers by offset without copies.
+///
+/// The first macro bloc of the file holds a header shared by all the processes
+/// with a process shared spinlock and the bitmap of the blocs in use. It only
+/// contains indexes as each process maps the file at its own address. The
+/// segment headers written at the beginning of the blocs keep addresses local
+/// to the process which mapped them, the others must only access the content
+/// through get_offset() and get_addr().
+
+//import
+use common::consts::*;
+use common::types::{Addr,Size};
+use common::shared::SharedPtrBox;
+use common::traits::{ChunkManagerPtr,MemorySource};
+use common::ops;
+use registry::registry::RegionRegistry;
+use registry::segment::{RegionSegment,RegionSegmentPtr};
+use mmsource::reserved::BlocBitmap;
+use portability::spinlock::SpinLock;
+use portability::osmem;
+use portability::shm;
+use portability::libc;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Written at the end of the header setup so the other processes know it is ready.
+const SHARED_MAGIC: usize = 0x4850_4353_484d;
+/// Milliseconds to wait for the process creating the file to set it up.
+const SHARED_SETUP_TIMEOUT: Size = 1000;
+
+/// Header placed at the beginning of the memory file.
+struct SharedHeader {
+	magic: AtomicUsize,
+	size: Size,
+	blocs: SpinLock<BlocBitmap>,
+}
+
+/// Implement the shared memory source.
+pub struct SharedMMSource {
+	/// Header of the file in our mapping.
+	header: SharedPtrBox<SharedHeader>,
+	/// Base address of our mapping of the file.
+	base: Addr,
+	/// Size of the file.
+	size: Size,
+	/// File descriptor of the memory file.
+	fd: i32,
+	/// Ref to registry to register the new macro blocs before giving them to the caller.
+	registry: Option<SharedPtrBox<RegionRegistry>>,
+}
+
+impl SharedMMSource {
+	/// Create a new heap in an anonymous memory file. Other processes can use it
+	/// with from_fd() after inheriting or receiving the file descriptor.
+	///
+	/// @param registry Define the regisitry to be used for bloc registration. Can be None to ignore.
+	/// @param size Size of the heap, at most MMSRC_RESERVE_MAX_SIZE.
+	pub fn new_memfd(registry: Option<SharedPtrBox<RegionRegistry>>, size: Size) -> Option<Self> {
+		let fd = shm::memfd_create(b"hpc_allocator\0");
+		if fd < 0 {
+			return None;
+		}
+		if !shm::set_size(fd, ops::up_to_power_of_2(size, MACRO_BLOC_SIZE)) {
+			shm::close(fd);
+			return None;
+		}
+		Self::attach(registry, fd, true)
+	}
+
+	/// Open the heap stored in the given POSIX shared memory object, the first
+	/// process create it with the given size. It must be removed with
+	/// shm::shm_unlink() when not needed anymore.
+	///
+	/// @param registry Define the regisitry to be used for bloc registration. Can be None to ignore.
+	/// @param name Name of the object starting with '/', it must be NULL terminated.
+	/// @param size Size of the heap if we create it, at most MMSRC_RESERVE_MAX_SIZE.
+	pub fn open_shm(registry: Option<SharedPtrBox<RegionRegistry>>, name: &[u8], size: Size) -> Option<Self> {
+		//first one create it
+		let mut fd = shm::shm_open(name, true);
+		let create = fd >= 0;
+		if create {
+			if !shm::set_size(fd, ops::up_to_power_of_2(size, MACRO_BLOC_SIZE)) {
+				shm::close(fd);
+				shm::shm_unlink(name);
+				return None;
+			}
+		} else {
+			fd = shm::shm_open(name, false);
+			if fd < 0 {
+				return None;
+			}
+		}
+		Self::attach(registry, fd, create)
+	}
+
+	/// Open the heap stored in a memory file created by another process. The
+	/// file descriptor is closed by close().
+	///
+	/// @param registry Define the regisitry to be used for bloc registration. Can be None to ignore.
+	/// @param fd File descriptor of the memory file.
+	pub fn from_fd(registry: Option<SharedPtrBox<RegionRegistry>>, fd: i32) -> Option<Self> {
+		Self::attach(registry, fd, false)
+	}
+
+	/// Map the memory file and setup or check the header.
+	///
+	/// @param registry Define the regisitry to be used for bloc registration. Can be None to ignore.
+	/// @param fd File descriptor of the memory file.
+	/// @param create Setup the header as we created the file.
+	fn attach(registry: Option<SharedPtrBox<RegionRegistry>>, fd: i32, create: bool) -> Option<Self> {
+		//the creator might not have set the size yet
+		let deadline = libc::get_time_ms() + SHARED_SETUP_TIMEOUT;
+		let mut size = shm::get_size(fd);
+		while size == 0 && libc::get_time_ms() < deadline {
+			libc::sleep_ms(1);
+			size = shm::get_size(fd);
+		}
+
+		//need at least one bloc after the header
+		if size < 2 * MACRO_BLOC_SIZE || size % MACRO_BLOC_SIZE != 0 || size > MMSRC_RESERVE_MAX_SIZE {
+			shm::close(fd);
+			return None;
+		}
+
+		//map aligned on macro blocs
+		let base = osmem::reserve_aligned(size, MACRO_BLOC_SIZE);
+		if base == NULL || osmem::mmap_shared(base, size, fd, 0) == NULL {
+			if base != NULL {
+				osmem::munmap(base, size);
+			}
+			shm::close(fd);
+			return None;
+		}
+
+		//setup or wait for the creator
+		let mut header: SharedPtrBox<SharedHeader> = SharedPtrBox::new_addr(base);
+		if create {
+			*header.get_mut() = SharedHeader {
+				magic: AtomicUsize::new(0),
+				size: size,
+				blocs: SpinLock::new_shared(BlocBitmap::new(size / MACRO_BLOC_SIZE)),
+			};
+			header.blocs.lock().mark(0, 1, true);
+			header.magic.store(SHARED_MAGIC, Ordering::Release);
+		} else {
+			while header.magic.load(Ordering::Acquire) != SHARED_MAGIC && libc::get_time_ms() < deadline {
+				libc::sleep_ms(1);
+			}
+			if header.magic.load(Ordering::Acquire) != SHARED_MAGIC || header.size != size {
+				osmem::munmap(base, size);
+				shm::close(fd);
+				return None;
+			}
+		}
+
+		Some(Self {
+			header: header,
+			base: base,
+			size: size,
+			fd: fd,
+			registry: registry,
+		})
+	}
+
+	/// Unmap the heap and close the file, all the segments must have been unmapped
+	/// by this process. The memory is freed once all the processes closed it.
+	pub fn close(&mut self) {
+		if self.base != NULL {
+			osmem::munmap(self.base, self.size);
+			shm::close(self.fd);
+			self.header = SharedPtrBox::new_null();
+			self.base = NULL;
+			self.fd = -1;
+		}
+	}
+
+	/// Return the file descriptor to be passed to other processes.
+	pub fn get_fd(&self) -> i32 {
+		self.fd
+	}
+
+	/// Check if the given address is in our mapping of the heap.
+	pub fn contains(&self, addr: Addr) -> bool {
+		addr >= self.base && addr < self.base + self.size
+	}
+
+	/// Convert an address of our mapping into an offset valid in all the processes.
+	pub fn get_offset(&self, addr: Addr) -> Size {
+		debug_assert!(self.contains(addr));
+		addr - self.base
+	}
+
+	/// Convert an offset received from another process into an address of our mapping.
+	pub fn get_addr(&self, offset: Size) -> Addr {
+		debug_assert!(offset < self.size);
+		self.base + offset
+	}
+
+	/// Compute the size of the segment to map for the given inner size.
+	fn get_total_size(inner_size: Size) -> Size {
+		let total_size = inner_size + mem::size_of::<RegionSegment>();
+		if total_size < REGION_SPLITTING {
+			REGION_SPLITTING
+		} else {
+			ops::up_to_power_of_2(total_size, SMALL_PAGE_SIZE)
+		}
+	}
+
+	/// Number of blocs to use for a segment of the given size.
+	fn get_blocs(total_size: Size) -> usize {
+		(total_size + MACRO_BLOC_SIZE - 1) / MACRO_BLOC_SIZE
+	}
+
+	/// Return the first bloc of the given address.
+	fn get_bloc_id(&self, addr: Addr) -> usize {
+		self.get_offset(addr) / MACRO_BLOC_SIZE
+	}
+
+	/// Take a free range in the heap, NULL if full.
+	///
+	/// @param total_size Size to take.
+	fn take(&self, total_size: Size) -> Addr {
+		match self.header.blocs.lock().take(Self::get_blocs(total_size)) {
+			Some(id) => self.base + id * MACRO_BLOC_SIZE,
+			None => NULL,
+		}
+	}
+
+	/// Free the pages of a segment in the file and give back its blocs.
+	///
+	/// @param addr Address of the segment.
+	/// @param size Size of the segment.
+	fn release(&self, addr: Addr, size: Size) {
+		osmem::madvise_remove(addr, size);
+		self.header.blocs.lock().mark(self.get_bloc_id(addr), Self::get_blocs(size), false);
+	}
+
+	/// Try to resize a segment without moving it.
+	///
+	/// @param addr Address of the segment.
+	/// @param old_size Current size of the segment.
+	/// @param new_size Requested size.
+	fn resize_in_place(&self, addr: Addr, old_size: Size, new_size: Size) -> bool {
+		let id = self.get_bloc_id(addr);
+		let old_cnt = Self::get_blocs(old_size);
+		let new_cnt = Self::get_blocs(new_size);
+
+		if new_size < old_size {
+			//shrink, the pages are removed before giving the blocs
+			osmem::madvise_remove(addr + new_size, old_size - new_size);
+			self.header.blocs.lock().mark(id + new_cnt, old_cnt - new_cnt, false);
+			true
+		} else {
+			//grow if the next blocs are free
+			let mut blocs = self.header.blocs.lock();
+			if blocs.is_free(id + old_cnt, new_cnt - old_cnt) {
+				blocs.mark(id + old_cnt, new_cnt - old_cnt, true);
+				true
+			} else {
+				false
+			}
+		}
+	}
+}
+
+impl MemorySource for SharedMMSource {
+	fn map(&mut self,inner_size: Size, _zero_filled: bool, manager: Option<ChunkManagerPtr>) -> (RegionSegmentPtr, bool) {
+		//errors
+		debug_assert!(inner_size > 0);
+
+		//take, we cannot fallback on private memory
+		let total_size = Self::get_total_size(inner_size);
+		let ptr = self.take(total_size);
+		if ptr == NULL {
+			return (RegionSegmentPtr::new_null(), false);
+		}
+
+		//register
+		let mut res = if self.registry.is_some() && manager.is_some() {
+			self.registry.as_mut().unwrap().set_entry(ptr, total_size, manager.unwrap())
+		} else {
+			RegionSegment::new(ptr, total_size, manager)
+		};
+		res.set_shared();
+
+		//pages are removed from the file when released
+		(res, true)
+	}
+
+	fn remap(&mut self,old_segment: RegionSegmentPtr,new_inner_size: Size, manager: Option<ChunkManagerPtr>) -> RegionSegmentPtr {
+		//errors
+		old_segment.sanity_check();
+
+		//checkup size
+		let total_size = Self::get_total_size(new_inner_size);
+		let old_addr = old_segment.get_root_addr();
+		let old_size = old_segment.get_total_size();
+
+		//unregister
+		if self.registry.is_some() && old_segment.has_manager() {
+			self.registry.as_mut().unwrap().remove_from_segment(old_segment.clone());
+		}
+
+		//resize in place or copy as mremap() would not move the content in the file
+		let ptr = if self.resize_in_place(old_addr, old_size, total_size) {
+			old_addr
+		} else {
+			let ptr = self.take(total_size);
+			if ptr != NULL {
+				libc::memcpy(ptr, old_addr, if old_size < total_size {old_size} else {total_size});
+				self.release(old_addr, old_size);
+			}
+			ptr
+		};
+
+		//failure, register back the old one
+		if ptr == NULL {
+			if self.registry.is_some() && old_segment.has_manager() {
+				self.registry.as_mut().unwrap().set_segment_entry(old_segment);
+			}
+			return RegionSegmentPtr::new_null();
+		}
+
+		//register
+		let mut res = if self.registry.is_some() && manager.is_some() {
+			self.registry.as_mut().unwrap().set_entry(ptr, total_size, manager.unwrap())
+		} else {
+			RegionSegment::new(ptr, total_size, manager)
+		};
+		res.set_shared();
+		res
+	}
+
+	fn unmap(&mut self,segment: RegionSegmentPtr) {
+		//errors
+		segment.sanity_check();
+
+		//unregister
+		if self.registry.is_some() && segment.has_manager() {
+			self.registry.as_mut().unwrap().remove_from_segment(segment.clone());
+		}
+
+		//free the pages for all the processes
+		self.release(segment.get_root_addr(), segment.get_total_size());
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	extern crate libc;
+
+	use mmsource::shared::*;
+	use chunk::dummy::*;
+	use chunk::small::manager::SmallChunkManager;
+	use common::traits::{ChunkManager,MemorySourcePtr};
+
+	#[test]
+	fn two_mappings() {
+		let name = b"/hpc_allocator_test_two_mappings\0";
+		shm::shm_unlink(name);
+		let registry = RegionRegistry::new();
+		let mut manager = DummyChunkManager::new();
+		let mut source1 = SharedMMSource::open_shm(Some(SharedPtrBox::new_ref(&registry)), name, 16*1024*1024).unwrap();
+		let mut source2 = SharedMMSource::open_shm(None, name, 0).unwrap();
+		assert!(shm::shm_unlink(name));
+
+		//each one get its own bloc
+		let (seg1,zeroed) = source1.map(1024*1024,true,Some(SharedPtrBox::new_ref_mut(&mut manager)));
+		assert_eq!(zeroed, true);
+		assert_eq!(registry.get_segment(seg1.get_root_addr()).is_some(), true);
+		let (seg2,_) = source2.map(1024*1024,true,None);
+		assert_eq!(source1.get_offset(seg1.get_root_addr()), MACRO_BLOC_SIZE);
+		assert_eq!(source2.get_offset(seg2.get_root_addr()), 2*MACRO_BLOC_SIZE);
+
+		//exchange by offset
+		unsafe{*(seg1.get_content_addr() as * mut u64) = 42};
+		let offset = source1.get_offset(seg1.get_content_addr());
+		assert_eq!(unsafe{*(source2.get_addr(offset) as * const u64)}, 42);
+
+		//freed for both
+		source1.unmap(seg1);
+		assert_eq!(unsafe{*(source2.get_addr(offset) as * const u64)}, 0);
+		let (seg1,_) = source2.map(1024*1024,true,None);
+		assert_eq!(source2.get_offset(seg1.get_root_addr()), MACRO_BLOC_SIZE);
+
+		source2.unmap(seg1);
+		source2.unmap(seg2);
+		source1.close();
+		source2.close();
+	}
+
+	#[test]
+	fn remap() {
+		let mut source = SharedMMSource::new_memfd(None, 16*1024*1024).unwrap();
+
+		//grow in place
+		let (seg,_) = source.map(1024*1024,true,None);
+		let addr = seg.get_root_addr();
+		unsafe{*(seg.get_content_addr() as * mut u8) = 42};
+		let seg = source.remap(seg,3*1024*1024,None);
+		assert_eq!(seg.get_root_addr(), addr);
+
+		//shrink give back the end
+		let seg = source.remap(seg,1024*1024,None);
+		assert_eq!(seg.get_root_addr(), addr);
+		let (seg2,_) = source.map(1024*1024,true,None);
+		assert_eq!(seg2.get_root_addr(), addr + MACRO_BLOC_SIZE);
+
+		//move by copy
+		let seg = source.remap(seg,3*1024*1024,None);
+		assert_eq!(seg.get_root_addr(), addr + 2*MACRO_BLOC_SIZE);
+		assert_eq!(unsafe{*(seg.get_content_addr() as * const u8)}, 42);
+		let (seg3,_) = source.map(1024*1024,true,None);
+		assert_eq!(seg3.get_root_addr(), addr);
+		assert_eq!(unsafe{*(seg3.get_content_addr() as * const u8)}, 0);
+
+		//no room
+		let (seg4,_) = source.map(10*1024*1024,true,None);
+		assert!(seg4.is_null());
+		assert!(source.remap(seg.clone(),10*1024*1024,None).is_null());
+		assert_eq!(unsafe{*(seg.get_content_addr() as * const u8)}, 42);
+
+		source.unmap(seg);
+		source.unmap(seg2);
+		source.unmap(seg3);
+		source.close();
+	}
+
+	#[test]
+	fn other_process() {
+		let mut source = SharedMMSource::new_memfd(None, 16*1024*1024).unwrap();
+		let fd = source.get_fd();
+
+		//the child map the file again as an unrelated process would do
+		let pid = unsafe{libc::fork()};
+		assert!(pid >= 0);
+		if pid == 0 {
+			let status = match SharedMMSource::from_fd(None, unsafe{libc::dup(fd)}) {
+				Some(mut child) => {
+					let (seg,_) = child.map(4*1024*1024,true,None);
+					unsafe{*(seg.get_content_addr() as * mut u64) = 42};
+					(child.get_offset(seg.get_content_addr()) / MACRO_BLOC_SIZE) as i32
+				},
+				None => 0,
+			};
+			unsafe{libc::_exit(status)};
+		}
+
+		//wait
+		let mut status = 0;
+		assert_eq!(unsafe{libc::waitpid(pid, &mut status, 0)}, pid);
+		assert!(unsafe{libc::WIFEXITED(status)});
+		assert_eq!(unsafe{libc::WEXITSTATUS(status)}, 1);
+
+		//get by offset and the blocs are taken
+		let offset = MACRO_BLOC_SIZE + mem::size_of::<RegionSegment>();
+		assert_eq!(unsafe{*(source.get_addr(offset) as * const u64)}, 42);
+		let (seg,_) = source.map(1024*1024,true,None);
+		assert_eq!(source.get_offset(seg.get_root_addr()), 4*MACRO_BLOC_SIZE);
+
+		source.unmap(seg);
+		source.close();
+	}
+
+	#[test]
+	fn small_runs_not_purged() {
+		let mut source = SharedMMSource::new_memfd(None, 16*1024*1024).unwrap();
+		let mut manager = SmallChunkManager::new(true, Some(MemorySourcePtr::new_ref(&source)));
+
+		//keep one to not release the container
+		let (keep,_) = manager.malloc(16, BASIC_ALIGN, false);
+		let mut ptrs = [NULL; 4096];
+		for ptr in ptrs.iter_mut() {
+			*ptr = manager.malloc(16, BASIC_ALIGN, false).0;
+			unsafe{libc::memset(*ptr as * mut libc::c_void, 0xFF, 16)};
+		}
+		for ptr in ptrs.iter() {
+			manager.free(*ptr);
+		}
+
+		//MADV_DONTNEED would keep the content of the file
+		assert_eq!(manager.trim(), 0);
+		let (ptr,zeroed) = manager.malloc(128, BASIC_ALIGN, true);
+		assert!(ptr != NULL);
+		assert_eq!(zeroed, false);
+		manager.free(ptr);
+		manager.free(keep);
+
+		manager.trim();
+		source.close();
+	}
+}
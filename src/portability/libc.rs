@@ -23,3 +23,109 @@ pub fn memcpy(to: Addr, from: Addr, size: Size) {
 pub fn memset(ptr: Addr, value:  i32, size: Size) {
 	unsafe{libc::memset(ptr as * mut libc::c_void,value,size);}
 }
+
+/// wrapper to getenv, return None if the variable is not defined.
+///
+/// @param name Name of the variable, it must be NULL terminated.
+pub fn getenv(name: &[u8]) -> Option<&'static [u8]> {
+	debug_assert!(name.last() == Some(&0));
+	unsafe {
+		let ptr = libc::getenv(name.as_ptr() as * const libc::c_char);
+		if ptr.is_null() {
+			None
+		} else {
+			Some(core::slice::from_raw_parts(ptr as * const u8, libc::strlen(ptr)))
+		}
+	}
+}
+
+/// Write a message on the error output without allocating memory.
+pub fn write_stderr(msg: &[u8]) {
+	unsafe{libc::write(2, msg.as_ptr() as * const libc::c_void, msg.len())};
+}
+
+/// wrapper to abort
+pub fn abort() -> ! {
+	unsafe{libc::abort()}
+}
+
+/// Error code for out of memory.
+pub const ENOMEM: i32 = libc::ENOMEM;
+
+/// Error code for invalid argument.
+pub const EINVAL: i32 = libc::EINVAL;
+
+/// Set the errno of the current thread.
+pub fn set_errno(value: i32) {
+	unsafe{*libc::__errno_location() = value};
+}
+
+/// Return the errno of the current thread.
+pub fn get_errno() -> i32 {
+	unsafe{*libc::__errno_location()}
+}
+
+/// Return the time of a monotonic clock in milliseconds.
+pub fn get_time_ms() -> Size {
+	let mut ts = libc::timespec{tv_sec: 0, tv_nsec: 0};
+	unsafe{libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts)};
+	ts.tv_sec as Size * 1000 + ts.tv_nsec as Size / 1000000
+}
+
+/// Make the current thread sleep for the given number of milliseconds.
+pub fn sleep_ms(ms: Size) {
+	let ts = libc::timespec{tv_sec: (ms / 1000) as libc::time_t, tv_nsec: ((ms % 1000) * 1000000) as libc::c_long};
+	unsafe{libc::nanosleep(&ts, 0 as * mut libc::timespec)};
+}
+
+/// Formatter output to a file descriptor, usable without allocating memory.
+pub struct FdWriter {
+	fd: i32,
+}
+
+impl FdWriter {
+	pub fn new(fd: i32) -> Self {
+		Self {
+			fd: fd,
+		}
+	}
+}
+
+impl core::fmt::Write for FdWriter {
+	fn write_str(&mut self, s: &str) -> core::fmt::Result {
+		//loop as write can be partial
+		let mut done = 0;
+		while done < s.len() {
+			let res = unsafe{libc::write(self.fd, s[done..].as_ptr() as * const libc::c_void, s.len() - done)};
+			if res <= 0 {
+				return Err(core::fmt::Error);
+			}
+			done += res as usize;
+		}
+		Ok(())
+	}
+}
+
+/// Formatter output to a C FILE stream.
+pub struct FileWriter {
+	file: * mut libc::FILE,
+}
+
+impl FileWriter {
+	pub fn new(file: * mut libc::FILE) -> Self {
+		Self {
+			file: file,
+		}
+	}
+}
+
+impl core::fmt::Write for FileWriter {
+	fn write_str(&mut self, s: &str) -> core::fmt::Result {
+		let res = unsafe{libc::fwrite(s.as_ptr() as * const libc::c_void, 1, s.len(), self.file)};
+		if res != s.len() {
+			Err(core::fmt::Error)
+		} else {
+			Ok(())
+		}
+	}
+}
@@ -12,14 +12,18 @@
 //import
 extern crate libc;
 
+//import
+use common::types::{Addr,Size};
+
 // requiered functions
 extern {
 	fn numa_num_task_nodes() -> libc::c_int;
 	fn numa_preferred() -> libc::c_int;
+	fn numa_tonode_memory(start: * mut libc::c_void, size: libc::size_t, node: libc::c_int);
 }
 
 /// Determine the best NUMA node for the current task. Return -1 if there is
-/// multiple NUMA node the task can run on.
+/// multiple NUMA node the task can run on or if we cannot determine it.
 pub fn numa_detect_affinity() -> i32 {
 	// if more than one node
 	if unsafe{numa_num_task_nodes()} > 1 {
@@ -32,8 +36,19 @@ pub fn numa_detect_affinity() -> i32 {
 		return preferred;
 	}
 
-	//fail
-	panic!("Preferred node is -1, cannot determine which NUMA node to use !");
+	//fail, consider as unbound
+	-1
+}
+
+/// Bind the given memory range to a NUMA node so pages are placed on it
+/// on first touch.
+pub fn numa_bind_memory(addr: Addr, size: Size, node: i32) {
+	//errors
+	debug_assert!(addr != 0);
+	debug_assert!(node >= 0);
+
+	//call
+	unsafe{numa_tonode_memory(addr as * mut libc::c_void, size, node)};
 }
 
 #[cfg(test)]
@@ -41,6 +56,7 @@ mod tests
 {
 	extern crate std;
 	use portability::libnuma::*;
+	use portability::osmem;
 
 	#[test]
 	fn test_numa_preferred() {
@@ -57,4 +73,12 @@ mod tests
 		let res = numa_detect_affinity();
 		assert!(res > -1);
 	}
+
+	#[test]
+	fn test_numa_bind_memory() {
+		let ptr = osmem::mmap(0, 2*1024*1024);
+		numa_bind_memory(ptr, 2*1024*1024, 0);
+		unsafe{*(ptr as * mut u8) = 1};
+		osmem::munmap(ptr, 2*1024*1024);
+	}
 }
@@ -0,0 +1,42 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+///Import the few functions of the C++ runtime needed to implement the nothrow
+///C++ operator new semantic. They are linked as weak symbols so the allocator can
+///still be loaded in pure C programs where libstdc++ is not present.
+
+/// Function registered with std::set_new_handler().
+pub type NewHandler = extern "C-unwind" fn();
+
+// requiered functions, NULL if libstdc++ is not loaded
+extern "C" {
+	/// std::get_new_handler()
+	#[linkage = "extern_weak"]
+	static _ZSt15get_new_handlerv: Option<unsafe extern "C" fn() -> Option<NewHandler>>;
+}
+
+/// Return the handler registered with std::set_new_handler(), None if there
+/// is none or if libstdc++ is not loaded.
+pub fn get_new_handler() -> Option<NewHandler> {
+	match unsafe{_ZSt15get_new_handlerv} {
+		Some(func) => unsafe{func()},
+		None => None,
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use portability::libstdcxx::*;
+
+	#[test]
+	fn test_get_new_handler() {
+		//never registered
+		assert!(get_new_handler().is_none());
+	}
+}
@@ -14,4 +14,6 @@ pub mod spinlock;
 pub mod arch;
 pub mod libc;
 pub mod libnuma;
+pub mod libstdcxx;
+pub mod shm;
 //pub mod hwloc;
\ No newline at end of file
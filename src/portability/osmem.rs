@@ -14,8 +14,9 @@ extern crate libc;
 //import
 use common::types::{Addr,Size};
 use common::consts::*;
+use common::ops;
 
-///wrapper to mmap function
+///wrapper to mmap function, return NULL if the OS cannot provide the memory.
 pub fn mmap(addr:Addr,size:Size) -> Addr
 {
 	//check
@@ -35,13 +36,159 @@ pub fn mmap(addr:Addr,size:Size) -> Addr
 	
 	//check error
 	if res == libc::MAP_FAILED {
-		//TODO REPLACE BY WARNING
-		panic!("Out of memory, failed to request memory to the OS via mmap.");
+		return NULL;
 	}
 
 	res as Addr
 }
 
+///Map memory aligned on the given power of 2 by mapping more then unmapping
+///the extra parts. Return NULL if the OS cannot provide the memory.
+pub fn mmap_aligned(size:Size,align:Size) -> Addr {
+	//check
+	debug_assert!(ops::is_power_of_2(align));
+
+	//trivial
+	if align <= SMALL_PAGE_SIZE {
+		return mmap(0,size);
+	}
+
+	//map more
+	let total = size + align - SMALL_PAGE_SIZE;
+	let ptr = mmap(0,total);
+	if ptr == NULL {
+		return NULL;
+	}
+
+	trim_aligned(ptr,total,size,align)
+}
+
+///Unmap the head and tail of a mapping to keep only the aligned part.
+fn trim_aligned(ptr:Addr,total:Size,size:Size,align:Size) -> Addr {
+	let res = ops::up_to_power_of_2(ptr,align);
+	if res > ptr {
+		munmap(ptr,res - ptr);
+	}
+	if ptr + total > res + size {
+		munmap(res + size,ptr + total - res - size);
+	}
+	res
+}
+
+///Reserve an address range without memory behind it (PROT_NONE), it needs to be
+///commited before use. With a non NULL address it replaces the current mapping at
+///this place. Return NULL if the OS cannot provide the range.
+pub fn reserve(addr:Addr,size:Size) -> Addr {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size != 0);
+
+	//flags
+	let mut flags = libc::MAP_ANON | libc::MAP_PRIVATE | libc::MAP_NORESERVE;
+	if addr != 0 {
+		flags |= libc::MAP_FIXED;
+	}
+
+	//call
+	let res = unsafe{libc::mmap(addr as *mut libc::c_void, size, libc::PROT_NONE, flags, -1,0)};
+
+	//check error
+	if res == libc::MAP_FAILED {
+		return NULL;
+	}
+
+	res as Addr
+}
+
+///Same than reserve() but with the range aligned on the given power of 2.
+pub fn reserve_aligned(size:Size,align:Size) -> Addr {
+	//check
+	debug_assert!(ops::is_power_of_2(align));
+	debug_assert!(align >= SMALL_PAGE_SIZE);
+
+	//reserve more
+	let total = size + align - SMALL_PAGE_SIZE;
+	let ptr = reserve(0,total);
+	if ptr == NULL {
+		return NULL;
+	}
+
+	trim_aligned(ptr,total,size,align)
+}
+
+///Make a part of a reserved range usable, the pages will be zero on first access.
+///Return false if the OS refused, eg. if there is not enough memory.
+pub fn commit(addr:Addr,size:Size) -> bool {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+
+	//call
+	let ret = unsafe{libc::mprotect(addr as *mut libc::c_void,size,libc::PROT_READ | libc::PROT_WRITE)};
+
+	//ret
+	ret == 0
+}
+
+///Give the pages of a commited range back to the OS and make it unusable again.
+///The range stays reserved.
+pub fn decommit(addr:Addr,size:Size) -> bool {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+
+	//drop the pages then the access
+	let ret = unsafe{libc::mprotect(addr as *mut libc::c_void,size,libc::PROT_NONE)};
+	madvise_dontneed(addr,size) && ret == 0
+}
+
+///Map the given part of a memory file with MAP_SHARED so the content is shared
+///with the other processes mapping it. With a non NULL address it replaces the
+///current mapping at this place. Return NULL on failure.
+pub fn mmap_shared(addr:Addr,size:Size,fd:i32,offset:Size) -> Addr {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+	debug_assert!(offset % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size != 0);
+
+	//flags
+	let mut flags = libc::MAP_SHARED;
+	if addr != 0 {
+		flags |= libc::MAP_FIXED;
+	}
+
+	//call
+	let res = unsafe{libc::mmap(addr as *mut libc::c_void, size, libc::PROT_READ | libc::PROT_WRITE, flags, fd, offset as libc::off_t)};
+
+	//check error
+	if res == libc::MAP_FAILED {
+		return NULL;
+	}
+
+	res as Addr
+}
+
+///Map memory backed by huge pages with MAP_HUGETLB. Return NULL if the OS cannot
+///provide them, eg. if there is not enought pages reserved.
+pub fn mmap_hugetlb(size:Size) -> Addr {
+	//check
+	debug_assert!(size % HUGE_PAGE_SIZE == 0);
+	debug_assert!(size != 0);
+
+	//call
+	let res = unsafe{libc::mmap(0 as *mut libc::c_void, size,libc::PROT_READ | libc::PROT_WRITE, libc::MAP_ANON | libc::MAP_PRIVATE | libc::MAP_HUGETLB, -1,0)};
+
+	//check error
+	if res == libc::MAP_FAILED {
+		return NULL;
+	}
+
+	res as Addr
+}
+
+///wrapper to munmap function, return false if the call failed.
 pub fn munmap(addr:Addr,size:Size) -> bool {
 	//check
 	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
@@ -51,15 +198,15 @@ pub fn munmap(addr:Addr,size:Size) -> bool {
 	//call
 	let ret = unsafe{libc::munmap(addr as *mut libc::c_void,size)};
 
-	//warn
-	if ret != 0 {
-		panic!("Failed to return memory to the OS via munmap.");
-	}
+	//can only fail on invalid range so this is a bug
+	debug_assert!(ret == 0);
 
 	//ret
-	ret != 0
+	ret == 0
 }
 
+///wrapper to mremap function, return NULL if the segment cannot be remapped. In this
+///case the old mapping is left untouched.
 pub fn mremap(addr:Addr,old_size:Size,new_size:Size,dest_addr:Addr) -> Addr {
 	//check
 	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
@@ -79,18 +226,77 @@ pub fn mremap(addr:Addr,old_size:Size,new_size:Size,dest_addr:Addr) -> Addr {
 
 	//check
 	if ret == libc::MAP_FAILED {
-		panic!("Failed to remap memory via mremap.");
+		return NULL;
 	}
 
 	//ret
 	ret as Addr
 }
 
+/// Tell the OS it can drop the pages of the given range, they will be
+/// zero filled on next access. Return false if the call failed.
+pub fn madvise_dontneed(addr:Addr,size:Size) -> bool {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+
+	//call
+	let ret = unsafe{libc::madvise(addr as *mut libc::c_void,size,libc::MADV_DONTNEED)};
+
+	//ret
+	ret == 0
+}
+
+/// Ask the OS to back the given range with transparent huge pages. Return false
+/// if the call failed, eg. if THP is disabled.
+pub fn madvise_hugepage(addr:Addr,size:Size) -> bool {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+
+	//call
+	let ret = unsafe{libc::madvise(addr as *mut libc::c_void,size,libc::MADV_HUGEPAGE)};
+
+	//ret
+	ret == 0
+}
+
+/// Free the pages of a shared mapping and their backing store in the memory
+/// file, they will be zero filled on next access in all the processes.
+/// Return false if the call failed.
+pub fn madvise_remove(addr:Addr,size:Size) -> bool {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+
+	//call
+	let ret = unsafe{libc::madvise(addr as *mut libc::c_void,size,libc::MADV_REMOVE)};
+
+	//ret
+	ret == 0
+}
+
+/// Tell the OS it can reclaim the pages of the given range when it is under
+/// memory pressure, the content is undefined until next write. Fallback on
+/// MADV_DONTNEED if not supported by the kernel. Return false if the call failed.
+pub fn madvise_free(addr:Addr,size:Size) -> bool {
+	//check
+	debug_assert!(addr % SMALL_PAGE_SIZE == 0);
+	debug_assert!(size % SMALL_PAGE_SIZE == 0);
+
+	//call
+	let ret = unsafe{libc::madvise(addr as *mut libc::c_void,size,libc::MADV_FREE)};
+
+	//ret
+	ret == 0 || madvise_dontneed(addr,size)
+}
+
 #[cfg(test)]
 mod tests
 {
 	use common::consts::*;
 	use portability::osmem;
+	use portability::shm;
 
 	#[test]
 	fn test_mmap_mremap_munap() {
@@ -105,6 +311,19 @@ mod tests
 		osmem::munmap(ptr,4*4096);
 	}
 
+	#[test]
+	fn test_mmap_failure() {
+		//cannot get more than the address space
+		assert_eq!(osmem::mmap(0, 1 << 62), NULL);
+
+		//remap failure keep the old mapping
+		let ptr = osmem::mmap(0, 4096);
+		unsafe{*(ptr as * mut u8) = 42};
+		assert_eq!(osmem::mremap(ptr, 4096, 1 << 62, 0), NULL);
+		assert_eq!(unsafe{*(ptr as * const u8)}, 42);
+		assert!(osmem::munmap(ptr, 4096));
+	}
+
 	#[test]
 	fn test_mmap_fixed() {
 		let ptr = osmem::mmap(0, 8*4096);
@@ -113,6 +332,86 @@ mod tests
 		osmem::munmap(ptr, 8*4096);
 	}
 
+	#[test]
+	fn test_madvise_dontneed() {
+		let ptr = osmem::mmap(0, 4*4096);
+		unsafe{*(ptr as * mut u8) = 1};
+		assert!(osmem::madvise_dontneed(ptr, 4*4096));
+		assert_eq!(unsafe{*(ptr as * const u8)}, 0);
+		osmem::munmap(ptr, 4*4096);
+	}
+
+	#[test]
+	fn test_mmap_aligned() {
+		let ptr = osmem::mmap_aligned(3*HUGE_PAGE_SIZE, HUGE_PAGE_SIZE);
+		assert!(ptr != 0);
+		assert_eq!(ptr % HUGE_PAGE_SIZE, 0);
+		unsafe{*((ptr + 3*HUGE_PAGE_SIZE - 1) as * mut u8) = 1};
+		osmem::madvise_hugepage(ptr, 3*HUGE_PAGE_SIZE);
+		osmem::munmap(ptr, 3*HUGE_PAGE_SIZE);
+	}
+
+	#[test]
+	fn test_reserve_commit() {
+		let ptr = osmem::reserve_aligned(4*MACRO_BLOC_SIZE, MACRO_BLOC_SIZE);
+		assert!(ptr != 0);
+		assert_eq!(ptr % MACRO_BLOC_SIZE, 0);
+
+		//commit a part
+		assert!(osmem::commit(ptr + MACRO_BLOC_SIZE, MACRO_BLOC_SIZE));
+		unsafe{*((ptr + MACRO_BLOC_SIZE) as * mut u8) = 42};
+
+		//decommit reset it
+		assert!(osmem::decommit(ptr + MACRO_BLOC_SIZE, MACRO_BLOC_SIZE));
+		assert!(osmem::commit(ptr + MACRO_BLOC_SIZE, MACRO_BLOC_SIZE));
+		assert_eq!(unsafe{*((ptr + MACRO_BLOC_SIZE) as * const u8)}, 0);
+
+		//replace a part
+		assert_eq!(osmem::reserve(ptr + MACRO_BLOC_SIZE, MACRO_BLOC_SIZE), ptr + MACRO_BLOC_SIZE);
+		osmem::munmap(ptr, 4*MACRO_BLOC_SIZE);
+	}
+
+	#[test]
+	fn test_mmap_hugetlb() {
+		//depend on the huge pages reserved on the system
+		let ptr = osmem::mmap_hugetlb(HUGE_PAGE_SIZE);
+		if ptr != 0 {
+			assert_eq!(ptr % HUGE_PAGE_SIZE, 0);
+			osmem::munmap(ptr, HUGE_PAGE_SIZE);
+		}
+	}
+
+	#[test]
+	fn test_mmap_shared() {
+		let fd = shm::memfd_create(b"test_mmap_shared\0");
+		assert!(shm::set_size(fd, 2*4096));
+
+		//two views of the same file
+		let ptr1 = osmem::mmap_shared(0, 2*4096, fd, 0);
+		let ptr2 = osmem::mmap_shared(0, 4096, fd, 4096);
+		assert!(ptr1 != 0 && ptr2 != 0);
+		unsafe{*((ptr1 + 4096) as * mut u8) = 42};
+		assert_eq!(unsafe{*(ptr2 as * const u8)}, 42);
+
+		//remove the content for both
+		assert!(osmem::madvise_remove(ptr2, 4096));
+		assert_eq!(unsafe{*((ptr1 + 4096) as * const u8)}, 0);
+
+		osmem::munmap(ptr1, 2*4096);
+		osmem::munmap(ptr2, 4096);
+		shm::close(fd);
+	}
+
+	#[test]
+	fn test_madvise_free() {
+		let ptr = osmem::mmap(0, 4*4096);
+		unsafe{*(ptr as * mut u8) = 1};
+		assert!(osmem::madvise_free(ptr, 4*4096));
+		unsafe{*(ptr as * mut u8) = 2};
+		assert_eq!(unsafe{*(ptr as * const u8)}, 2);
+		osmem::munmap(ptr, 4*4096);
+	}
+
 	#[test]
 	fn test_mremap_fixed() {
 		let ptr = osmem::mmap(0, 8*4096);
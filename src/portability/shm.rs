@@ -0,0 +1,100 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+///Wrappers to create and open the memory files (memfd_create() and shm_open())
+///used to share memory between processes. All of them return -1 on failure
+///with errno set.
+
+//import
+extern crate libc;
+
+//import
+use common::types::Size;
+
+/// Create an anonymous memory file with memfd_create(). It is shared with
+/// other processes by inheriting or passing the descriptor.
+///
+/// @param name Name for debugging in /proc/self/fd, it must be NULL terminated.
+pub fn memfd_create(name: &[u8]) -> i32 {
+	debug_assert!(name.last() == Some(&0));
+	//not wrapped by the libc crate version we use
+	unsafe{libc::syscall(libc::SYS_memfd_create, name.as_ptr() as * const libc::c_char, 0) as i32}
+}
+
+/// Open a POSIX shared memory object with shm_open().
+///
+/// @param name Name of the object starting with '/', it must be NULL terminated.
+/// @param create Create the object, fail if it already exists.
+pub fn shm_open(name: &[u8], create: bool) -> i32 {
+	debug_assert!(name.last() == Some(&0));
+	let flags = if create {
+		libc::O_RDWR | libc::O_CREAT | libc::O_EXCL
+	} else {
+		libc::O_RDWR
+	};
+	unsafe{libc::shm_open(name.as_ptr() as * const libc::c_char, flags, 0o600)}
+}
+
+/// Remove the name of a POSIX shared memory object, it is freed once no more
+/// process map it. Return false on failure.
+///
+/// @param name Name of the object, it must be NULL terminated.
+pub fn shm_unlink(name: &[u8]) -> bool {
+	debug_assert!(name.last() == Some(&0));
+	unsafe{libc::shm_unlink(name.as_ptr() as * const libc::c_char) == 0}
+}
+
+/// Set the size of the memory file. Return false on failure.
+pub fn set_size(fd: i32, size: Size) -> bool {
+	unsafe{libc::ftruncate(fd, size as libc::off_t) == 0}
+}
+
+/// Return the size of the memory file, 0 on failure.
+pub fn get_size(fd: i32) -> Size {
+	let mut st: libc::stat = unsafe{::core::mem::zeroed()};
+	if unsafe{libc::fstat(fd, &mut st)} != 0 {
+		return 0;
+	}
+	st.st_size as Size
+}
+
+/// Close the file descriptor.
+pub fn close(fd: i32) {
+	unsafe{libc::close(fd)};
+}
+
+#[cfg(test)]
+mod tests
+{
+	use portability::shm::*;
+
+	#[test]
+	fn test_memfd() {
+		let fd = memfd_create(b"test_memfd\0");
+		assert!(fd >= 0);
+		assert_eq!(get_size(fd), 0);
+		assert!(set_size(fd, 4096));
+		assert_eq!(get_size(fd), 4096);
+		close(fd);
+	}
+
+	#[test]
+	fn test_shm_open() {
+		let name = b"/hpc_allocator_test_shm_open\0";
+		shm_unlink(name);
+		let fd = shm_open(name, true);
+		assert!(fd >= 0);
+		assert_eq!(shm_open(name, true), -1);
+		let fd2 = shm_open(name, false);
+		assert!(fd2 >= 0);
+		assert!(shm_unlink(name));
+		assert_eq!(shm_open(name, false), -1);
+		close(fd);
+		close(fd2);
+	}
+}
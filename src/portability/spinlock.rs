@@ -18,6 +18,7 @@ use core::ops::{Drop, Deref, DerefMut};
 //low level spincloks (pthread_spinlock_t) are int (from /usr/include/bits/pthreadtypes.h)
 type PthreadSpinLock = libc::c_ulong;
 const PTHREAD_PROCESS_PRIVATE: libc::c_int = 0;
+const PTHREAD_PROCESS_SHARED: libc::c_int = 1;
 
 //declare extern funcs
 extern {
@@ -45,13 +46,23 @@ pub struct SpinLockGuard<'a, T:'a>
 impl <T> SpinLock<T> {
 	///Construct the spinlock and embed the content in it
 	pub fn new(obj: T) -> Self {
+		Self::new_pshared(obj,PTHREAD_PROCESS_PRIVATE)
+	}
+
+	///Construct a spinlock which can be placed in memory shared between processes
+	pub fn new_shared(obj: T) -> Self {
+		Self::new_pshared(obj,PTHREAD_PROCESS_SHARED)
+	}
+
+	///Construct the spinlock with the given sharing mode
+	fn new_pshared(obj: T, pshared: libc::c_int) -> Self {
 		let ret = Self {
 			lock: 0,
 			data: obj, 
 		};
 
 		let ptr = &ret.lock as * const PthreadSpinLock;
-		let status = unsafe{pthread_spin_init(ptr,PTHREAD_PROCESS_PRIVATE)};
+		let status = unsafe{pthread_spin_init(ptr,pshared)};
 		if status != 0 {
 			panic!("Fail to init pthread spinlock !");
 		}
@@ -95,6 +106,13 @@ impl <T> SpinLock<T> {
 	}
 }
 
+///To be able to init arrays of spinlocks
+impl <T: Default> Default for SpinLock<T> {
+	fn default() -> Self {
+		Self::new(T::default())
+	}
+}
+
 ///Implement deref for spin lock guard
 impl<'a, T> Deref for SpinLockGuard<'a, T>
 {
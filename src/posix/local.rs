@@ -15,16 +15,23 @@ use registry::registry::RegionRegistryPtr;
 use common::traits::{Allocator,ChunkManager,ChunkManagerPtr,MemorySourcePtr};
 use chunk::huge::HugeChunkManager;
 use chunk::medium::manager::MediumChunkManager;
-use chunk::small::manager::{SmallChunkManager,SMALL_CHUNK_MAX_SIZE};
+use chunk::small::manager::{SmallChunkManager,SMALL_CHUNK_MAX_SIZE,SMALL_SIZE_CLASSES};
+use chunk::small::tcache::SmallTCache;
 use common::mpscf_queue::MPSCFQueue;
 use common::types::{Addr,Size};
 use common::consts::*;
+use common::config;
+use common::ops;
+use common::stats::{AllocStats,DetailedReport,StatsReport};
 use portability::libc;
+use core::mem;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 /// Define a local allocator to be used to build the UMA/NUMA posix allocator by creating one local
 /// allocator for every thread and store it into a TLS.
 /// This allocator use the remote free queue to handle remote free without forcing the whole
 /// managers to be thread safe.
+#[repr(C)]
 pub struct LocalAllocator {
 	list_handler: ListNode,//CAUTION, This should be first
 	registry: Option<RegionRegistryPtr>,
@@ -36,6 +43,16 @@ pub struct LocalAllocator {
 	rfq: MPSCFQueue,
 	use_lock: bool,
 	parent: Option<ChunkManagerPtr>,
+	/// Size from which we use the huge chunk manager, from runtime config.
+	huge_alloc_threashold: Size,
+	/// Statistic counters of the allocator.
+	stats: AllocStats,
+	/// Next allocator in the chain of all the allocators, used to aggregate statistics.
+	next_allocator: Addr,
+	/// Cache of small chunks, only enabled for the thread local allocators.
+	tcache: SmallTCache,
+	/// Set by trim_shared() to ask the owner thread to flush its cache.
+	tcache_flush_requested: AtomicBool,
 }
 
 #[derive(PartialEq)]
@@ -58,6 +75,11 @@ impl LocalAllocator {
 			rfq: MPSCFQueue::new(),
 			use_lock: use_lock,
 			parent: None,
+			huge_alloc_threashold: config::get().huge_alloc_threashold,
+			stats: AllocStats::new(),
+			next_allocator: NULL,
+			tcache: SmallTCache::new(0),
+			tcache_flush_requested: AtomicBool::new(false),
 		}
 	}
 
@@ -97,7 +119,52 @@ impl LocalAllocator {
 		
 		//free it
 		match chunk_manager {
-			Some(mut chunk_manager) => chunk_manager.free(addr),
+			Some(chunk_manager) => self.free_from(addr, chunk_manager),
+			None => {},
+		}
+	}
+
+	/// Free the given address when the caller already fetched its chunk manager
+	/// from the registry, this avoid a second lookup on the free path.
+	///
+	/// @param addr Address to free.
+	/// @param chunk_manager The chunk manager owning the address.
+	pub fn free_from(&mut self,addr: Addr,mut chunk_manager: ChunkManagerPtr) {
+		//errors
+		debug_assert!(self.is_init);
+		debug_assert!(addr != NULL);
+
+		//keep small chunks in the thread cache, their size is the one of the class
+		if self.tcache.is_enabled() && chunk_manager == ChunkManagerPtr::new_ref(&self.small) {
+			let chunk_size = self.tcache_free(addr);
+			self.stats.on_free(chunk_size, chunk_size);
+		} else {
+			self.stats.on_free(chunk_manager.get_inner_size(addr), chunk_manager.get_total_size(addr));
+			chunk_manager.free(addr);
+		}
+	}
+
+	/// Free the given address, the size is the one requested at allocation time
+	/// as for C23 free_sized(). Small chunks get their chunk manager from the run
+	/// header without walking the registry. The size is checked in debug mode.
+	///
+	/// @param addr Address to free.
+	/// @param size Size requested at allocation time, or the alignment if larger.
+	pub fn free_sized(&mut self,addr: Addr,size: Size) {
+		//errors
+		debug_assert!(addr == NULL || size <= self.get_inner_size(addr));
+
+		//large ones go the usual way
+		if addr == NULL || size >= SMALL_CHUNK_MAX_SIZE {
+			self.free(addr);
+			return;
+		}
+
+		//small ones
+		let chunk_manager = SmallChunkManager::get_owner(addr);
+		debug_assert!(chunk_manager == self.get_chunk_manager(addr));
+		match chunk_manager {
+			Some(chunk_manager) => self.free_from(addr, chunk_manager),
 			None => {},
 		}
 	}
@@ -105,9 +172,15 @@ impl LocalAllocator {
 	pub fn calloc(&mut self,nmemb: Size, size: Size) -> Addr {
 		//errors
 		debug_assert!(self.is_init);
+
+		//check overflow
+		let total = match nmemb.checked_mul(size) {
+			Some(total) => total,
+			None => return NULL,
+		};
 		
 		//do it
-		return self.internal_malloc(size*nmemb,BASIC_ALIGN,true);
+		return self.internal_malloc(total,BASIC_ALIGN,true);
 	}
 
 	pub fn realloc(&mut self,ptr: Addr,size:Size) -> Addr {
@@ -135,27 +208,39 @@ impl LocalAllocator {
 					let huge_ptr = ChunkManagerPtr::new_ref(&self.huge);
 
 					//check if can strictly realloc in one kind of allocator
-					let size_class = LocalAllocator::get_size_class(size);
+					let size_class = self.get_size_class(size);
 					let is_realloc_in_small = size_class == ManagerClass::ManagerSmall && manager == small_ptr;
 					let is_realloc_in_medium = size_class == ManagerClass::ManagerMedium && manager == medium_ptr;
 					let is_realloc_in_huge = size_class == ManagerClass::ManagerHuge && manager == huge_ptr;
 
 					//local and same class realloc otherwise alloc/copy/free
-					if is_realloc_in_small {
-						res = self.small.realloc(ptr, size);
-					} else if is_realloc_in_medium {
-						res = self.medium.realloc(ptr, size);
-					} else if is_realloc_in_huge {
-						res = self.huge.realloc(ptr, size);
+					let old_inner_size = manager.get_inner_size(ptr);
+					let old_total_size = manager.get_total_size(ptr);
+					if is_realloc_in_small || is_realloc_in_medium || is_realloc_in_huge {
+						if is_realloc_in_small {
+							res = self.small.realloc(ptr, size);
+						} else if is_realloc_in_medium {
+							res = self.medium.realloc(ptr, size);
+						} else {
+							res = self.huge.realloc(ptr, size);
+						}
+						if res != NULL {
+							self.stats.on_realloc(size, old_inner_size, old_total_size, manager.get_inner_size(res), manager.get_total_size(res));
+						}
 					} else {
 						let current_size = self.get_inner_size(ptr);
 						res = self.internal_malloc(size, BASIC_ALIGN, false);
-						if size < current_size {
-							libc::memcpy(res, ptr, size);
-						} else {
-							libc::memcpy(res, ptr, current_size);
+
+						//on out of memory the old chunk is left untouched
+						if res != NULL {
+							if size < current_size {
+								libc::memcpy(res, ptr, size);
+							} else {
+								libc::memcpy(res, ptr, current_size);
+							}
+							self.stats.on_free(old_inner_size, old_total_size);
+							manager.get_mut().free(ptr);
 						}
-						manager.get_mut().free(ptr);
 					}
 				},
 
@@ -165,7 +250,9 @@ impl LocalAllocator {
 					//panic!("The old segment isn't managed by current memory allocator, try to copy, but create a memory leak and may segfault during unsage copy !");
 
 					res = self.internal_malloc(size, BASIC_ALIGN, false);
-					libc::memcpy(res, ptr, size);
+					if res != NULL {
+						libc::memcpy(res, ptr, size);
+					}
 				}
 			}
 		}
@@ -178,12 +265,17 @@ impl LocalAllocator {
 		//errors
 		debug_assert!(self.is_init);
 
+		//alignement must be a power of 2 multiple of sizeof(void*)
+		if !ops::is_power_of_2(align) || align % mem::size_of::<Addr>() != 0 {
+			return libc::EINVAL;
+		}
+
 		//do it
 		let tmp = self.internal_malloc(size,align,false);
 		unsafe{*memptr = tmp as * mut Addr};
 
 		if tmp == 0 {
-			return -1;
+			return libc::ENOMEM;
 		} else {
 			return 0;
 		}
@@ -193,32 +285,115 @@ impl LocalAllocator {
 		//errors
 		debug_assert!(self.is_init);
 
+		//alignement must be a power of 2
+		if !ops::is_power_of_2(align) {
+			return NULL;
+		}
+
 		return self.internal_malloc(size,align,false);
 	}
 
-	pub fn valloc(&mut self, _size: Size) -> Addr {
+	pub fn valloc(&mut self, size: Size) -> Addr {
 		//errors
 		debug_assert!(self.is_init);
 
-		unimplemented!();
+		return self.internal_malloc(size,SMALL_PAGE_SIZE,false);
 	}
 
 	pub fn memalign(&mut self, align: Size, size: Size) -> Addr {
 		//errors
 		debug_assert!(self.is_init);
 
+		//glibc accept any alignement and round it up to the next power of 2
+		let align = match align.checked_next_power_of_two() {
+			Some(align) => align,
+			None => return NULL,
+		};
+
 		//do it
 		let tmp = self.internal_malloc(size,align,false);
 		
 		return tmp;
 	}
 
-	pub fn pvalloc(&mut self, _size: Size) -> Addr {
+	pub fn pvalloc(&mut self, size: Size) -> Addr {
 		//errors
 		debug_assert!(self.is_init);
 
-		//not supported
-		unimplemented!();
+		//round to whole pages, as glibc we return one page for 0
+		let size = if size == 0 {
+			SMALL_PAGE_SIZE
+		} else if size >= PHYS_MAX_ADDR {
+			return NULL;
+		} else {
+			ops::up_to_power_of_2(size,SMALL_PAGE_SIZE)
+		};
+
+		return self.internal_malloc(size,SMALL_PAGE_SIZE,false);
+	}
+
+	/// Return the statistic counters of the allocator.
+	pub fn get_stats(&self) -> &AllocStats {
+		&self.stats
+	}
+
+	/// Add the counters and small runs usage of the allocator to the report.
+	pub fn collect_stats(&self, report: &mut StatsReport) {
+		self.stats.accumulate(report);
+		self.small.collect_stats(report);
+	}
+
+	/// Add the per size class usage of all the chunk managers to the report.
+	pub fn collect_details(&self, details: &mut DetailedReport) {
+		self.small.collect_details(details);
+		self.medium.collect_details(details);
+		self.huge.collect_details(details);
+	}
+
+	/// Return unused memory kept by the chunk managers to the memory source
+	/// or to the OS. Return the released size.
+	pub fn trim(&mut self) -> Size {
+		self.flush_tcache();
+		self.small.trim() + self.medium.trim()
+	}
+
+	/// Same than trim() but to be called from another thread than the owner.
+	/// The thread cache is not protected so we only ask the owner to flush it
+	/// on its next call.
+	pub fn trim_shared(&mut self) -> Size {
+		self.tcache_flush_requested.store(true, Ordering::Release);
+		self.small.trim() + self.medium.trim()
+	}
+
+	/// Enable the small chunk thread cache with the given number of chunks per
+	/// size class, 0 to disable it. This must only be used by allocators
+	/// accessed by a single thread.
+	pub fn set_tcache_depth(&mut self, depth: Size) {
+		self.flush_tcache();
+		self.tcache.set_depth(depth);
+	}
+
+	/// Return all the chunks of the thread cache to their runs.
+	pub fn flush_tcache(&mut self) {
+		if self.tcache.get_cached() > 0 {
+			self.stats.on_tcache_flush();
+			self.tcache.flush_all(&mut self.small);
+		}
+	}
+
+	/// Change the size from which we use the huge chunk manager.
+	pub fn set_huge_alloc_threashold(&mut self, threashold: Size) {
+		self.huge_alloc_threashold = threashold;
+	}
+
+	/// Return the next allocator in the chain used to aggregate statistics.
+	pub fn get_next_allocator(&self) -> Addr {
+		self.next_allocator
+	}
+
+	/// Set the next allocator in the chain used to aggregate statistics.
+	pub fn set_next_allocator(&mut self, next: Addr) {
+		self.next_allocator = next;
 	}
 
 	pub fn rebind_mmsource(&mut self,mmsource:Option<MemorySourcePtr>) {
@@ -241,33 +416,86 @@ impl LocalAllocator {
 			fsize = size;
 		}
 
+		//cannot be served by the OS, avoid overflows when adding headers
+		if fsize >= PHYS_MAX_ADDR {
+			return NULL;
+		}
+
 		//round size
 		let ptr;
 		let zeroed;
+		let manager: &dyn ChunkManager;
+		let mut chunk_size = 0;
 		if fsize <= SMALL_CHUNK_MAX_SIZE {
-			let (a,b) = self.small.malloc(fsize, align, zero);
+			let (a,b) = if self.tcache.is_enabled() {
+				//the chunks of the class are aligned enougth
+				let size_class = SmallChunkManager::get_size_class_for(fsize, if align < BASIC_ALIGN {BASIC_ALIGN} else {align});
+				chunk_size = SMALL_SIZE_CLASSES[size_class];
+				(self.tcache_malloc(size_class), false)
+			} else {
+				self.small.malloc(fsize, align, zero)
+			};
 			ptr = a;
 			zeroed = b;
-		} else if size > HUGE_ALLOC_THREASHOLD {
+			manager = &self.small;
+		} else if size > self.huge_alloc_threashold {
 			let (a,b) = self.huge.malloc(fsize, align, zero);
 			ptr = a;
 			zeroed = b;
+			manager = &self.huge;
 		} else {
 			let (a,b) = self.medium.malloc(fsize, align, zero);
 			ptr = a;
 			zeroed = b;
+			manager = &self.medium;
 		}
 
-		//if need reset
+		//account, the thread cache already gave the size
+		if ptr != NULL && chunk_size != 0 {
+			self.stats.on_alloc(size, chunk_size, chunk_size);
+		} else if ptr != NULL {
+			self.stats.on_alloc(size, manager.get_inner_size(ptr), manager.get_total_size(ptr));
+		}
+
+		//if need reset, the managers tell us if the memory is already known to be zero
+		//(fresh pages from the OS) so we avoid touching it and keep the first touch placement
 		if ptr != 0 && zero && ! zeroed {
-			assert!(false);
-			libc::memset(ptr, 0, fsize);
+			libc::memset(ptr, 0, size);
 		}
 
 		//final
 		return ptr;
 	}
 
+	/// Allocate a small chunk of the given size class from the thread cache,
+	/// refill it from the runs if empty.
+	fn tcache_malloc(&mut self, size_class: usize) -> Addr {
+		//fast path
+		let res = self.tcache.pop(size_class);
+		if res != NULL {
+			self.stats.on_tcache_hit();
+			return res;
+		}
+
+		//refill by batch
+		self.stats.on_tcache_refill();
+		self.tcache.refill(size_class, &mut self.small);
+		self.tcache.pop(size_class)
+	}
+
+	/// Keep a small chunk in the thread cache, flush half of it to the runs if full.
+	/// Return the size of the chunk.
+	fn tcache_free(&mut self, addr: Addr) -> Size {
+		let size_class = self.small.get_chunk_size_class(addr);
+		if !self.tcache.push(size_class, addr) {
+			self.stats.on_tcache_flush();
+			self.tcache.flush(size_class, &mut self.small);
+			let pushed = self.tcache.push(size_class, addr);
+			debug_assert!(pushed);
+		}
+		SMALL_SIZE_CLASSES[size_class]
+	}
+
 	fn get_chunk_manager(&self, ptr: Addr) -> Option<ChunkManagerPtr> {
 		//errors
 		debug_assert!(self.is_init);
@@ -295,10 +523,10 @@ impl LocalAllocator {
 		return small_ptr != manager && medium_ptr != manager && huge_ptr != manager;
 	}
 
-	fn get_size_class(size: Size) -> ManagerClass {
+	fn get_size_class(&self, size: Size) -> ManagerClass {
 		if size < SMALL_CHUNK_MAX_SIZE {
 			return ManagerClass::ManagerSmall;
-		} else if size < HUGE_ALLOC_THREASHOLD {
+		} else if size < self.huge_alloc_threashold {
 			return ManagerClass::ManagerMedium;
 		} else {
 			return ManagerClass::ManagerHuge;
@@ -441,6 +669,17 @@ impl Allocator for LocalAllocator {
 	}
 
 	fn flush_remote(&mut self) {
+		//flush the thread cache if asked by trim_shared()
+		if self.tcache_flush_requested.load(Ordering::Relaxed) {
+			self.tcache_flush_requested.store(false, Ordering::Relaxed);
+			self.flush_tcache();
+		}
+
+		//this is on the hot path, keep it to a single read if nothing to do
+		if self.rfq.is_empty() {
+			return;
+		}
+
 		let handler = self.rfq.dequeue_all();
 		match handler {
 			Some(handler) => {
@@ -13,4 +13,5 @@
 //export
 pub mod seq;
 pub mod numa;
-pub mod local;
\ No newline at end of file
+pub mod local;
+pub mod report;
\ No newline at end of file
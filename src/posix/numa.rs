@@ -14,14 +14,23 @@ extern crate libc;
 
 //import
 use posix::local::LocalAllocator;
+use chunk::small::manager::{SmallChunkManager,SMALL_CHUNK_MAX_SIZE};
+use posix::report;
 use registry::registry::RegionRegistry;
 use mmsource::cached::CachedMMSource;
 use common::shared::SharedPtrBox;
+use common::list::List;
+use common::stats::{DetailedReport,StatsReport};
 use common::types::{Addr,Size};
 use common::consts::*;
+use common::config;
+use common::oom;
 use common::traits::{Allocator, ChunkManagerPtr};
 use core::mem;
 use portability::osmem;
+use portability::libnuma;
+use portability::libc::{get_time_ms,sleep_ms};
+use portability::spinlock::SpinLock;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// Global variable to store the registry
@@ -29,10 +38,18 @@ static mut GBL_NUMA_ALLOCATOR: Addr = 0;
 static mut GBL_PTHREAD_KEY: libc::pthread_key_t = 0;
 static mut GBL_PROTECT_INIT: AtomicUsize = AtomicUsize::new(0);
 
+/// Thread allocator of the current thread, NULL until first call. This is a TLS
+/// slot so the hot path avoid calling pthread_getspecific(). The LD_PRELOAD
+/// library should be built with the initial-exec TLS model (see README). The
+/// pthread key is kept to get the exit destructor.
+#[thread_local]
+static mut TLS_THREAD_ALLOCATOR: Addr = NULL;
+
 /// Object handling the thread local memory allocator.
 pub struct ThreadNumaAllocator {
 	allocator: SharedPtrBox<LocalAllocator>,
 	region_registry: SharedPtrBox<RegionRegistry>,
+	numa_node: i32,
 }
 
 pub struct ThreadNumaAllocatorHandler {
@@ -43,8 +60,16 @@ pub struct ThreadNumaAllocatorHandler {
 /// objects tracking.
 pub struct NumaAllocator {
 	region_registry: SharedPtrBox<RegionRegistry>,
+	/// Shared memory source also used as fallback for threads not bound to a NUMA node.
 	egg_memory_source: SharedPtrBox<CachedMMSource>,
 	egg_allocator: SharedPtrBox<LocalAllocator>,
+	/// Address of the memory source of each NUMA node, NULL until first thread bound on it.
+	node_memory_sources: SpinLock<[Addr; NUMA_MAX_NODES]>,
+	/// Local allocators of exited threads waiting for reuse, one list per NUMA node
+	/// and a last one for threads not bound.
+	free_allocators: SpinLock<[List<LocalAllocator>; NUMA_MAX_NODES + 1]>,
+	/// Head of the chain of all the thread local allocators ever created, used to aggregate statistics.
+	all_allocators: SpinLock<Addr>,
 }
 
 /// Object to handle a NUMA allocator
@@ -109,9 +134,15 @@ pub fn init() {
 		return;
 	}
 
+	//load tuning from environment
+	config::init_from_env();
+
 	// allocate
 	let total_size = NumaAllocator::egg_mem_size();
 	let ptr = osmem::mmap(0, total_size);
+	if ptr == NULL {
+		oom::fatal("the global allocator");
+	}
 
 	//calc addresses
 	let numa_allocator_size = mem::size_of::<NumaAllocator>();
@@ -123,8 +154,7 @@ pub fn init() {
 	*numa_allocator.get_mut() = NumaAllocator::new(other_egg_element_addr);
 
 	//create key
-	//TODO create a function for destructor
-	unsafe{libc::pthread_key_create(&mut GBL_PTHREAD_KEY, None)};
+	unsafe{libc::pthread_key_create(&mut GBL_PTHREAD_KEY, Some(thread_exit_destructor))};
 
 	unsafe {
 		// commit
@@ -133,6 +163,52 @@ pub fn init() {
 		// update atomic protection to release threads in waiting queue
 		GBL_PROTECT_INIT.store(2, Ordering::Relaxed);
 	}
+
+	//once ready as creating a thread or registering with atexit() can call malloc()
+	if config::get().mmsrc_purge_thread {
+		start_purge_thread();
+	}
+	if config::get().report_at_exit != config::ReportMode::None {
+		unsafe{libc::atexit(report::print_at_exit)};
+	}
+}
+
+/// Start the background thread applying the decay policy of the memory sources.
+fn start_purge_thread() {
+	let mut thread: libc::pthread_t = 0;
+	let status = unsafe{libc::pthread_create(&mut thread, 0 as * const libc::pthread_attr_t, purge_thread_main, 0 as * mut libc::c_void)};
+	if status == 0 {
+		unsafe{libc::pthread_detach(thread)};
+	}
+}
+
+/// Body of the background purge thread, it never exit.
+extern "C" fn purge_thread_main(_arg: * mut libc::c_void) -> * mut libc::c_void {
+	let numa_allocator = NumaAllocatorHandler::new().get_numa_allocator();
+	loop {
+		sleep_ms(MMSRC_DECAY_PERIOD);
+		numa_allocator.decay(get_time_ms());
+	}
+}
+
+/// Called by pthread on thread exit to recycle the allocator of the thread.
+extern "C" fn thread_exit_destructor(ptr: * mut libc::c_void) {
+	//trivial
+	if ptr.is_null() {
+		return;
+	}
+
+	//forget it in the TLS so a later malloc in another destructor get a new one
+	unsafe {
+		if TLS_THREAD_ALLOCATOR == ptr as Addr {
+			TLS_THREAD_ALLOCATOR = NULL;
+		}
+	}
+
+	//park it for next threads
+	let thread_alloc: SharedPtrBox<ThreadNumaAllocator> = SharedPtrBox::new_addr(ptr as Addr);
+	let mut numa_allocator_handler = NumaAllocatorHandler::new();
+	numa_allocator_handler.get_numa_allocator().release_thread_allocator(thread_alloc);
 }
 
 impl NumaAllocator {
@@ -162,6 +238,9 @@ impl NumaAllocator {
 			region_registry: region_registry,
 			egg_memory_source: egg_mm_source,
 			egg_allocator: egg_allocator,
+			node_memory_sources: SpinLock::new([NULL; NUMA_MAX_NODES]),
+			free_allocators: SpinLock::new([List::new(); NUMA_MAX_NODES + 1]),
+			all_allocators: SpinLock::new(NULL),
 		}
 	}
 
@@ -180,47 +259,317 @@ impl NumaAllocator {
 		return total_size;
 	}
 
-	pub fn get_new_local_allocator(&mut self) -> SharedPtrBox<LocalAllocator> {
+	/// Return the memory source to be used by threads bound to the given NUMA node.
+	/// It is created on first request. Threads which are not bound (node -1)
+	/// use the egg memory source as fallback.
+	pub fn get_memory_source(&mut self, numa_node: i32) -> SharedPtrBox<CachedMMSource> {
+		//not bound
+		if numa_node < 0 || numa_node as Size >= NUMA_MAX_NODES {
+			return self.egg_memory_source.clone();
+		}
+
+		//already there
+		let id = numa_node as Size;
+		let addr = self.node_memory_sources.nolock_safe_read()[id];
+		if addr != NULL {
+			return SharedPtrBox::new_addr(addr);
+		}
+
+		//create under lock to avoid dual init
+		let mut sources = self.node_memory_sources.lock();
+		if sources[id] == NULL {
+			let ptr = self.egg_allocator.malloc(mem::size_of::<CachedMMSource>(), BASIC_ALIGN, false);
+			if ptr == NULL {
+				oom::fatal("a NUMA memory source");
+			}
+			let mut mm_source: SharedPtrBox<CachedMMSource> = SharedPtrBox::new_addr(ptr);
+			*mm_source.get_mut() = CachedMMSource::new_numa(Some(self.region_registry.clone()), numa_node);
+			sources[id] = ptr;
+		}
+
+		SharedPtrBox::new_addr(sources[id])
+	}
+
+	pub fn get_new_local_allocator(&mut self, numa_node: i32) -> SharedPtrBox<LocalAllocator> {
+		let mut mm_source = self.get_memory_source(numa_node);
 		let size = mem::size_of::<LocalAllocator>();
 		let ptr = self.egg_allocator.malloc(size, BASIC_ALIGN, false);
+		if ptr == NULL {
+			oom::fatal("a local allocator");
+		}
 		let mut local_allocator: SharedPtrBox<LocalAllocator> = SharedPtrBox::new_addr(ptr);
-		*local_allocator.get_mut() = LocalAllocator::new(true, Some(self.region_registry.clone()), Some(SharedPtrBox::new_ref_mut(self.egg_memory_source.get_mut())));
+		*local_allocator.get_mut() = LocalAllocator::new(true, Some(self.region_registry.clone()), Some(SharedPtrBox::new_ref_mut(mm_source.get_mut())));
 		local_allocator.clone().post_init(ChunkManagerPtr::new_ref_mut(&mut *local_allocator.clone().get_mut()));
+
+		//used by only one thread at a time so can cache small chunks
+		local_allocator.set_tcache_depth(config::get().tcache_depth);
+
+		//register in chain for statistics
+		{
+			let mut head = self.all_allocators.lock();
+			local_allocator.set_next_allocator(*head);
+			*head = local_allocator.get_addr();
+		}
+
 		return local_allocator;
 	}
 
+	/// Aggregate the statistics of all the allocators and memory sources.
+	pub fn collect_stats(&self) -> StatsReport {
+		let mut report = StatsReport::new();
+
+		//egg allocator & source
+		self.egg_allocator.collect_stats(&mut report);
+		self.egg_memory_source.collect_stats(&mut report);
+
+		//threads
+		let mut cur = *self.all_allocators.lock();
+		while cur != NULL {
+			let allocator: SharedPtrBox<LocalAllocator> = SharedPtrBox::new_addr(cur);
+			allocator.collect_stats(&mut report);
+			report.threads += 1;
+			cur = allocator.get_next_allocator();
+		}
+
+		//numa memory sources
+		let sources = self.node_memory_sources.lock();
+		for addr in sources.iter() {
+			if *addr != NULL {
+				let mm_source: SharedPtrBox<CachedMMSource> = SharedPtrBox::new_addr(*addr);
+				mm_source.collect_stats(&mut report);
+			}
+		}
+
+		report
+	}
+
+	/// Apply the decay policy of all the memory sources. Return the size returned to the OS.
+	///
+	/// @param now Current time in milliseconds from get_time_ms().
+	pub fn decay(&self, now: Size) -> Size {
+		let mut res = self.egg_memory_source.decay(now);
+		let sources = self.node_memory_sources.lock();
+		for addr in sources.iter() {
+			if *addr != NULL {
+				let mm_source: SharedPtrBox<CachedMMSource> = SharedPtrBox::new_addr(*addr);
+				res += mm_source.decay(now);
+			}
+		}
+		res
+	}
+
+	/// Return unused memory of all the thread allocators and flush the memory
+	/// source caches. Return the released size. The thread caches of the other
+	/// threads are flushed by their owner on their next call.
+	pub fn trim(&mut self) -> Size {
+		//allocator of the calling thread if any
+		let tls = unsafe{TLS_THREAD_ALLOCATOR};
+		let current = if tls == NULL {
+			NULL
+		} else {
+			let thread_alloc: SharedPtrBox<ThreadNumaAllocator> = SharedPtrBox::new_addr(tls);
+			thread_alloc.allocator.get_addr()
+		};
+
+		//allocators
+		let mut res = self.egg_allocator.trim();
+		let mut cur = *self.all_allocators.lock();
+		while cur != NULL {
+			let mut allocator: SharedPtrBox<LocalAllocator> = SharedPtrBox::new_addr(cur);
+			if cur == current {
+				res += allocator.trim();
+			} else {
+				res += allocator.trim_shared();
+			}
+			cur = allocator.get_next_allocator();
+		}
+
+		//memory sources
+		res += self.egg_memory_source.get_cached_size();
+		self.egg_memory_source.free_all();
+		let sources = self.node_memory_sources.lock();
+		for addr in sources.iter() {
+			if *addr != NULL {
+				let mut mm_source: SharedPtrBox<CachedMMSource> = SharedPtrBox::new_addr(*addr);
+				res += mm_source.get_cached_size();
+				mm_source.free_all();
+			}
+		}
+
+		res
+	}
+
+	/// Apply a mallopt() parameter to the global config and to all the
+	/// components already built. Return false if not supported or invalid.
+	pub fn mallopt(&mut self, param: i32, value: i32) -> bool {
+		//update config
+		let cfg = match config::update(|cfg| cfg.apply_mallopt(param, value)) {
+			Some(cfg) => cfg,
+			None => return false,
+		};
+
+		//update allocators
+		self.egg_allocator.set_huge_alloc_threashold(cfg.huge_alloc_threashold);
+		let mut cur = *self.all_allocators.lock();
+		while cur != NULL {
+			let mut allocator: SharedPtrBox<LocalAllocator> = SharedPtrBox::new_addr(cur);
+			allocator.set_huge_alloc_threashold(cfg.huge_alloc_threashold);
+			cur = allocator.get_next_allocator();
+		}
+
+		//update memory sources
+		self.egg_memory_source.set_limits(cfg.mmsrc_max_size, cfg.mmsrc_threashold);
+		let sources = self.node_memory_sources.lock();
+		for addr in sources.iter() {
+			if *addr != NULL {
+				let mut mm_source: SharedPtrBox<CachedMMSource> = SharedPtrBox::new_addr(*addr);
+				mm_source.set_limits(cfg.mmsrc_max_size, cfg.mmsrc_threashold);
+			}
+		}
+
+		true
+	}
+
+	/// Build the detailed report with breakdown by size classes.
+	pub fn collect_details(&self) -> DetailedReport {
+		let mut details = DetailedReport::new();
+		details.summary = self.collect_stats();
+
+		//egg allocator & source
+		self.egg_allocator.collect_details(&mut details);
+		self.egg_memory_source.collect_details(&mut details);
+
+		//threads
+		let mut cur = *self.all_allocators.lock();
+		while cur != NULL {
+			let allocator: SharedPtrBox<LocalAllocator> = SharedPtrBox::new_addr(cur);
+			allocator.collect_details(&mut details);
+			cur = allocator.get_next_allocator();
+		}
+
+		//numa memory sources
+		let sources = self.node_memory_sources.lock();
+		for addr in sources.iter() {
+			if *addr != NULL {
+				let mm_source: SharedPtrBox<CachedMMSource> = SharedPtrBox::new_addr(*addr);
+				mm_source.collect_details(&mut details);
+			}
+		}
+
+		details
+	}
+
+	/// Return the free list to use to park the allocators of the given NUMA node.
+	fn get_free_list_id(numa_node: i32) -> Size {
+		if numa_node < 0 || numa_node as Size >= NUMA_MAX_NODES {
+			NUMA_MAX_NODES
+		} else {
+			numa_node as Size
+		}
+	}
+
+	/// Park the allocator of an exiting thread so it can be handed, with all
+	/// its cached memory, to the next thread created on the same NUMA node.
+	pub fn release_thread_allocator(&mut self, mut thread_alloc: SharedPtrBox<ThreadNumaAllocator>) {
+		//apply pending remote free and do not keep the cached chunks while parked
+		thread_alloc.flush_remote();
+		thread_alloc.allocator.flush_tcache();
+
+		//park local allocator
+		let id = Self::get_free_list_id(thread_alloc.get_numa_node());
+		self.free_allocators.lock()[id].push_back(thread_alloc.allocator.clone());
+
+		//the thread handler itself can be freed
+		self.egg_allocator.free(thread_alloc.get_addr());
+	}
+
+	/// Try to reuse a local allocator from an exited thread of the same NUMA node.
+	fn get_free_local_allocator(&mut self, numa_node: i32) -> Option<SharedPtrBox<LocalAllocator>> {
+		let id = Self::get_free_list_id(numa_node);
+		self.free_allocators.lock()[id].pop_front()
+	}
+
 	pub fn get_new_thread_allocator(&mut self) -> SharedPtrBox<ThreadNumaAllocator> {
-		//get allocator
-		let allocator = self.get_new_local_allocator();
+		//get allocator on the node the thread is bound to, reuse one from exited threads if possible
+		let numa_node = libnuma::numa_detect_affinity();
+		let allocator;
+		match self.get_free_local_allocator(numa_node) {
+			Some(x) => allocator = x,
+			None => allocator = self.get_new_local_allocator(numa_node),
+		}
 		let registry = self.region_registry.clone();
 
 		//allocate
 		let size = mem::size_of::<ThreadNumaAllocator>();
 		let ptr = self.egg_allocator.malloc(size, BASIC_ALIGN, false);
+		if ptr == NULL {
+			oom::fatal("a thread allocator");
+		}
 
 		//spawn
 		let mut thread_alloc: SharedPtrBox<ThreadNumaAllocator> = SharedPtrBox::new_addr(ptr);
-		*thread_alloc.get_mut() = ThreadNumaAllocator::new(allocator.clone(), registry.clone());
+		*thread_alloc.get_mut() = ThreadNumaAllocator::new(allocator.clone(), registry.clone(), numa_node);
 
 		//ret
 		return thread_alloc.clone();
 	}
+
+	/// Rebind a thread allocator to the NUMA node the thread is now bound to.
+	/// This is to be used by threads which get bound after their first malloc.
+	/// Memory already obtained by the thread stays valid and where it is,
+	/// the thread cache and the fully free macro blocs go back to the old node
+	/// and new macro blocs are fetched from the new node memory source.
+	pub fn rebind_thread_allocator(&mut self, mut thread_alloc: SharedPtrBox<ThreadNumaAllocator>) {
+		//nothing to do if not moved
+		let numa_node = libnuma::numa_detect_affinity();
+		if numa_node == thread_alloc.get_numa_node() {
+			return;
+		}
+
+		//rebind
+		let mm_source = self.get_memory_source(numa_node);
+		thread_alloc.rebind(numa_node, mm_source);
+	}
 }
 
 impl ThreadNumaAllocator {
 	#[inline]
-	pub fn new(alloc: SharedPtrBox<LocalAllocator>, registry: SharedPtrBox<RegionRegistry>) -> Self {
+	pub fn new(alloc: SharedPtrBox<LocalAllocator>, registry: SharedPtrBox<RegionRegistry>, numa_node: i32) -> Self {
 		Self {
 			allocator: alloc,
 			region_registry: registry,
+			numa_node: numa_node,
 		}
 	}
 
+	/// Return the NUMA node the thread allocator is bound to, -1 if not bound.
+	#[inline]
+	pub fn get_numa_node(&self) -> i32 {
+		self.numa_node
+	}
+
 	#[inline]
 	pub fn flush_remote(&mut self) {
 		self.allocator.flush_remote();
 	}
 
+	/// Switch all the chunk managers of the thread to the memory source of
+	/// the given NUMA node. The cached chunks are released first so the free
+	/// memory goes back to the old node instead of being reused. Only the free
+	/// space of the macro blocs still holding chunks in use can still be reused,
+	/// once empty the new node memory source returns them to the OS.
+	pub fn rebind(&mut self, numa_node: i32, mut mm_source: SharedPtrBox<CachedMMSource>) {
+		//apply pending remote free before switching
+		self.flush_remote();
+
+		//flush the thread cache and give the empty macro blocs to the old source
+		self.allocator.trim();
+
+		//rebind small, medium & huge
+		self.allocator.rebind_mmsource(Some(SharedPtrBox::new_ref_mut(mm_source.get_mut())));
+		self.numa_node = numa_node;
+	}
+
 	#[inline]
 	pub fn is_distant_manager(&mut self, chunk_manager: ChunkManagerPtr) -> bool {
 		if chunk_manager.is_thread_safe() {
@@ -296,13 +645,43 @@ impl ThreadNumaAllocator {
 		}
 
 		//get chunk manager
-		let mut chunk_manager = self.get_chunk_manager(addr);
+		let chunk_manager = self.get_chunk_manager(addr);
+		self.free_from(addr, chunk_manager);
+	}
+
+	/// Free with the size requested at allocation time as for C23 free_sized().
+	/// Small chunks get their chunk manager from the run header without walking
+	/// the registry. The size is checked against the owning chunk manager in debug mode.
+	/// @param addr Address to free.
+	/// @param size Size requested at allocation time, or the alignment if larger.
+	pub fn free_sized(&mut self,addr: Addr,size: Size) {
+		//errors
+		debug_assert!(addr == NULL || size <= self.get_chunk_manager(addr).get_inner_size(addr));
+
+		//large ones go the usual way
+		if addr == NULL || size >= SMALL_CHUNK_MAX_SIZE {
+			self.free(addr);
+			return;
+		}
 
-		//if local
+		//small ones
+		let chunk_manager = SmallChunkManager::get_owner(addr);
+		debug_assert!(chunk_manager == Some(self.get_chunk_manager(addr)));
+		match chunk_manager {
+			Some(chunk_manager) => self.free_from(addr, chunk_manager),
+			None => {},
+		}
+	}
+
+	/// Free the address to the given chunk manager, going through the remote free
+	/// queue if it belongs to another thread.
+	fn free_from(&mut self,addr: Addr,mut chunk_manager: ChunkManagerPtr) {
+		//if local, reuse the manager to not walk the registry twice
 		if self.is_distant_manager(chunk_manager.clone()) {
+			self.allocator.get_stats().on_remote_free();
 			chunk_manager.remote_free(addr);
 		} else {
-			self.allocator.free(addr);
+			self.allocator.free_from(addr, chunk_manager);
 		}
 	}
 
@@ -324,13 +703,19 @@ impl ThreadNumaAllocator {
 				//TODO : made this cas optional for resistant mode
 				//allocWarning("The old segment isn't managed by current memory allocator, try to copy, but create a memory leak and may segfault during unsafe copy.");
 				res = self.malloc(size);
-				unsafe{libc::memcpy(res as *mut libc::c_void,ptr as *mut libc::c_void,size)};
-			} else if self.is_distant_manager(chunk_manager.clone()) {
+				if res != NULL {
+					unsafe{libc::memcpy(res as *mut libc::c_void,ptr as *mut libc::c_void,size)};
+				}
+			} else if self.is_distant_manager(chunk_manager.clone()) || !self.allocator.is_local_chunk_manager(chunk_manager.clone()) {
+				//chunks of other threads are moved here so their thread cache is never
+				//touched, on out of memory the old chunk is left untouched
 				let new_ptr = self.malloc(size);
-				let inner_size = chunk_manager.get_inner_size(ptr);
-				let copy_size = min(size,inner_size);
-				unsafe{libc::memcpy(new_ptr as * mut libc::c_void,ptr as * mut libc::c_void,copy_size)};
-				self.free(ptr);
+				if new_ptr != NULL {
+					let inner_size = chunk_manager.get_inner_size(ptr);
+					let copy_size = min(size,inner_size);
+					unsafe{libc::memcpy(new_ptr as * mut libc::c_void,ptr as * mut libc::c_void,copy_size)};
+					self.free(ptr);
+				}
 				res = new_ptr;
 			} else {
 				let parent_chunk_manager = chunk_manager.get_parent_chunk_manager();
@@ -389,10 +774,48 @@ impl NumaAllocatorHandler {
 	pub fn get_numa_allocator(&mut self) -> SharedPtrBox<NumaAllocator> {
 		return self.numa_allocator.clone();
 	}
+
+	/// Aggregate the statistics of the whole allocator.
+	pub fn collect_stats(&self) -> StatsReport {
+		return self.numa_allocator.collect_stats();
+	}
+
+	/// Return unused memory to the OS, see NumaAllocator::trim().
+	pub fn trim(&mut self) -> Size {
+		return self.numa_allocator.trim();
+	}
+
+	/// Apply a mallopt() parameter, see NumaAllocator::mallopt().
+	pub fn mallopt(&mut self, param: i32, value: i32) -> bool {
+		return self.numa_allocator.mallopt(param, value);
+	}
+
+	/// Build the detailed report of the whole allocator.
+	pub fn collect_details(&self) -> DetailedReport {
+		return self.numa_allocator.collect_details();
+	}
 }
 
 impl ThreadNumaAllocatorHandler {
+	#[inline]
 	pub fn new() -> Self {
+		//fast path, only touch the TLS, being set also mean the global init is done
+		let ptr = unsafe{TLS_THREAD_ALLOCATOR};
+		if ptr != NULL {
+			let mut allocator: SharedPtrBox<ThreadNumaAllocator> = SharedPtrBox::new_addr(ptr);
+			allocator.flush_remote();
+			return Self {
+				allocator: allocator
+			};
+		}
+
+		//first call of the thread
+		Self::new_slow()
+	}
+
+	/// Init the allocator if needed and setup the one of the current thread.
+	#[inline(never)]
+	fn new_slow() -> Self {
 		unsafe {
 			// TODO need to implement a full atomic based spinlock to avoid dual init
 			if GBL_NUMA_ALLOCATOR == 0 {
@@ -400,27 +823,34 @@ impl ThreadNumaAllocatorHandler {
 			}
 			
 			//get thread specific
-			let ptr = libc::pthread_getspecific(GBL_PTHREAD_KEY) as Addr;
+			let mut ptr = libc::pthread_getspecific(GBL_PTHREAD_KEY) as Addr;
 
-			//init
+			//init, the key is still used to get the thread exit destructor
 			if ptr == NULL {
 				let mut numa_allocator_handler = NumaAllocatorHandler::new();
 				let allocator = numa_allocator_handler.get_numa_allocator().get_new_thread_allocator();
 				libc::pthread_setspecific(GBL_PTHREAD_KEY, allocator.get_addr() as * mut libc::c_void);
-				Self {
-					allocator: allocator
-				}
-			} else {
-				let mut allocator: SharedPtrBox<ThreadNumaAllocator> = SharedPtrBox::new_addr(ptr as Addr);
-				allocator.flush_remote();
-				//return
-				Self {
-					allocator: allocator
-				}
+				ptr = allocator.get_addr();
+			}
+
+			//cache for next calls
+			TLS_THREAD_ALLOCATOR = ptr;
+			
+			//return
+			let mut allocator: SharedPtrBox<ThreadNumaAllocator> = SharedPtrBox::new_addr(ptr);
+			allocator.flush_remote();
+			Self {
+				allocator: allocator
 			}
 		}
 	}
 
+	/// Rebind the thread allocator to the NUMA node the thread is currently bound to.
+	pub fn numa_rebind(&mut self) {
+		let mut numa_allocator_handler = NumaAllocatorHandler::new();
+		numa_allocator_handler.get_numa_allocator().rebind_thread_allocator(self.allocator.clone());
+	}
+
 	#[inline]
 	pub fn malloc(&mut self,size: Size) -> Addr {
 		return self.allocator.malloc(size);
@@ -461,6 +891,11 @@ impl ThreadNumaAllocatorHandler {
 		self.allocator.free(addr);
 	}
 
+	#[inline]
+	pub fn free_sized(&mut self,addr: Addr,size: Size) {
+		self.allocator.free_sized(addr, size);
+	}
+
 	#[inline]
 	pub fn realloc(&mut self,ptr: Addr,size:Size) -> Addr {
 		return self.allocator.realloc(ptr, size);
@@ -610,10 +1045,56 @@ mod tests
 {
 	extern crate std;
 	use posix::numa::*;
+	use common::stats::STATS_VERSION;
+	use chunk::small::manager::SMALL_SIZE_CLASSES;
+	use portability::libc;
+	use core::ops::{Deref,DerefMut};
 
 	// CAUTION HERE WE USE A GLOBAL ALLOCATOR SO TEST MUST BE WRITTEN
 	// TO BE REPRODUCIBLE AND NOT INTERFER TOGETHER
 
+	/// Private NumaAllocator built in its own egg memory as done by init(),
+	/// all its memory is returned to the OS on drop.
+	struct TestNumaAllocator {
+		allocator: SharedPtrBox<NumaAllocator>,
+		size: Size,
+	}
+
+	impl TestNumaAllocator {
+		fn new() -> Self {
+			let size = NumaAllocator::egg_mem_size();
+			let ptr = osmem::mmap(0, size);
+			let mut allocator: SharedPtrBox<NumaAllocator> = SharedPtrBox::new_addr(ptr);
+			*allocator.get_mut() = NumaAllocator::new(ptr + mem::size_of::<NumaAllocator>());
+			Self {
+				allocator: allocator,
+				size: size,
+			}
+		}
+	}
+
+	impl Deref for TestNumaAllocator {
+		type Target = SharedPtrBox<NumaAllocator>;
+
+		fn deref(&self) -> &SharedPtrBox<NumaAllocator> {
+			&self.allocator
+		}
+	}
+
+	impl DerefMut for TestNumaAllocator {
+		fn deref_mut(&mut self) -> &mut SharedPtrBox<NumaAllocator> {
+			&mut self.allocator
+		}
+	}
+
+	impl Drop for TestNumaAllocator {
+		fn drop(&mut self) {
+			self.allocator.trim();
+			self.allocator.region_registry.unmap_all_memory();
+			osmem::munmap(self.allocator.get_addr(), self.size);
+		}
+	}
+
 	#[test]
 	fn basic_1() {
 		let mut allocator = ThreadNumaAllocatorHandler::new();
@@ -654,4 +1135,524 @@ mod tests
 		allocator.free(ptr2);
 		allocator.free(ptr3);
 	}
-}
+
+	#[test]
+	fn valloc() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		for size in [0, 1, 100, 4096, 64*1024, 4*1024*1024].iter() {
+			let mut ptr = allocator.valloc(*size);
+			assert_ne!(ptr, 0);
+			assert_eq!(ptr % SMALL_PAGE_SIZE, 0);
+			assert!(allocator.get_inner_size(ptr) >= *size);
+			assert!(allocator.get_total_size(ptr) >= allocator.get_inner_size(ptr));
+			unsafe{*(ptr as * mut u8) = 42};
+
+			//grow and shrink through all the managers
+			for new_size in [8*1024*1024, 128*1024, 32].iter() {
+				ptr = allocator.realloc(ptr, *new_size);
+				assert_eq!(unsafe{*(ptr as * mut u8)}, 42);
+				assert!(allocator.get_inner_size(ptr) >= *new_size);
+			}
+			allocator.free(ptr);
+		}
+	}
+
+	#[test]
+	fn basic_align() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		let mut sizes = std::vec::Vec::new();
+		for size in 0..1024 {
+			sizes.push(size);
+		}
+		sizes.extend_from_slice(&[4000, 64*1024+3, 1024*1024-5, 4*1024*1024+7]);
+		for size in sizes.iter() {
+			let ptr = allocator.malloc(*size);
+			assert_eq!(ptr % BASIC_ALIGN, 0);
+			let ptr = allocator.realloc(ptr, *size + 100);
+			assert_eq!(ptr % BASIC_ALIGN, 0);
+			allocator.free(ptr);
+			let ptr = allocator.memalign(8, *size);
+			assert_eq!(ptr % BASIC_ALIGN, 0);
+			allocator.free(ptr);
+			let ptr = allocator.calloc(1, *size);
+			assert_eq!(ptr % BASIC_ALIGN, 0);
+			allocator.free(ptr);
+		}
+	}
+
+	#[test]
+	fn calloc_reuse() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		for size in [16, 100, 4000, 64*1024+3, 1024*1024-5, 4*1024*1024+7].iter() {
+			for _ in 0..4 {
+				//dirty memory then get it back with calloc
+				let ptr = allocator.malloc(*size);
+				libc::memset(ptr, 0xFF, *size);
+				allocator.free(ptr);
+
+				let ptr = allocator.calloc(*size, 1);
+				assert_ne!(ptr, 0);
+				for i in 0..*size {
+					assert_eq!(unsafe{*((ptr + i) as * const u8)}, 0);
+				}
+				allocator.free(ptr);
+			}
+		}
+	}
+
+	#[test]
+	fn calloc_overflow() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		assert_eq!(allocator.calloc(Size::max_value() / 2 + 1, 2), NULL);
+		assert_eq!(allocator.calloc(2, Size::max_value() / 2 + 1), NULL);
+		let ptr = allocator.calloc(Size::max_value(), 0);
+		assert_ne!(ptr, NULL);
+		allocator.free(ptr);
+	}
+
+	#[test]
+	fn bad_alignment() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		let mut ptr: * mut Addr = 0 as * mut Addr;
+
+		//posix_memalign
+		assert_eq!(allocator.posix_memalign(&mut ptr, 0, 16), libc::EINVAL);
+		assert_eq!(allocator.posix_memalign(&mut ptr, 4, 16), libc::EINVAL);
+		assert_eq!(allocator.posix_memalign(&mut ptr, 24, 16), libc::EINVAL);
+		assert_eq!(allocator.posix_memalign(&mut ptr, 64, 16), 0);
+		assert_eq!(ptr as Addr % 64, 0);
+		allocator.free(ptr as Addr);
+
+		//aligned_alloc
+		assert_eq!(allocator.aligned_alloc(0, 16), NULL);
+		assert_eq!(allocator.aligned_alloc(24, 16), NULL);
+		let ptr = allocator.aligned_alloc(32, 16);
+		assert_eq!(ptr % 32, 0);
+		allocator.free(ptr);
+	}
+
+	#[test]
+	fn free_sized() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		for size in [0, 16, 100, 4000, 64*1024+3, 4*1024*1024+7].iter() {
+			let ptr = allocator.malloc(*size);
+			assert_ne!(ptr, NULL);
+			allocator.free_sized(ptr, *size);
+		}
+		allocator.free_sized(NULL, 0);
+	}
+
+	#[test]
+	fn free_sized_remote() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+
+		//small chunk from another thread goes back to its owner, not in our cache
+		let ptr = std::thread::spawn(|| {
+			ThreadNumaAllocatorHandler::new().malloc(32)
+		}).join().unwrap();
+		assert_ne!(ptr, NULL);
+		assert!(SmallChunkManager::get_owner(ptr) == Some(allocator.allocator.get_chunk_manager(ptr)));
+		allocator.free_sized(ptr, 32);
+		let local = allocator.malloc(32);
+		assert_ne!(local, ptr);
+		allocator.free_sized(local, 32);
+		assert_eq!(allocator.malloc(32), local);
+		allocator.free_sized(local, 32);
+
+		//aligned ones use the alignment as size
+		let ptr = allocator.memalign(256, 8);
+		assert_eq!(ptr % 256, 0);
+		allocator.free_sized(ptr, 256);
+	}
+
+	#[test]
+	fn realloc_remote() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		let (to_main, from_thread) = std::sync::mpsc::channel();
+		let (to_thread, from_main) = std::sync::mpsc::channel();
+
+		//the owner keeps a chunk in its thread cache while we realloc one of its chunks
+		let owner = std::thread::spawn(move || {
+			let mut allocator = ThreadNumaAllocatorHandler::new();
+			let ptr = allocator.malloc(32);
+			let cached = allocator.malloc(64);
+			allocator.free(cached);
+			to_main.send((ptr, cached)).unwrap();
+			from_main.recv().unwrap();
+			assert_eq!(allocator.malloc(64), cached);
+			allocator.free(cached);
+		});
+		let (ptr, cached) = from_thread.recv().unwrap();
+
+		//move it to our thread, the owner cache must be left untouched
+		libc::memset(ptr, 1, 32);
+		let new_ptr = allocator.realloc(ptr, 64);
+		assert_ne!(new_ptr, NULL);
+		assert_ne!(new_ptr, cached);
+		let manager = allocator.allocator.get_chunk_manager(new_ptr);
+		assert!(allocator.allocator.allocator.is_local_chunk_manager(manager));
+		assert_eq!(unsafe{*(new_ptr as *const u8).offset(31)}, 1);
+		allocator.free(new_ptr);
+
+		to_thread.send(()).unwrap();
+		owner.join().unwrap();
+	}
+
+	#[test]
+	fn memalign_small() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		let mut align = BASIC_ALIGN;
+		while align <= SMALL_PAGE_SIZE {
+			for size in SMALL_SIZE_CLASSES.iter() {
+				let ptr = allocator.memalign(align, *size);
+				assert_ne!(ptr, 0);
+				assert_eq!(ptr % align, 0);
+				assert!(allocator.get_inner_size(ptr) >= *size);
+				allocator.free(ptr);
+			}
+			align *= 2;
+		}
+	}
+
+	#[test]
+	fn memalign_not_power_of_2() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		for align in [0, 3, 24, 100, 1000, 3000, 5000, 40000].iter() {
+			for size in [8, 100, 4096, 64*1024+1].iter() {
+				let ptr = allocator.memalign(*align, *size);
+				assert_ne!(ptr, 0);
+				assert_eq!(ptr % align.next_power_of_two(), 0);
+				assert!(allocator.get_inner_size(ptr) >= *size);
+				allocator.free(ptr);
+			}
+		}
+		assert_eq!(allocator.memalign(usize::max_value(), 8), NULL);
+	}
+
+	#[test]
+	fn pvalloc() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		for size in [0, 1, 4096, 4097, 64*1024+1, 4*1024*1024+1].iter() {
+			let ptr = allocator.pvalloc(*size);
+			assert_ne!(ptr, 0);
+			assert_eq!(ptr % SMALL_PAGE_SIZE, 0);
+			let pages = if *size == 0 {1} else {(*size + SMALL_PAGE_SIZE - 1) / SMALL_PAGE_SIZE};
+			assert!(allocator.get_inner_size(ptr) >= pages * SMALL_PAGE_SIZE);
+			unsafe{*((ptr + pages * SMALL_PAGE_SIZE - 1) as * mut u8) = 42};
+			let ptr = allocator.realloc(ptr, pages * SMALL_PAGE_SIZE + 1);
+			assert_eq!(unsafe{*((ptr + pages * SMALL_PAGE_SIZE - 1) as * mut u8)}, 42);
+			allocator.free(ptr);
+		}
+	}
+
+	#[test]
+	fn node_memory_sources() {
+		let mut numa_allocator = TestNumaAllocator::new();
+
+		//fallback
+		let fallback = numa_allocator.get_memory_source(-1);
+		assert_eq!(fallback.get_numa_node(), -1);
+		assert!(fallback == numa_allocator.get_memory_source(NUMA_MAX_NODES as i32));
+
+		//node
+		let node0 = numa_allocator.get_memory_source(0);
+		assert_eq!(node0.get_numa_node(), 0);
+		assert!(node0 != fallback);
+		assert!(node0 == numa_allocator.get_memory_source(0));
+
+		//allocate from node
+		let mut local = numa_allocator.get_new_local_allocator(0);
+		let ptr1 = local.malloc(4*1024*1024, BASIC_ALIGN, false);
+		assert_ne!(ptr1, 0);
+		local.free(ptr1);
+	}
+
+	#[test]
+	fn recycle_thread_allocator() {
+		let mut numa_allocator = TestNumaAllocator::new();
+
+		//first thread
+		let mut thread1 = numa_allocator.get_new_thread_allocator();
+		let local1 = thread1.allocator.get_addr();
+		let ptr1 = thread1.malloc(64);
+		numa_allocator.release_thread_allocator(thread1);
+
+		//second thread inherit it
+		let mut thread2 = numa_allocator.get_new_thread_allocator();
+		assert_eq!(thread2.allocator.get_addr(), local1);
+		thread2.free(ptr1);
+
+		//third get a new one
+		let thread3 = numa_allocator.get_new_thread_allocator();
+		assert_ne!(thread3.allocator.get_addr(), local1);
+	}
+
+	#[test]
+	fn thread_exit() {
+		let mut handlers = std::vec::Vec::new();
+		for _ in 0..16 {
+			handlers.push(std::thread::spawn(|| {
+				let mut allocator = ThreadNumaAllocatorHandler::new();
+				let ptr = allocator.malloc(32);
+				assert_ne!(ptr, 0);
+				allocator.free(ptr);
+			}));
+		}
+
+		for handler in handlers {
+			handler.join().unwrap();
+		}
+	}
+
+	#[test]
+	fn tcache() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		let hits = || {
+			let mut report = StatsReport::new();
+			ThreadNumaAllocatorHandler::new().allocator.allocator.get_stats().accumulate(&mut report);
+			report.tcache_hits
+		};
+
+		//freed chunk is reused from the cache
+		let ptr1 = allocator.malloc(48);
+		allocator.free(ptr1);
+		let before = hits();
+		let ptr2 = allocator.malloc(48);
+		assert_eq!(ptr1, ptr2);
+		assert_eq!(hits(), before + 1);
+
+		//zeroed when needed
+		libc::memset(ptr2, 0xFF, 48);
+		allocator.free(ptr2);
+		let ptr3 = allocator.calloc(1, 48);
+		assert_eq!(ptr3, ptr2);
+		for i in 0..48 {
+			assert_eq!(unsafe{*((ptr3 + i) as * const u8)}, 0);
+		}
+
+		//overflow the cache and come back
+		let mut ptrs = std::vec::Vec::new();
+		for _ in 0..1000 {
+			ptrs.push(allocator.malloc(16));
+		}
+		for ptr in ptrs.iter() {
+			allocator.free(*ptr);
+		}
+		allocator.free(ptr3);
+	}
+
+	#[test]
+	fn tcache_stats() {
+		let mut allocator = ThreadNumaAllocatorHandler::new();
+		let report = || {
+			let mut report = StatsReport::new();
+			ThreadNumaAllocatorHandler::new().allocator.allocator.get_stats().accumulate(&mut report);
+			report
+		};
+
+		//the size class is accounted
+		let before = report();
+		let ptr = allocator.malloc(20);
+		let after = report();
+		assert_eq!(after.allocs, before.allocs + 1);
+		assert_eq!(after.requested_bytes, before.requested_bytes + 20);
+		assert_eq!(after.inner_bytes, before.inner_bytes + 32);
+		assert_eq!(after.total_bytes_in_use, before.total_bytes_in_use.wrapping_add(32));
+
+		//nothing left in use once in the cache
+		allocator.free(ptr);
+		let after = report();
+		assert_eq!(after.frees, before.frees + 1);
+		assert_eq!(after.inner_bytes_in_use, before.inner_bytes_in_use);
+		assert_eq!(after.total_bytes_in_use, before.total_bytes_in_use);
+	}
+
+	#[test]
+	fn tls_fast_path() {
+		//same allocator on each call, cached in the TLS
+		let first = ThreadNumaAllocatorHandler::new().allocator.get_addr();
+		let second = ThreadNumaAllocatorHandler::new().allocator.get_addr();
+		assert_eq!(first, second);
+		assert_eq!(unsafe{TLS_THREAD_ALLOCATOR}, first);
+		assert_eq!(unsafe{super::libc::pthread_getspecific(GBL_PTHREAD_KEY)} as Addr, first);
+
+		//other threads get their own
+		let other = std::thread::spawn(|| {
+			ThreadNumaAllocatorHandler::new().allocator.get_addr()
+		}).join().unwrap();
+		assert_ne!(other, first);
+	}
+
+	#[test]
+	fn rebind_thread_allocator() {
+		let mut numa_allocator = TestNumaAllocator::new();
+
+		//alloc before
+		let mut thread_alloc = numa_allocator.get_new_thread_allocator();
+		let ptr1 = thread_alloc.malloc(4*1024*1024);
+
+		//move to node 0
+		let mm_source = numa_allocator.get_memory_source(0);
+		thread_alloc.rebind(0, mm_source);
+		assert_eq!(thread_alloc.get_numa_node(), 0);
+
+		//alloc after & free old one
+		let ptr2 = thread_alloc.malloc(4*1024*1024);
+		assert_ne!(ptr2, NULL);
+		thread_alloc.free(ptr1);
+		thread_alloc.free(ptr2);
+
+		//back to the node detected for the current thread
+		numa_allocator.rebind_thread_allocator(thread_alloc.clone());
+		assert_eq!(thread_alloc.get_numa_node(), libnuma::numa_detect_affinity());
+	}
+
+	#[test]
+	fn rebind_no_old_reuse() {
+		let mut numa_allocator = TestNumaAllocator::new();
+		let registry = numa_allocator.region_registry.clone();
+
+		//alloc & free before, the small one stays in the thread cache
+		let mut thread_alloc = numa_allocator.get_new_thread_allocator();
+		let sizes = [48, 1024, 64*1024];
+		let mut old = [NULL; 3];
+		for (i,size) in sizes.iter().enumerate() {
+			let ptr = thread_alloc.malloc(*size);
+			old[i] = registry.get_segment(ptr).unwrap().get_root_addr();
+			thread_alloc.free(ptr);
+		}
+
+		//move to another source
+		let numa_node = if thread_alloc.get_numa_node() < 0 {0} else {-1};
+		let mm_source = numa_allocator.get_memory_source(numa_node);
+		thread_alloc.rebind(numa_node, mm_source);
+
+		//nothing from the old node
+		for size in sizes.iter() {
+			let ptr = thread_alloc.malloc(*size);
+			assert_ne!(ptr, NULL);
+			let segment = registry.get_segment(ptr).unwrap().get_root_addr();
+			assert!(!old.contains(&segment));
+			thread_alloc.free(ptr);
+		}
+	}
+
+	#[test]
+	fn collect_stats() {
+		let mut numa_allocator = TestNumaAllocator::new();
+
+		//do some allocs
+		let mut thread1 = numa_allocator.get_new_thread_allocator();
+		let mut thread2 = numa_allocator.get_new_thread_allocator();
+		let ptr1 = thread1.malloc(16);
+		let ptr2 = thread2.malloc(4*1024*1024);
+		let ptr3 = thread2.malloc(1024);
+		thread1.free(ptr1);
+		let ptr3 = thread2.realloc(ptr3, 2048);
+
+		//check
+		let report = numa_allocator.collect_stats();
+		assert_eq!(report.version, STATS_VERSION);
+		assert_eq!(report.threads, 2);
+		assert!(report.allocs >= 3);
+		assert!(report.frees >= 1);
+		assert!(report.reallocs + report.frees >= 2);
+		assert!(report.requested_bytes >= 16 + 4*1024*1024 + 1024);
+		assert!(report.inner_bytes_in_use >= 4*1024*1024 + 2048);
+		assert!(report.total_bytes_in_use >= report.inner_bytes_in_use);
+		assert!(report.small_chunks >= report.small_chunks_used);
+		assert!(report.mmap_calls >= 1);
+
+		//details
+		let details = numa_allocator.collect_details();
+		assert_eq!(details.summary.threads, 2);
+		assert!(details.huge_segments >= 1);
+		assert!(details.huge_bytes >= 4*1024*1024);
+		assert!(details.medium.iter().any(|x| x.chunks > 0));
+
+		thread2.free(ptr2);
+		thread2.free(ptr3);
+	}
+
+	#[test]
+	fn decay() {
+		let mut numa_allocator = TestNumaAllocator::new();
+
+		//fill the cache
+		let mut thread_alloc = numa_allocator.get_new_thread_allocator();
+		let ptr1 = thread_alloc.malloc(4*1024*1024);
+		thread_alloc.free(ptr1);
+		let cached = numa_allocator.collect_stats().mmsrc_cached_bytes;
+		assert!(cached > 0);
+
+		//returned after the decay
+		let now = get_time_ms();
+		assert_eq!(numa_allocator.decay(now), 0);
+		assert_eq!(numa_allocator.decay(now + config::get().mmsrc_muzzy_decay) as u64, cached);
+		assert_eq!(numa_allocator.collect_stats().mmsrc_cached_bytes, 0);
+	}
+
+	#[test]
+	fn trim() {
+		let mut numa_allocator = TestNumaAllocator::new();
+
+		//fill the cache & keep a free medium chunk
+		let mut thread_alloc = numa_allocator.get_new_thread_allocator();
+		let keep = thread_alloc.malloc(1024);
+		let ptr1 = thread_alloc.malloc(64*1024);
+		let ptr2 = thread_alloc.malloc(1024*1024);
+		thread_alloc.free(ptr1);
+		thread_alloc.free(ptr2);
+
+		//trim
+		assert!(numa_allocator.trim() > 0);
+		assert_eq!(numa_allocator.collect_stats().mmsrc_cached_bytes, 0);
+
+		//still usable
+		let ptr3 = thread_alloc.malloc(64*1024);
+		assert_ne!(ptr3, NULL);
+		thread_alloc.free(ptr3);
+		thread_alloc.free(keep);
+	}
+
+	#[test]
+	fn trim_other_thread_tcache() {
+		let mut numa_allocator = TestNumaAllocator::new();
+		let flushes = |thread_alloc: &SharedPtrBox<ThreadNumaAllocator>| {
+			let mut report = StatsReport::new();
+			thread_alloc.allocator.get_stats().accumulate(&mut report);
+			report.tcache_flushes
+		};
+
+		//keep a chunk in the cache of an allocator not owned by the current thread
+		let mut thread_alloc = numa_allocator.get_new_thread_allocator();
+		let ptr1 = thread_alloc.malloc(48);
+		thread_alloc.free(ptr1);
+		let before = flushes(&thread_alloc);
+
+		//trim only ask for the flush
+		numa_allocator.trim();
+		assert_eq!(flushes(&thread_alloc), before);
+
+		//the owner does it on its next call
+		thread_alloc.flush_remote();
+		assert_eq!(flushes(&thread_alloc), before + 1);
+		thread_alloc.flush_remote();
+		assert_eq!(flushes(&thread_alloc), before + 1);
+	}
+
+	#[test]
+	fn mallopt() {
+		let mut numa_allocator = TestNumaAllocator::new();
+
+		//invalid does not change config
+		let before = *config::get();
+		assert_eq!(numa_allocator.mallopt(-3, -1), false);
+		assert_eq!(numa_allocator.mallopt(1234, 0), false);
+		assert_eq!(*config::get(), before);
+
+		//accepted but without effect
+		assert!(numa_allocator.mallopt(-8, 2));
+		assert_eq!(*config::get(), before);
+	}
+}
\ No newline at end of file
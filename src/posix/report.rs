@@ -0,0 +1,228 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+/// This module format the statistics of the allocator as a human readable
+/// report (malloc_stats), as a structured XML dump (malloc_info) or as JSON
+/// to be archived by job scripts. It only rely on core::fmt so it does not
+/// allocate memory.
+
+//import
+use common::stats::DetailedReport;
+use common::config::{self,ReportMode};
+use posix::numa::NumaAllocatorHandler;
+use portability::libc::FdWriter;
+use core::fmt::{Result,Write};
+
+/// Compute a percentage without risking division by zero.
+fn percent(value: u64, total: u64) -> u64 {
+	if total == 0 {
+		0
+	} else {
+		value * 100 / total
+	}
+}
+
+/// Print the human readable report.
+///
+/// @param out Where to write.
+/// @param details The report to print.
+pub fn print_text<W: Write>(out: &mut W, details: &DetailedReport) -> Result {
+	//summary
+	let s = &details.summary;
+	writeln!(out, "================= HPC allocator statistics =================")?;
+	writeln!(out, "threads              : {}", s.threads)?;
+	writeln!(out, "allocs               : {}", s.allocs)?;
+	writeln!(out, "frees                : {} (remote {})", s.frees, s.remote_frees)?;
+	writeln!(out, "reallocs             : {}", s.reallocs)?;
+	writeln!(out, "requested bytes      : {}", s.requested_bytes)?;
+	writeln!(out, "inner bytes          : {} (in use {})", s.inner_bytes, s.inner_bytes_in_use)?;
+	writeln!(out, "total bytes          : {} (in use {})", s.total_bytes, s.total_bytes_in_use)?;
+	writeln!(out, "tcache hits          : {} (refills {}, flushes {})", s.tcache_hits, s.tcache_refills, s.tcache_flushes)?;
+
+	//small
+	writeln!(out, "------------------------ small -----------------------------")?;
+	writeln!(out, "{:>10} {:>10} {:>12} {:>12} {:>6}", "size", "runs", "chunks", "used", "fill")?;
+	for class in details.small.iter() {
+		writeln!(out, "{:>10} {:>10} {:>12} {:>12} {:>5}%", class.size, class.runs, class.chunks, class.chunks_used, percent(class.chunks_used, class.chunks))?;
+	}
+
+	//medium
+	writeln!(out, "----------------------- medium free ------------------------")?;
+	writeln!(out, "{:>20} {:>12} {:>16}", "max size", "chunks", "bytes")?;
+	for list in details.medium.iter() {
+		if list.chunks > 0 {
+			writeln!(out, "{:>20} {:>12} {:>16}", list.max_size, list.chunks, list.bytes)?;
+		}
+	}
+
+	//huge
+	writeln!(out, "------------------------- huge -----------------------------")?;
+	writeln!(out, "segments             : {}", details.huge_segments)?;
+	writeln!(out, "bytes                : {}", details.huge_bytes)?;
+
+	//memory source
+	writeln!(out, "--------------------- memory source ------------------------")?;
+	writeln!(out, "cached bytes         : {}", s.mmsrc_cached_bytes)?;
+	writeln!(out, "cached blocs         : {}", details.mmsrc_blocs)?;
+	writeln!(out, "mmap calls           : {}", s.mmap_calls)?;
+	writeln!(out, "munmap calls         : {}", s.munmap_calls)?;
+	writeln!(out, "mremap calls         : {}", s.mremap_calls)?;
+	writeln!(out, "============================================================")
+}
+
+/// Print the structured XML report in the spirit of glibc malloc_info().
+///
+/// @param out Where to write.
+/// @param details The report to print.
+pub fn print_xml<W: Write>(out: &mut W, details: &DetailedReport) -> Result {
+	//summary
+	let s = &details.summary;
+	writeln!(out, "<malloc version=\"hpc-{}\">", s.version)?;
+	writeln!(out, "<summary threads=\"{}\" allocs=\"{}\" frees=\"{}\" remote_frees=\"{}\" reallocs=\"{}\"/>",
+		s.threads, s.allocs, s.frees, s.remote_frees, s.reallocs)?;
+	writeln!(out, "<bytes requested=\"{}\" inner=\"{}\" total=\"{}\" inner_in_use=\"{}\" total_in_use=\"{}\"/>",
+		s.requested_bytes, s.inner_bytes, s.total_bytes, s.inner_bytes_in_use, s.total_bytes_in_use)?;
+
+	writeln!(out, "<tcache hits=\"{}\" refills=\"{}\" flushes=\"{}\"/>", s.tcache_hits, s.tcache_refills, s.tcache_flushes)?;
+
+	//small
+	writeln!(out, "<small>")?;
+	for class in details.small.iter() {
+		writeln!(out, "<class size=\"{}\" runs=\"{}\" chunks=\"{}\" used=\"{}\"/>", class.size, class.runs, class.chunks, class.chunks_used)?;
+	}
+	writeln!(out, "</small>")?;
+
+	//medium
+	writeln!(out, "<medium>")?;
+	for list in details.medium.iter() {
+		if list.chunks > 0 {
+			writeln!(out, "<free max_size=\"{}\" chunks=\"{}\" bytes=\"{}\"/>", list.max_size, list.chunks, list.bytes)?;
+		}
+	}
+	writeln!(out, "</medium>")?;
+
+	//huge & memory source
+	writeln!(out, "<huge segments=\"{}\" bytes=\"{}\"/>", details.huge_segments, details.huge_bytes)?;
+	writeln!(out, "<mmsource cached_bytes=\"{}\" cached_blocs=\"{}\" mmap=\"{}\" munmap=\"{}\" mremap=\"{}\"/>",
+		s.mmsrc_cached_bytes, details.mmsrc_blocs, s.mmap_calls, s.munmap_calls, s.mremap_calls)?;
+	writeln!(out, "</malloc>")
+}
+
+/// Print the same content than the XML report as a JSON object.
+///
+/// @param out Where to write.
+/// @param details The report to print.
+pub fn print_json<W: Write>(out: &mut W, details: &DetailedReport) -> Result {
+	//summary
+	let s = &details.summary;
+	writeln!(out, "{{")?;
+	writeln!(out, "\t\"version\": \"hpc-{}\",", s.version)?;
+	writeln!(out, "\t\"summary\": {{\"threads\": {}, \"allocs\": {}, \"frees\": {}, \"remote_frees\": {}, \"reallocs\": {}}},",
+		s.threads, s.allocs, s.frees, s.remote_frees, s.reallocs)?;
+	writeln!(out, "\t\"bytes\": {{\"requested\": {}, \"inner\": {}, \"total\": {}, \"inner_in_use\": {}, \"total_in_use\": {}}},",
+		s.requested_bytes, s.inner_bytes, s.total_bytes, s.inner_bytes_in_use, s.total_bytes_in_use)?;
+	writeln!(out, "\t\"tcache\": {{\"hits\": {}, \"refills\": {}, \"flushes\": {}}},", s.tcache_hits, s.tcache_refills, s.tcache_flushes)?;
+
+	//small
+	writeln!(out, "\t\"small\": [")?;
+	for (i,class) in details.small.iter().enumerate() {
+		let sep = if i + 1 < details.small.len() {","} else {""};
+		writeln!(out, "\t\t{{\"size\": {}, \"runs\": {}, \"chunks\": {}, \"used\": {}}}{}", class.size, class.runs, class.chunks, class.chunks_used, sep)?;
+	}
+	writeln!(out, "\t],")?;
+
+	//medium, no trailing comma so separate before each entry
+	write!(out, "\t\"medium\": [")?;
+	let mut sep = "";
+	for list in details.medium.iter() {
+		if list.chunks > 0 {
+			write!(out, "{}\n\t\t{{\"max_size\": {}, \"chunks\": {}, \"bytes\": {}}}", sep, list.max_size, list.chunks, list.bytes)?;
+			sep = ",";
+		}
+	}
+	writeln!(out, "\n\t],")?;
+
+	//huge & memory source
+	writeln!(out, "\t\"huge\": {{\"segments\": {}, \"bytes\": {}}},", details.huge_segments, details.huge_bytes)?;
+	writeln!(out, "\t\"mmsource\": {{\"cached_bytes\": {}, \"cached_blocs\": {}, \"mmap\": {}, \"munmap\": {}, \"mremap\": {}}}",
+		s.mmsrc_cached_bytes, details.mmsrc_blocs, s.mmap_calls, s.munmap_calls, s.mremap_calls)?;
+	writeln!(out, "}}")
+}
+
+/// Print the report on stderr in the format selected by the config. To be
+/// registered with atexit().
+pub extern "C" fn print_at_exit() {
+	let mut out = FdWriter::new(2);
+	let details = NumaAllocatorHandler::new().collect_details();
+	let _ = match config::get().report_at_exit {
+		ReportMode::None => Ok(()),
+		ReportMode::Text => print_text(&mut out, &details),
+		ReportMode::Xml => print_xml(&mut out, &details),
+		ReportMode::Json => print_json(&mut out, &details),
+	};
+}
+
+#[cfg(test)]
+mod tests
+{
+	extern crate std;
+	use posix::report::*;
+	use self::std::string::String;
+
+	#[test]
+	fn text() {
+		let mut details = DetailedReport::new();
+		details.summary.allocs = 42;
+		details.small[0].size = 8;
+		details.small[0].chunks = 10;
+		details.small[0].chunks_used = 5;
+		details.medium[3].max_size = 64;
+		details.medium[3].chunks = 2;
+
+		let mut out = String::new();
+		print_text(&mut out, &details).unwrap();
+		assert!(out.contains("allocs               : 42"));
+		assert!(out.contains("   50%"));
+		assert!(out.contains("                  64            2"));
+	}
+
+	#[test]
+	fn xml() {
+		let mut details = DetailedReport::new();
+		details.huge_segments = 3;
+		details.small[1].size = 16;
+
+		let mut out = String::new();
+		print_xml(&mut out, &details).unwrap();
+		assert!(out.starts_with("<malloc version=\"hpc-2\">"));
+		assert!(out.contains("<class size=\"16\" runs=\"0\" chunks=\"0\" used=\"0\"/>"));
+		assert!(out.contains("<huge segments=\"3\" bytes=\"0\"/>"));
+		assert!(out.ends_with("</malloc>\n"));
+		assert!(!out.contains("<free "));
+	}
+
+	#[test]
+	fn json() {
+		let mut details = DetailedReport::new();
+		details.huge_segments = 3;
+		details.small[1].size = 16;
+		details.medium[2].max_size = 32;
+		details.medium[2].chunks = 1;
+		details.medium[5].max_size = 256;
+		details.medium[5].chunks = 4;
+
+		let mut out = String::new();
+		print_json(&mut out, &details).unwrap();
+		assert!(out.starts_with("{\n\t\"version\": \"hpc-2\","));
+		assert!(out.contains("{\"size\": 16, \"runs\": 0, \"chunks\": 0, \"used\": 0},\n"));
+		assert!(out.contains("\"medium\": [\n\t\t{\"max_size\": 32, \"chunks\": 1, \"bytes\": 0},\n\t\t{\"max_size\": 256, \"chunks\": 4, \"bytes\": 0}\n\t],"));
+		assert!(out.contains("\"huge\": {\"segments\": 3, \"bytes\": 0},"));
+		assert!(out.ends_with("}\n}\n"));
+		assert!(!out.contains(",\n\t]"));
+	}
+}
@@ -16,6 +16,8 @@ use mmsource::cached::CachedMMSource;
 use common::shared::SharedPtrBox;
 use common::types::{Addr,Size};
 use common::consts::*;
+use common::config;
+use common::oom;
 use common::traits::{ChunkManager};
 use core::mem;
 use portability::osmem;
@@ -82,6 +84,9 @@ pub fn init() {
 		return;
 	}
 
+	//load tuning from environment
+	config::init_from_env();
+
 	// calc size
 	let registry_size = mem::size_of::<RegionRegistry>();
 	let mm_source_size = mem::size_of::<CachedMMSource>();
@@ -93,6 +98,9 @@ pub fn init() {
 
 	// allocate
 	let ptr = osmem::mmap(0, total_size);
+	if ptr == NULL {
+		oom::fatal("the global allocator");
+	}
 
 	// unsaface global variable handling
 	unsafe {
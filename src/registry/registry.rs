@@ -25,6 +25,7 @@ use portability::osmem;
 use core::ptr;
 use core::mem;
 use common::shared::SharedPtrBox;
+use common::oom;
 
 pub type RegistryPtr = * const Region;
 pub type RegionRegistryPtr = SharedPtrBox<RegionRegistry>;
@@ -280,6 +281,9 @@ impl RegionRegistry {
 			if regions[id].is_null() {
 				// @todo this may be better to hardly control this address choice, maybe use the allocator when a first chain is available.
 				let addr = osmem::mmap(0,mem::size_of::<Region>());
+				if addr == NULL {
+					oom::fatal("a region of the registry");
+				}
 				let region = Region::new(addr,false);
 				// @todo PARALLEL check for atomic operation instead of lock 
 				regions[id] = region;
@@ -312,8 +316,9 @@ impl RegionRegistry {
 		id
 	}
 
-	/// Unmap all the registred memory. This might be usefull for some unit tests.
-	fn unmap_all_memory(&mut self) {
+	/// Unmap all the registred memory. This is used to drop a private arena in
+	/// one go and by some unit tests.
+	pub fn unmap_all_memory(&mut self) {
 		let mut regions = self.regions.lock();
 		
 		for i in 0..MAX_REGIONS {
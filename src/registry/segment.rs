@@ -13,12 +13,15 @@ use common::types::{Size,Addr};
 use common::traits::{ChunkManagerPtr};
 use common::consts::*;
 use common::shared::SharedPtrBox;
+use portability::libc;
 use core::mem;
 
 ///A region is a segment of the memory of a size at least 
 ///MACRO_BLOC_SIZE, it is used to be handled by the 
 ///memory source and registred into the region registry.
 ///It is handled by a chunk manager
+///The size must stay a multiple of BASIC_ALIGN as huge chunks start just after it.
+#[repr(align(16))]
 pub struct RegionSegment
 {
 	///Base address, this eat 8 bytes but permit to copy the struct instead of having to handle
@@ -26,6 +29,12 @@ pub struct RegionSegment
 	base: Addr,
 	///Keep track of the size of the segement
 	size: Size,
+	///Size of the pages backing the segment, HUGE_PAGE_SIZE for MAP_HUGETLB mappings.
+	page_size: Size,
+	///The pages are shared with other processes so madvise(MADV_DONTNEED) keeps the content.
+	shared: bool,
+	///NUMA node the pages are bound to, -1 if not bound.
+	numa_node: i32,
 	///pointer to the chunk manager to handle its content
 	manager: Option<ChunkManagerPtr>,
 }
@@ -46,9 +55,16 @@ impl RegionSegment {
 		//cast address into struct ref
 		let mut segment: SharedPtrBox<RegionSegment> = SharedPtrBox::new_addr(ptr);
 
+		//clear first so the alignment padding at the end of the struct does not
+		//keep an old padded chunk header which would be found by unpad()
+		libc::memset(ptr, 0, mem::size_of::<RegionSegment>());
+
 		//fill
 		segment.base = ptr;
 		segment.size = total_size;
+		segment.page_size = SMALL_PAGE_SIZE;
+		segment.shared = false;
+		segment.numa_node = -1;
 		segment.manager = manager;
 
 		//return
@@ -151,6 +167,42 @@ impl RegionSegment {
 		self.size - mem::size_of::<RegionSegment>()
 	}
 
+	///Return the size of the pages backing the segment.
+	#[inline]
+	pub fn get_page_size(&self) -> Size {
+		self.page_size
+	}
+
+	///Record the size of the pages backing the segment if they are not the standard ones.
+	pub fn set_page_size(&mut self, page_size: Size) {
+		debug_assert!(page_size.is_power_of_two() && page_size >= SMALL_PAGE_SIZE);
+		debug_assert!(self.size % page_size == 0);
+		self.page_size = page_size;
+	}
+
+	///Mark the segment as mapped from memory shared with other processes.
+	pub fn set_shared(&mut self) {
+		self.shared = true;
+	}
+
+	///Return the NUMA node the pages are bound to, -1 if not bound.
+	#[inline]
+	pub fn get_numa_node(&self) -> i32 {
+		self.numa_node
+	}
+
+	///Record the NUMA node the pages are bound to.
+	pub fn set_numa_node(&mut self, numa_node: i32) {
+		self.numa_node = numa_node;
+	}
+
+	///Check if the content can be dropped by pieces with madvise(MADV_DONTNEED)
+	///and comes back zero filled.
+	#[inline]
+	pub fn is_purgeable(&self) -> bool {
+		self.page_size == SMALL_PAGE_SIZE && !self.shared
+	}
+
 	///Return manager in safe way
 	#[inline]
 	pub fn get_manager(self: &Self) -> Option<ChunkManagerPtr> {
@@ -186,10 +238,13 @@ mod tests
 	use core::mem;
 	use portability::osmem;
 	use chunk::dummy::DummyChunkManager;
+	use chunk::padding::PaddedChunk;
 
 	#[test]
 	fn struct_size() {
-		assert_eq!(mem::size_of::<RegionSegment>(), 32);
+		assert_eq!(mem::size_of::<RegionSegment>(), 48);
+		//huge chunks start just after it
+		assert_eq!(mem::size_of::<RegionSegment>() % BASIC_ALIGN, 0);
 	}
 
 	#[test]
@@ -202,6 +257,16 @@ mod tests
 		osmem::munmap(ptr,4*4096);
 	}
 
+	#[test]
+	fn new_clear_padding() {
+		let ptr = osmem::mmap(0,4*4096);
+		libc::memset(ptr, PADDED_CHUNK_MAGICK as i32, 4096);
+		let reg = RegionSegment::new(ptr,4*4096,None);
+		let addr = reg.get_content_addr();
+		assert_eq!(PaddedChunk::unpad(addr), addr);
+		osmem::munmap(ptr,4*4096);
+	}
+
 	#[test]
 	fn get_segment() {
 		let ptr = osmem::mmap(0,4*4096);
@@ -228,7 +293,7 @@ mod tests
 		let pmanager: ChunkManagerPtr = SharedPtrBox::new_ref_mut(&mut manager);
 		let reg = RegionSegment::new(ptr,4*4096,Some(pmanager));
 		let addr = reg.get_content_addr();
-		assert_eq!(addr,ptr+32);
+		assert_eq!(addr,ptr+48);
 		osmem::munmap(ptr,4*4096);
 	}
 
@@ -264,7 +329,7 @@ mod tests
 		let mut manager = DummyChunkManager{};
 		let pmanager: ChunkManagerPtr = SharedPtrBox::new_ref_mut(&mut manager);
 		let reg = RegionSegment::new(ptr,4*4096,Some(pmanager));
-		assert_eq!(reg.get_inner_size(),4*4096-32);
+		assert_eq!(reg.get_inner_size(),4*4096-48);
 		osmem::munmap(ptr,4*4096);
 	}
 
@@ -0,0 +1,121 @@
+/*****************************************************
+             PROJECT  : hpc_allocator_rust
+             VERSION  : 0.1.0-dev
+             DATE     : 05/2018
+             AUTHOR   : Valat Sébastien
+             LICENSE  : CeCILL-C
+*****************************************************/
+
+//Check the C++ operator new failures of the allocator loaded with LD_PRELOAD,
+//std::bad_alloc must reach the caller through frames with destructors.
+
+/****************************************************/
+#include <new>
+#include <string>
+#include <cstdio>
+#include <cstdlib>
+#include <cerrno>
+
+/****************************************************/
+//cannot be satisfied
+static const size_t TOO_LARGE = (size_t)1 << 62;
+
+//keep the allocations so they cannot be removed by the compiler
+static void * volatile sink = NULL;
+
+//count calls to the new handler
+static int handler_calls = 0;
+
+/****************************************************/
+#define CHECK(cond) do { if (!(cond)) { fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); exit(1); } } while(0)
+
+/****************************************************/
+//Flag its destruction while unwinding.
+struct Guard
+{
+	Guard(bool * destroyed) : destroyed(destroyed) {}
+	~Guard() { *destroyed = true; }
+	bool * destroyed;
+};
+
+/****************************************************/
+//Allocate from a frame which has to be cleaned up by the unwinder, not inlined
+//so the exception has to leave it.
+template <class F>
+__attribute__((noinline)) static void alloc_in_frame(bool * destroyed, F alloc)
+{
+	Guard guard(destroyed);
+	std::string keep("a string large enough to be allocated on the heap");
+	sink = alloc();
+	sink = &keep;
+}
+
+/****************************************************/
+//Check the given allocation throws std::bad_alloc through alloc_in_frame().
+template <class F>
+static void check_throw(F alloc)
+{
+	bool destroyed = false;
+	bool caught = false;
+	try {
+		alloc_in_frame(&destroyed, alloc);
+	} catch (const std::bad_alloc &) {
+		caught = true;
+	}
+	CHECK(caught);
+	CHECK(destroyed);
+}
+
+/****************************************************/
+static void retry_once_handler(void)
+{
+	handler_calls++;
+	if (handler_calls == 2)
+		std::set_new_handler(NULL);
+}
+
+/****************************************************/
+static void throwing_handler(void)
+{
+	handler_calls++;
+	throw std::bad_alloc();
+}
+
+/****************************************************/
+int main(void)
+{
+	//throwing variants
+	check_throw([] { return operator new(TOO_LARGE); });
+	check_throw([] { return operator new[](TOO_LARGE); });
+	check_throw([] { return operator new(TOO_LARGE, std::align_val_t(64)); });
+	check_throw([] { return operator new[](TOO_LARGE, std::align_val_t(64)); });
+	check_throw([] { return (void*)new char[TOO_LARGE]; });
+
+	//the handler is called until it is removed
+	std::set_new_handler(retry_once_handler);
+	check_throw([] { return operator new(TOO_LARGE); });
+	CHECK(handler_calls == 2);
+
+	//the handler can throw by itself
+	handler_calls = 0;
+	std::set_new_handler(throwing_handler);
+	check_throw([] { return operator new(TOO_LARGE); });
+	CHECK(handler_calls == 1);
+	std::set_new_handler(NULL);
+
+	//nothrow variants
+	errno = 0;
+	CHECK(operator new(TOO_LARGE, std::nothrow) == NULL);
+	CHECK(errno == ENOMEM);
+	CHECK(operator new[](TOO_LARGE, std::nothrow) == NULL);
+	CHECK(operator new(TOO_LARGE, std::align_val_t(64), std::nothrow) == NULL);
+	CHECK(operator new[](TOO_LARGE, std::align_val_t(64), std::nothrow) == NULL);
+
+	//still usable
+	char * ptr = new char[64];
+	sink = ptr;
+	delete [] ptr;
+
+	printf("OK\n");
+	return 0;
+}